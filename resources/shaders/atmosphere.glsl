@@ -0,0 +1,98 @@
+// atmosphere.glsl
+// Analytic sky colour and height fog. Include it after the Globals block.
+
+layout(set=0, binding=5)
+uniform Atmosphere {
+    vec4 view_forward;      // sky pass view rays, forward plus right and up to the screen edges
+    vec4 view_right;
+    vec4 view_up;
+    vec4 sun_direction;     // towards the sun, w is the cosine of the disc's angular radius
+    vec4 moon_direction;    // towards the moon, likewise
+    vec4 sky_scattering;    // turbidity, exposure and whether the sky is drawn
+    vec4 fog_params;        // density, base height and height falloff
+};
+
+// Scattering coefficients per unit of air mass, Mie scaled by turbidity
+const vec3 RAYLEIGH = vec3(0.058, 0.135, 0.331);
+const float MIE = 0.021;
+const float MIE_G = 0.76;
+const float SUN_INTENSITY = 2.5;
+const vec3 NIGHT_SKY = vec3(0.004, 0.006, 0.012);
+const vec3 MOON_COLOR = vec3(0.75, 0.78, 0.85);
+
+// Relative thickness of air looking up at `cos_zenith`, Kasten and Young's fit
+float air_mass(float cos_zenith) {
+    float zenith = degrees(acos(clamp(cos_zenith, 0.0, 1.0)));
+    return 1.0 / (max(cos_zenith, 0.0) + 0.50572 * pow(96.07995 - zenith, -1.6364));
+}
+
+// Sunlight left after crossing the atmosphere along `direction`
+vec3 transmittance(vec3 direction) {
+    vec3 extinction = RAYLEIGH + MIE * sky_scattering.x;
+    return exp(-extinction * air_mass(direction.y));
+}
+
+// Light scattered towards the viewer along `direction`, without the sun disc. Anything below
+// the horizon gets the horizon's colour.
+vec3 sky_color(vec3 direction) {
+    direction = normalize(vec3(direction.x, max(direction.y, 0.0), direction.z));
+    vec3 to_sun = sun_direction.xyz;
+    float mu = dot(direction, to_sun);
+
+    float rayleigh_phase = 0.75 * (1.0 + mu * mu);
+    float g2 = MIE_G * MIE_G;
+    float mie_phase = 1.5 * (1.0 - g2) / (2.0 + g2) * (1.0 + mu * mu)
+        / pow(1.0 + g2 - 2.0 * MIE_G * mu, 1.5);
+
+    vec3 rayleigh = RAYLEIGH;
+    vec3 mie = vec3(MIE * sky_scattering.x);
+    vec3 extinction = rayleigh + mie;
+    vec3 scattered = (rayleigh * rayleigh_phase + mie * mie_phase) / extinction
+        * (1.0 - exp(-extinction * air_mass(direction.y)));
+
+    // The sun dims and reddens as it nears the horizon and is gone once it's below
+    float daylight = smoothstep(-0.1, 0.05, to_sun.y);
+    vec3 sunlight = SUN_INTENSITY * transmittance(to_sun) * daylight;
+    // Exposed so the bright horizon rolls off instead of clipping
+    return 1.0 - exp(-(scattered * sunlight + NIGHT_SKY) * sky_scattering.y);
+}
+
+// The sun disc over the sky, for the sky pass
+vec3 sun_disc(vec3 direction) {
+    float cos_angle = dot(normalize(direction), sun_direction.xyz);
+    float edge = 1.0 - sun_direction.w;
+    float disc = smoothstep(sun_direction.w - edge * 0.1, sun_direction.w + edge * 0.1, cos_angle);
+    float above = step(0.0, direction.y);
+    return disc * above * (1.0 - exp(-SUN_INTENSITY * 8.0 * transmittance(sun_direction.xyz)
+        * sky_scattering.y));
+}
+
+// The moon disc, only drawn against a dark sky
+vec3 moon_disc(vec3 direction) {
+    float cos_angle = dot(normalize(direction), moon_direction.xyz);
+    float edge = 1.0 - moon_direction.w;
+    float disc = smoothstep(moon_direction.w - edge * 0.1, moon_direction.w + edge * 0.1, cos_angle);
+    float above = step(0.0, direction.y);
+    float night = 1.0 - smoothstep(-0.1, 0.05, sun_direction.y);
+    return disc * above * night * MOON_COLOR * transmittance(moon_direction.xyz);
+}
+
+// Blends `color` at `position` towards the sky behind it. Fog thins exponentially with height,
+// so distant valleys haze over before peaks do.
+vec3 apply_fog(vec3 color, vec3 position) {
+    vec3 ray = position - camera_position.xyz;
+    float distance = length(ray);
+    vec3 direction = ray / max(distance, 0.0001);
+
+    float density = fog_params.x;
+    float falloff = max(fog_params.z, 0.0001);
+    float start = exp(-falloff * (camera_position.y - fog_params.y));
+    // Integral of the density along the ray, the straight line case when it's near level
+    float along = falloff * ray.y;
+    float amount = abs(along) > 0.0001
+        ? density * start * (1.0 - exp(-along)) / falloff / direction.y
+        : density * start * distance;
+    float fog = 1.0 - exp(-max(amount, 0.0));
+
+    return mix(color, sky_color(direction), fog);
+}
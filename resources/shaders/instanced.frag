@@ -0,0 +1,24 @@
+// instanced.frag
+#version 450
+
+layout(location=0) in vec3 frag_normal;
+layout(location=1) in vec3 frag_color;
+layout(location=2) in vec3 frag_position;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+#include "lighting.glsl"
+#include "atmosphere.glsl"
+
+void main() {
+    vec3 normal = normalize(frag_normal);
+    vec3 color = blinn_phong(frag_color, frag_position, normal, vec3(0.05), 8.0);
+    f_color = vec4(apply_fog(color, frag_position), 1.0);
+}
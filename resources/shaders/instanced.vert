@@ -0,0 +1,41 @@
+// instanced.vert
+#version 450
+
+layout(location=0) in vec3 vert_pos;
+layout(location=1) in vec3 vert_normal;
+layout(location=2) in vec3 vert_color;
+// Model matrix columns
+layout(location=3) in vec4 instance_model_0;
+layout(location=4) in vec4 instance_model_1;
+layout(location=5) in vec4 instance_model_2;
+layout(location=6) in vec4 instance_model_3;
+layout(location=7) in vec4 instance_tint;
+
+layout(location=0) out vec3 frag_normal;
+layout(location=1) out vec3 frag_color;
+layout(location=2) out vec3 frag_position;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+layout(set=1, binding=0)
+uniform Model {
+    mat4 model;
+    mat4 normal_matrix;
+};
+
+void main() {
+    // Instances are placed with rotations and uniform scales only, so their normals just need
+    // renormalising
+    mat4 instance_model = mat4(instance_model_0, instance_model_1, instance_model_2, instance_model_3);
+    mat4 world = model * instance_model;
+    frag_normal = mat3(normal_matrix) * mat3(instance_model) * vert_normal;
+    frag_color = vert_color * instance_tint.rgb;
+    vec4 world_position = world * vec4(vert_pos, 1.0);
+    frag_position = world_position.xyz;
+    gl_Position = view_proj * world_position;
+}
@@ -0,0 +1,25 @@
+// lamp.vert
+#version 450
+
+layout(location=0) in vec3 vert_pos;
+layout(location=1) in vec3 vert_color;
+
+layout(location=0) out vec3 frag_color;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+layout(set=1, binding=0)
+uniform Model {
+    mat4 model;
+    mat4 normal_matrix;
+};
+
+void main() {
+    frag_color = vert_color;
+    gl_Position = view_proj * model * vec4(vert_pos, 1.0);
+}
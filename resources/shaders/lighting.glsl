@@ -0,0 +1,106 @@
+// lighting.glsl
+// Blinn-Phong shading from the scene's lights. Include it after the Globals block.
+
+#define MAX_LIGHTS 8
+#define MAX_CASCADES 4
+
+struct Light {
+    vec4 position;  // w is the kind, 0 directional, 1 point or 2 spot
+    vec4 direction; // w is the range
+    vec4 color;     // w is the intensity
+    vec4 cone;      // cosines of the inner and outer spot angles
+};
+
+layout(set=0, binding=1)
+uniform Lights {
+    vec4 ambient;
+    uvec4 light_count;
+    Light lights[MAX_LIGHTS];
+};
+
+layout(set=0, binding=2) uniform texture2DArray t_shadow;
+layout(set=0, binding=3) uniform samplerShadow s_shadow;
+
+layout(set=0, binding=4)
+uniform Shadows {
+    mat4 cascade_view_proj[MAX_CASCADES];
+    vec4 cascade_spheres[MAX_CASCADES]; // centre and radius
+    vec4 shadow_params;                 // texel size, PCF radius and normal offset
+    ivec4 shadow_info;                  // cascade count and the shadowed light's index
+};
+
+// How much of the shadowed light reaches `position`, 0 in full shadow up to 1
+float shadow(vec3 position, vec3 normal) {
+    // The first cascade whose sphere holds the position, or one past the last. Sampling
+    // happens either way to stay in uniform control flow.
+    int cascade = shadow_info.x;
+    for (int i = shadow_info.x - 1; i >= 0; i--) {
+        vec3 offset = position - cascade_spheres[i].xyz;
+        float radius = cascade_spheres[i].w;
+        cascade = dot(offset, offset) < radius * radius ? i : cascade;
+    }
+    int layer = min(cascade, max(shadow_info.x - 1, 0));
+
+    // Texels cover more ground in the wider cascades, so push further out along the normal
+    float texel_size = 2.0 * cascade_spheres[layer].w * shadow_params.x;
+    vec3 offset_position = position + normal * texel_size * shadow_params.z;
+    vec4 light_position = cascade_view_proj[layer] * vec4(offset_position, 1.0);
+    vec3 coords = light_position.xyz / light_position.w;
+    vec2 uv = coords.xy * vec2(0.5, -0.5) + 0.5;
+
+    int radius = int(shadow_params.y);
+    float lit = 0.0;
+    for (int x = -radius; x <= radius; x++) {
+        for (int y = -radius; y <= radius; y++) {
+            vec2 sample_uv = uv + vec2(x, y) * shadow_params.x;
+            lit += texture(
+                sampler2DArrayShadow(t_shadow, s_shadow),
+                vec4(sample_uv, float(layer), coords.z)
+            );
+        }
+    }
+    float samples = float((2 * radius + 1) * (2 * radius + 1));
+    return cascade < shadow_info.x ? lit / samples : 1.0;
+}
+
+// Lit colour of a surface with diffuse colour `albedo`. `specular` tints the highlights and
+// `shininess` tightens them.
+vec3 blinn_phong(vec3 albedo, vec3 position, vec3 normal, vec3 specular, float shininess) {
+    vec3 view_dir = normalize(camera_position.xyz - position);
+    vec3 color = ambient.rgb * albedo;
+
+    for (uint i = 0; i < light_count.x; i++) {
+        Light light = lights[i];
+        vec3 to_light;
+        float attenuation = 1.0;
+
+        if (light.position.w < 0.5) {
+            to_light = -light.direction.xyz;
+        } else {
+            vec3 offset = light.position.xyz - position;
+            float distance = length(offset);
+            to_light = offset / max(distance, 0.0001);
+
+            // Inverse square, smoothly reaching zero at the range
+            float fade = clamp(1.0 - pow(distance / light.direction.w, 4.0), 0.0, 1.0);
+            attenuation = fade * fade / (distance * distance + 1.0);
+
+            if (light.position.w > 1.5) {
+                float cos_angle = dot(-to_light, light.direction.xyz);
+                attenuation *= smoothstep(light.cone.y, light.cone.x, cos_angle);
+            }
+        }
+
+        if (int(i) == shadow_info.y) {
+            attenuation *= shadow(position, normal);
+        }
+
+        vec3 radiance = light.color.rgb * light.color.w * attenuation;
+        float diffuse = max(dot(normal, to_light), 0.0);
+        vec3 halfway = normalize(to_light + view_dir);
+        float highlight = diffuse > 0.0 ? pow(max(dot(normal, halfway), 0.0), shininess) : 0.0;
+
+        color += radiance * (albedo * diffuse + specular * highlight);
+    }
+    return color;
+}
@@ -0,0 +1,33 @@
+// model.frag
+#version 450
+
+layout(location=0) in vec3 frag_normal;
+layout(location=1) in vec2 frag_uv;
+layout(location=2) in vec3 frag_position;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+#include "lighting.glsl"
+#include "atmosphere.glsl"
+
+layout(set=2, binding=0) uniform texture2D t_diffuse;
+layout(set=2, binding=1) uniform sampler s_diffuse;
+
+layout(set=3, binding=0)
+uniform Material {
+    vec4 specular; // w is the shininess
+};
+
+void main() {
+    vec4 albedo = texture(sampler2D(t_diffuse, s_diffuse), frag_uv);
+    vec3 normal = normalize(frag_normal);
+    vec3 color = blinn_phong(albedo.rgb, frag_position, normal, specular.rgb, specular.w);
+    f_color = vec4(apply_fog(color, frag_position), albedo.a);
+}
@@ -0,0 +1,31 @@
+// model.vert
+#version 450
+
+layout(location=0) in vec3 vert_pos;
+layout(location=1) in vec3 vert_normal;
+layout(location=2) in vec2 vert_uv;
+
+layout(location=0) out vec3 frag_normal;
+layout(location=1) out vec2 frag_uv;
+layout(location=2) out vec3 frag_position;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+layout(set=1, binding=0)
+uniform Model {
+    mat4 model;
+    mat4 normal_matrix;
+};
+
+void main() {
+    frag_normal = mat3(normal_matrix) * vert_normal;
+    frag_uv = vert_uv;
+    vec4 world_position = model * vec4(vert_pos, 1.0);
+    frag_position = world_position.xyz;
+    gl_Position = view_proj * world_position;
+}
@@ -6,8 +6,21 @@ layout(location=1) in vec3 vert_color;
 
 layout(location=0) out vec4 frag_color;
 
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+layout(set=1, binding=0)
+uniform Model {
+    mat4 model;
+    mat4 normal_matrix;
+};
+
 void main() {
     frag_color = vec4(vert_color, 1.0);
-    gl_Position = vec4(vert_pos, 1.0);
+    gl_Position = view_proj * model * vec4(vert_pos, 1.0);
 }
 
@@ -0,0 +1,19 @@
+// shadow.vert
+#version 450
+
+layout(location=0) in vec3 vert_pos;
+
+layout(set=0, binding=0)
+uniform Cascade {
+    mat4 light_view_proj;
+};
+
+layout(set=1, binding=0)
+uniform Model {
+    mat4 model;
+    mat4 normal_matrix;
+};
+
+void main() {
+    gl_Position = light_view_proj * model * vec4(vert_pos, 1.0);
+}
@@ -0,0 +1,25 @@
+// shadow_instanced.vert
+#version 450
+
+layout(location=0) in vec3 vert_pos;
+// Model matrix columns
+layout(location=3) in vec4 instance_model_0;
+layout(location=4) in vec4 instance_model_1;
+layout(location=5) in vec4 instance_model_2;
+layout(location=6) in vec4 instance_model_3;
+
+layout(set=0, binding=0)
+uniform Cascade {
+    mat4 light_view_proj;
+};
+
+layout(set=1, binding=0)
+uniform Model {
+    mat4 model;
+    mat4 normal_matrix;
+};
+
+void main() {
+    mat4 instance_model = mat4(instance_model_0, instance_model_1, instance_model_2, instance_model_3);
+    gl_Position = light_view_proj * model * instance_model * vec4(vert_pos, 1.0);
+}
@@ -0,0 +1,20 @@
+// sky.frag
+#version 450
+
+layout(location=0) in vec3 frag_ray;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+#include "atmosphere.glsl"
+
+void main() {
+    vec3 direction = normalize(frag_ray);
+    f_color = vec4(min(sky_color(direction) + sun_disc(direction) + moon_disc(direction), 1.0), 1.0);
+}
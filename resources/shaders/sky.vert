@@ -0,0 +1,20 @@
+// sky.vert
+#version 450
+
+layout(location=0) out vec3 frag_ray;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+#include "atmosphere.glsl"
+
+void main() {
+    // One triangle covering the screen
+    vec2 ndc = vec2(float((gl_VertexIndex << 1) & 2), float(gl_VertexIndex & 2)) * 2.0 - 1.0;
+    frag_ray = view_forward.xyz + ndc.x * view_right.xyz + ndc.y * view_up.xyz;
+    gl_Position = vec4(ndc, 0.5, 1.0);
+}
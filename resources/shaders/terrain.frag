@@ -0,0 +1,101 @@
+// terrain.frag
+#version 450
+
+layout(location=0) in vec3 frag_normal;
+layout(location=1) in vec2 frag_uv;
+layout(location=2) in vec3 frag_position;
+layout(location=3) in vec4 frag_weights;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+#include "lighting.glsl"
+#include "atmosphere.glsl"
+
+layout(set=2, binding=0) uniform texture2DArray t_layers;
+layout(set=2, binding=1) uniform sampler s_layers;
+
+layout(set=3, binding=0)
+uniform TerrainMaterial {
+    vec4 layer_scale;       // texture repeats per world unit
+    vec4 layer_specular;
+    vec4 layer_color[4];
+    vec4 material_params;   // layer count, triplanar sharpness and anti-tiling strength
+};
+
+float hash(vec2 p) {
+    return fract(sin(dot(p, vec2(127.1, 311.7))) * 43758.5453);
+}
+
+float value_noise(vec2 p) {
+    vec2 i = floor(p);
+    vec2 f = fract(p);
+    vec2 u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(hash(i), hash(i + vec2(1.0, 0.0)), u.x),
+        mix(hash(i + vec2(0.0, 1.0)), hash(i + vec2(1.0, 1.0)), u.x),
+        u.y
+    );
+}
+
+// Blends two lookups shifted by offsets that change with a low frequency noise, so repeats of
+// the texture never line up. Gradients are passed in since the offsets jump between cells.
+vec3 sample_untiled(vec2 uv, vec2 duv_dx, vec2 duv_dy, float layer) {
+    float variation = value_noise(uv * 0.25) * 8.0 * material_params.z;
+    float index = floor(variation);
+    float blend = fract(variation);
+    vec2 offset_a = sin(vec2(3.0, 7.0) * index);
+    vec2 offset_b = sin(vec2(3.0, 7.0) * (index + 1.0));
+
+    vec3 color_a = textureGrad(
+        sampler2DArray(t_layers, s_layers), vec3(uv + offset_a, layer), duv_dx, duv_dy
+    ).rgb;
+    vec3 color_b = textureGrad(
+        sampler2DArray(t_layers, s_layers), vec3(uv + offset_b, layer), duv_dx, duv_dy
+    ).rgb;
+    float difference = dot(color_a - color_b, vec3(1.0));
+    return mix(color_a, color_b, smoothstep(0.2, 0.8, blend - 0.1 * difference));
+}
+
+// Projects the layer along all three axes, weighted by the normal. Flat ground is all top
+// projection while cliffs pick up the side ones and don't stretch.
+vec3 sample_triplanar(int layer, vec3 normal, vec3 dpdx, vec3 dpdy) {
+    float scale = layer_scale[layer];
+    vec3 weights = pow(abs(normal), vec3(material_params.y));
+    weights /= weights.x + weights.y + weights.z;
+
+    vec3 p = frag_position * scale;
+    vec3 dx = dpdx * scale;
+    vec3 dy = dpdy * scale;
+    vec3 color = vec3(0.0);
+    color += weights.x * sample_untiled(p.zy, dx.zy, dy.zy, float(layer));
+    color += weights.y * sample_untiled(p.xz, dx.xz, dy.xz, float(layer));
+    color += weights.z * sample_untiled(p.xy, dx.xy, dy.xy, float(layer));
+    return color * layer_color[layer].rgb;
+}
+
+void main() {
+    vec3 normal = normalize(frag_normal);
+    vec3 dpdx = dFdx(frag_position);
+    vec3 dpdy = dFdy(frag_position);
+
+    vec3 albedo = vec3(0.0);
+    float specular = 0.0;
+    int layer_count = int(material_params.x);
+    for (int layer = 0; layer < layer_count; layer++) {
+        float weight = frag_weights[layer];
+        if (weight > 0.001) {
+            albedo += weight * sample_triplanar(layer, normal, dpdx, dpdy);
+            specular += weight * layer_specular[layer];
+        }
+    }
+
+    vec3 color = blinn_phong(albedo, frag_position, normal, vec3(specular), 16.0);
+    f_color = vec4(apply_fog(color, frag_position), 1.0);
+}
@@ -0,0 +1,41 @@
+// water.frag
+#version 450
+
+layout(location=0) in vec3 frag_normal;
+layout(location=1) in float frag_depth;
+layout(location=2) in vec3 frag_position;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+layout(set=2, binding=0)
+uniform WaterUniforms {
+    vec4 shallow_color;
+    vec4 deep_color;
+    vec4 wave;  // amplitude, wave length, speed, opacity
+    vec4 depth; // deep depth
+};
+
+#include "atmosphere.glsl"
+
+void main() {
+    vec3 normal = normalize(frag_normal);
+    vec3 view_dir = normalize(camera_position.xyz - frag_position);
+
+    // Schlick's approximation, water reflects ~2% head on
+    float cos_theta = clamp(dot(normal, view_dir), 0.0, 1.0);
+    float fresnel = 0.02 + 0.98 * pow(1.0 - cos_theta, 5.0);
+
+    vec3 water = mix(shallow_color.rgb, deep_color.rgb, clamp(frag_depth / depth.x, 0.0, 1.0));
+    vec3 sky = sky_color(reflect(-view_dir, normal));
+    vec3 color = apply_fog(mix(water, sky, fresnel), frag_position);
+    float alpha = mix(wave.w, 1.0, fresnel) * clamp(frag_depth * 4.0, 0.0, 1.0);
+
+    f_color = vec4(color, alpha);
+}
@@ -0,0 +1,51 @@
+// water.vert
+#version 450
+
+layout(location=0) in vec3 vert_pos;
+layout(location=1) in float vert_depth;
+
+layout(location=0) out vec3 frag_normal;
+layout(location=1) out float frag_depth;
+layout(location=2) out vec3 frag_position;
+
+layout(set=0, binding=0)
+uniform Globals {
+    mat4 view_proj;
+    vec4 camera_position;
+    float time;
+};
+
+layout(set=1, binding=0)
+uniform Model {
+    mat4 model;
+    mat4 normal_matrix;
+};
+
+layout(set=2, binding=0)
+uniform WaterUniforms {
+    vec4 shallow_color;
+    vec4 deep_color;
+    vec4 wave;  // amplitude, wave length, speed, opacity
+    vec4 depth; // deep depth
+};
+
+const vec2 DIR_A = vec2(0.8, 0.6);
+const vec2 DIR_B = vec2(-0.4, 0.9);
+
+void main() {
+    vec3 position = (model * vec4(vert_pos, 1.0)).xyz;
+
+    // Two travelling sine waves, damped in the shallows so the shore stays put
+    float k = 6.2831853 / wave.y;
+    float phase_a = k * dot(position.xz, DIR_A) + time * wave.z;
+    float phase_b = 1.7 * k * dot(position.xz, DIR_B) + 1.3 * time * wave.z;
+    float amplitude = wave.x * clamp(vert_depth, 0.0, 1.0);
+
+    float offset = amplitude * (sin(phase_a) + 0.5 * sin(phase_b));
+    vec2 slope = amplitude * k * (cos(phase_a) * DIR_A + 0.85 * cos(phase_b) * DIR_B);
+
+    frag_normal = normalize(vec3(-slope.x, 1.0, -slope.y));
+    frag_depth = vert_depth;
+    frag_position = position + vec3(0.0, offset, 0.0);
+    gl_Position = view_proj * vec4(frag_position, 1.0);
+}
@@ -1,28 +1,55 @@
-use crate::{Config, Result};
+use crate::{na, Config, Result};
 use log::{info, warn};
-use std::{path::Path, time::Instant};
+use std::{path::Path, thread, time::Duration, time::Instant};
 use winit::{
     dpi::PhysicalSize,
-    event::{Event, VirtualKeyCode, WindowEvent},
+    event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::{Window, WindowBuilder},
 };
 
-use crate::input::InputState;
-use crate::objects::{Cube, Object};
-use crate::renderer::Renderer;
+use crate::biome::BiomeMap;
+use crate::climate::Climate;
+use crate::clock::Clock;
+use crate::export;
+use crate::input::{Bindings, InputState};
+use crate::objects::{
+    build_props, create_controller, Camera, CameraController, ChunkedTerrain, Cube, Lamp, Model,
+    Terrain, Transform, Water,
+};
+use crate::renderer::{Light, LightKind, Renderer};
+use crate::scene::{ObjectId, Scene};
+use crate::terrain::{
+    erode, splat_weights, Elevation, HeightFormat, Heightmap, HeightmapFile, SplatMap, SplatMasks,
+    TerrainConfig, TerrainGenerator,
+};
+use crate::water::{sea_level, WaterMap};
+
+// Everything built from the config's scene settings, shared by the app and headless renders
+struct World {
+    scene: Scene,
+    chunks: Option<ObjectId>,
+    // The sun lamp and its light as configured, which the sky tints and dims
+    sun: Option<(ObjectId, Light)>,
+    ground: Box<dyn Fn(f32, f32) -> f32>,
+}
 
 pub struct App {
     window: Window,
     event_loop: EventLoop<()>,
     input_state: InputState,
     renderer: Renderer,
-    cube: Cube,
+    scene: Scene,
+    chunks: Option<ObjectId>,
+    sun: Option<(ObjectId, Light)>,
+    ambient: [f32; 3],
+    clock: Clock,
+    controller: Box<dyn CameraController>,
 }
 
 impl App {
     pub async fn new(config: Config) -> Result<App> {
-        let input_state = InputState::new();
+        let input_state = InputState::new(Bindings::new(&config.input)?);
 
         let init_start = Instant::now();
 
@@ -30,7 +57,7 @@ impl App {
             let width = config.window.width;
             let height = config.window.height;
 
-            let title = config.application.name;
+            let title = &config.application.name;
 
             let event_loop = EventLoop::new();
             let size: PhysicalSize<u32> = PhysicalSize::from((width, height));
@@ -43,9 +70,13 @@ impl App {
         };
         info!("Window and Event Loop Created");
 
-        let mut renderer = Renderer::new(&window, config.window.bg_color).await;
+        let camera = Camera::new(&config.camera, 1.0);
+        let mut renderer =
+            Renderer::new(&window, config.window.bg_color, camera, &config.renderer).await?;
         renderer.init_clear_screen();
-        let cube = Cube::new(&renderer)?;
+
+        let world = build_world(&config, &mut renderer)?;
+        let controller = create_controller(&config.controls, &renderer.camera, world.ground);
 
         warn!(
             "Initialization time: {:#?} sec",
@@ -57,8 +88,12 @@ impl App {
             event_loop,
             input_state,
             renderer,
-            // objects,
-            cube,
+            scene: world.scene,
+            chunks: world.chunks,
+            sun: world.sun,
+            ambient: config.lighting.ambient,
+            clock: Clock::new(&config.clock),
+            controller,
         })
     }
 
@@ -67,20 +102,69 @@ impl App {
         let mut input_state = self.input_state;
         let window = self.window;
         let mut renderer = self.renderer;
-        // let objects = self.objects;
-        let cube = self.cube;
+        let mut scene = self.scene;
+        let chunks = self.chunks;
+        let sun = self.sun;
+        let ambient = self.ambient;
+        let mut clock = self.clock;
+        let mut controller = self.controller;
+        let mut last_frame = Instant::now();
 
         self.event_loop.run(move |event, _, control_flow| {
             match event {
                 Event::MainEventsCleared => {
-                    if input_state.is_key_pressed(VirtualKeyCode::Escape) {
-                        info!("Escape Key Pressed.");
+                    input_state.poll_gamepads();
+                    if input_state.is_action_pressed("quit") {
+                        info!("Quit Pressed.");
                         *control_flow = ControlFlow::Exit;
                     }
+
+                    // Hold look to steer with the mouse
+                    if input_state.is_action_just_pressed("look") {
+                        input_state.set_cursor_grab(&window, true);
+                    } else if input_state.is_action_just_released("look") {
+                        input_state.set_cursor_grab(&window, false);
+                    }
+
+                    let now = Instant::now();
+                    let dt = now.duration_since(last_frame).as_secs_f32();
+                    last_frame = now;
+                    controller.update(&mut renderer.camera, &input_state, dt);
+
+                    if input_state.is_action_just_pressed("pause") {
+                        let paused = !clock.is_paused();
+                        clock.set_paused(paused);
+                        info!("Clock {}", if paused { "paused" } else { "running" });
+                    }
+                    if input_state.is_action_just_pressed("time_faster") {
+                        clock.set_time_scale(clock.time_scale() * 2.0);
+                        info!("Time scale {}", clock.time_scale());
+                    }
+                    if input_state.is_action_just_pressed("time_slower") {
+                        clock.set_time_scale(clock.time_scale() / 2.0);
+                        info!("Time scale {}", clock.time_scale());
+                    }
+                    let hours = input_state.axis("time_of_day") * clock.scrub_speed * dt;
+                    clock.set_time_of_day(clock.time_of_day() + hours);
+                    input_state.end_frame();
+
+                    let steps = clock.advance(dt);
+                    renderer.set_time(clock.elapsed() as f32);
+                    renderer.sky.set_time_of_day(clock.time_of_day());
+                    apply_daylight(&mut renderer, &mut scene, &sun, ambient);
+
+                    if let Some(chunks) = chunks.and_then(|id| scene.get_mut::<ChunkedTerrain>(id))
+                    {
+                        let focus = renderer.camera.position;
+                        chunks.update_focus(&renderer, &focus);
+                    }
+                    for _ in 0..steps {
+                        scene.update(clock.timestep());
+                    }
                     window.request_redraw();
                 }
                 Event::RedrawRequested(_) => {
-                    renderer.render(&cube);
+                    renderer.render(&scene);
                 }
                 Event::WindowEvent {
                     event: WindowEvent::CloseRequested,
@@ -94,6 +178,9 @@ impl App {
                     event: WindowEvent::ScaleFactorChanged { new_inner_size, .. },
                     ..
                 } => renderer.resize(*new_inner_size),
+                Event::WindowEvent { event, .. } => {
+                    input_state.update_window(&event);
+                }
                 Event::LoopDestroyed => {
                     info!("Loop Destroyed");
                 }
@@ -107,3 +194,293 @@ impl App {
         });
     }
 }
+
+/// Draws one frame of the configured world into an image without opening a window, for batch
+/// jobs and golden image tests. The clock's starting time of day places the sun.
+pub async fn render_headless(config: Config, path: &Path) -> Result<()> {
+    let camera = Camera::new(&config.camera, 1.0);
+    let mut renderer = Renderer::headless(
+        config.window.width as u32,
+        config.window.height as u32,
+        config.window.bg_color,
+        camera,
+        &config.renderer,
+    )
+    .await?;
+    let mut world = build_world(&config, &mut renderer)?;
+
+    let clock = Clock::new(&config.clock);
+    renderer.set_time(clock.elapsed() as f32);
+    renderer.sky.set_time_of_day(clock.time_of_day());
+    apply_daylight(
+        &mut renderer,
+        &mut world.scene,
+        &world.sun,
+        config.lighting.ambient,
+    );
+
+    // Chunks stream in on worker threads, so wait for every one in view
+    if let Some(chunks) = world
+        .chunks
+        .and_then(|id| world.scene.get_mut::<ChunkedTerrain>(id))
+    {
+        let focus = renderer.camera.position;
+        chunks.update_focus(&renderer, &focus);
+        while chunks.is_loading() {
+            thread::sleep(Duration::from_millis(10));
+            chunks.update_focus(&renderer, &focus);
+        }
+    }
+
+    renderer.render_to_image(&world.scene, path).await
+}
+
+/// Builds the terrain heightmap, erodes it and writes it to `path` in the format its extension
+/// names. 16 bit formats spread the heights over their whole range, and the scale and offset
+/// that read them back are printed.
+pub fn export_heightmap(config: &Config, path: &Path) -> Result<()> {
+    let mut heightmap = base_heightmap(&config.terrain)?;
+    erode(&mut heightmap, &config.erosion, config.terrain.cell_size);
+
+    let elevation = match HeightFormat::from_path(path)? {
+        HeightFormat::Png | HeightFormat::R16 => Elevation::fit(&heightmap, u16::MAX as f32),
+        _ => Elevation::default(),
+    };
+    let mut file = HeightmapFile::new(heightmap, elevation);
+    file.cell_size = Some(config.terrain.cell_size as f64);
+    file.save(path)?;
+    info!(
+        "Wrote {}x{} heightmap to {}, scale {} offset {}",
+        file.heightmap.width,
+        file.heightmap.depth,
+        path.display(),
+        elevation.scale,
+        elevation.offset
+    );
+    Ok(())
+}
+
+pub fn export_mesh(config: &Config, path: &Path) -> Result<()> {
+    let maps = build_terrain(config)?;
+    let cell_size = config.terrain.cell_size;
+
+    let mut terrain = export::terrain_mesh(&maps.heightmap, cell_size, &config.export)?;
+    if config.export.texture {
+        terrain.texture = Some(export::bake_texture(&maps.splat, &config.materials)?);
+    }
+    let mut meshes = vec![terrain];
+    if config.export.includes_water(path) {
+        let [r, g, b, _] = config.water.shallow_color;
+        let color = [r, g, b, config.water.opacity];
+        meshes.extend(export::water_mesh(
+            &maps.water_map,
+            cell_size,
+            color,
+            &config.export,
+        ));
+    }
+
+    export::write_meshes(&meshes, path)?;
+    let triangles: usize = meshes.iter().map(export::ExportMesh::triangle_count).sum();
+    info!("Wrote {} triangles to {}", triangles, path.display());
+    Ok(())
+}
+
+// A single heightmap terrain and everything worked out over it
+struct TerrainMaps {
+    heightmap: Heightmap,
+    water_map: WaterMap,
+    biome_map: BiomeMap,
+    splat: SplatMap,
+}
+
+fn build_terrain(config: &Config) -> Result<TerrainMaps> {
+    let terrain_start = Instant::now();
+    let cell_size = config.terrain.cell_size;
+    let mut heightmap = base_heightmap(&config.terrain)?;
+    let erosion_maps = erode(&mut heightmap, &config.erosion, cell_size);
+    let climate = if config.climate.enabled {
+        let sea_level = config
+            .water
+            .sea_level
+            .unwrap_or_else(|| sea_level(&heightmap, config.water.sea_fraction));
+        Some(Climate::new(
+            &heightmap,
+            sea_level,
+            cell_size,
+            &config.climate,
+        ))
+    } else {
+        None
+    };
+    let precipitation = climate.as_ref().map(|climate| &climate.precipitation);
+    let water_map = WaterMap::new(&heightmap, &config.water, precipitation);
+    let biome_map = BiomeMap::new(
+        &heightmap,
+        &water_map,
+        climate.as_ref(),
+        cell_size,
+        &config.biomes,
+    )?;
+    info!(
+        "Terrain generated in {:#?} sec, sea level {}",
+        Instant::now().duration_since(terrain_start).as_secs_f32(),
+        water_map.sea_level
+    );
+
+    let masks = SplatMasks {
+        sediment: erosion_maps.sediment.as_ref(),
+        flow: erosion_maps.flow.as_ref(),
+        water: Some(&water_map),
+        biomes: Some(&biome_map),
+    };
+    let splat = splat_weights(&heightmap, cell_size, &config.materials, &masks);
+
+    Ok(TerrainMaps {
+        heightmap,
+        water_map,
+        biome_map,
+        splat,
+    })
+}
+
+// The terrain before erosion, generated unless the config imports a file
+fn base_heightmap(config: &TerrainConfig) -> Result<Heightmap> {
+    match &config.import {
+        Some(import) => import.load(),
+        None => {
+            let generator = TerrainGenerator::new(config)?;
+            Ok(generator.heightmap([0, 0], config.width, config.depth))
+        }
+    }
+}
+
+// Steers the sun lamp and scales the ambient light by the sky's time of day
+fn apply_daylight(
+    renderer: &mut Renderer,
+    scene: &mut Scene,
+    sun: &Option<(ObjectId, Light)>,
+    ambient: [f32; 3],
+) {
+    if !renderer.sky.enabled {
+        return;
+    }
+
+    let sky_light = renderer.sky.light();
+    if let Some((id, base)) = sun {
+        if let Some(lamp) = scene.get_mut::<Lamp>(*id) {
+            let light = lamp.light_mut();
+            for i in 0..3 {
+                light.color[i] = base.color[i] * sky_light.color[i];
+            }
+        }
+        // Turning the lamp rather than its light keeps the marker pointing along it
+        if let Some(node) = scene.node_mut(*id) {
+            node.transform.isometry.rotation =
+                rotation_between(&base.direction.into(), &sky_light.direction);
+        }
+    }
+    renderer.set_ambient([
+        ambient[0] * sky_light.ambient,
+        ambient[1] * sky_light.ambient,
+        ambient[2] * sky_light.ambient,
+    ]);
+}
+
+// Turns `from` onto `to`, about any perpendicular axis when they point opposite ways
+fn rotation_between(from: &na::Vector3<f32>, to: &na::Vector3<f32>) -> na::UnitQuaternion<f32> {
+    na::UnitQuaternion::rotation_between(from, to).unwrap_or_else(|| {
+        // Zero only when `from` lies along (1, 0, 1), which +y is perpendicular to
+        let axis = from.cross(&na::Vector3::new(1.0, 0.0, 1.0));
+        let axis = na::Unit::try_new(axis, f32::EPSILON).unwrap_or_else(na::Vector3::y_axis);
+        na::UnitQuaternion::from_axis_angle(&axis, std::f32::consts::PI)
+    })
+}
+
+fn build_world(config: &Config, renderer: &mut Renderer) -> Result<World> {
+    let mut scene = Scene::new();
+    scene.add(Cube::new(renderer)?, Transform::identity());
+
+    renderer.set_ambient(config.lighting.ambient);
+    // The first directional light is the sun by day and the moon by night, steered by the
+    // clock's time of day
+    let mut sun = None;
+    for light_config in &config.lighting.lights {
+        let lamp = Lamp::new(renderer, light_config.light.clone())?;
+        let position = na::Translation3::from(na::Vector3::from(light_config.position));
+        let id = scene.add(
+            lamp,
+            Transform::from_parts(position, na::UnitQuaternion::identity(), 1.0),
+        );
+        if sun.is_none() && light_config.light.kind == LightKind::Directional {
+            sun = Some((id, light_config.light.clone()));
+        }
+    }
+
+    for model_config in &config.models {
+        let model = Model::load(renderer, &model_config.path)?;
+        info!("Loaded model {}", model_config.path.display());
+        scene.add(model, model_config.transform());
+    }
+
+    let cell_size = config.terrain.cell_size;
+    let (chunks, ground): (_, Box<dyn Fn(f32, f32) -> f32>) = if config.chunks.enabled {
+        let chunks = ChunkedTerrain::new(
+            renderer,
+            &config.terrain,
+            config.chunks.clone(),
+            &config.materials,
+        )?;
+        let chunks = scene.add(chunks, Transform::identity());
+        let generator = TerrainGenerator::new(&config.terrain)?;
+        let ground = move |x: f32, z: f32| {
+            generator.height_at((x / cell_size) as f64, (z / cell_size) as f64)
+        };
+        (Some(chunks), Box::new(ground))
+    } else {
+        let TerrainMaps {
+            heightmap,
+            water_map,
+            biome_map,
+            splat,
+        } = build_terrain(config)?;
+        let terrain_config = &config.terrain;
+        let terrain = Terrain::new(
+            renderer,
+            &heightmap,
+            &splat,
+            &config.materials,
+            terrain_config.cell_size,
+        )?;
+        scene.add(terrain, Transform::identity());
+        let water = Water::new(
+            renderer,
+            &water_map,
+            terrain_config.cell_size,
+            &config.water,
+        )?;
+        scene.add(water, Transform::identity());
+
+        for prop_config in &config.props {
+            let props = build_props(renderer, prop_config, &heightmap, cell_size, |x, z| {
+                !water_map.is_wet(x, z) && biome_map.allows(x, z, &prop_config.biomes)
+            })?;
+            info!(
+                "Scattered {} {:?} props",
+                props.num_instances(),
+                prop_config.shape
+            );
+            scene.add(props, Transform::identity());
+        }
+
+        let ground = move |x: f32, z: f32| heightmap.sample(x / cell_size, z / cell_size);
+        (None, Box::new(ground))
+    };
+
+    Ok(World {
+        scene,
+        chunks,
+        sun,
+        ground,
+    })
+}
@@ -0,0 +1,281 @@
+use crate::objects::{Mesh, VertexAttribute};
+use crate::{na, Result};
+use std::{mem, path::Path, path::PathBuf};
+
+mod gltf;
+mod obj;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ModelVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+unsafe impl bytemuck::Pod for ModelVertex {}
+unsafe impl bytemuck::Zeroable for ModelVertex {}
+
+impl VertexAttribute for ModelVertex {
+    fn description<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float2,
+                },
+            ],
+        }
+    }
+}
+
+/// Where a material's texture comes from.
+#[derive(Clone, Debug)]
+pub enum TextureSource {
+    /// An image file next to the model, not read until the texture is created.
+    File(PathBuf),
+    /// An image that was packed into the model file, already decoded.
+    Image(image::RgbaImage),
+}
+
+/// Surface description read from the model file. Textures are only described here, creating
+/// them on the GPU is left to whatever draws the model.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    /// Linear RGBA, alpha is the opacity.
+    pub base_color: [f32; 4],
+    pub specular: [f32; 3],
+    pub shininess: f32,
+    pub diffuse_texture: Option<TextureSource>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material {
+            name: String::from("default"),
+            base_color: [1.0, 1.0, 1.0, 1.0],
+            specular: [0.0, 0.0, 0.0],
+            shininess: 1.0,
+            diffuse_texture: None,
+        }
+    }
+}
+
+pub struct ModelMesh {
+    pub mesh: Mesh<ModelVertex>,
+    /// Index into `ModelAsset::materials`.
+    pub material: Option<usize>,
+}
+
+/// Everything loaded from one model file.
+pub struct ModelAsset {
+    pub meshes: Vec<ModelMesh>,
+    pub materials: Vec<Material>,
+}
+
+/// Loads an OBJ (with its MTL files) or a glTF 2.0 `.gltf`/`.glb`, picked by file extension.
+pub fn load_model(path: &Path) -> Result<ModelAsset> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    let model = match extension.as_deref() {
+        Some("obj") => obj::load(path),
+        Some("gltf") | Some("glb") => gltf::load(path),
+        _ => return Err(format!("Unsupported model format: {}", path.display()).into()),
+    };
+    model.map_err(|e| format!("Failed to load {}: {}", path.display(), e).into())
+}
+
+/// Builds a mesh from flat attribute arrays, filling in smooth normals or zero texture
+/// coordinates when the file has none.
+fn build_mesh(
+    positions: &[[f32; 3]],
+    normals: Option<&[[f32; 3]]>,
+    uvs: Option<&[[f32; 2]]>,
+    indices: Vec<u32>,
+) -> Result<Mesh<ModelVertex>> {
+    if positions.len() > u16::MAX as usize + 1 {
+        return Err(format!(
+            "{} vertices is too many for 16 bit indices",
+            positions.len()
+        )
+        .into());
+    }
+    if indices.len() % 3 != 0 {
+        return Err(format!("{} indices don't make whole triangles", indices.len()).into());
+    }
+    if let Some(&index) = indices.iter().find(|&&i| i as usize >= positions.len()) {
+        return Err(format!(
+            "Index {} is out of range for {} vertices",
+            index,
+            positions.len()
+        )
+        .into());
+    }
+    for (name, len) in &[
+        ("normals", normals.map(|n| n.len())),
+        ("texture coordinates", uvs.map(|uv| uv.len())),
+    ] {
+        if let Some(len) = len {
+            if *len != positions.len() {
+                return Err(format!("{} {} for {} positions", len, name, positions.len()).into());
+            }
+        }
+    }
+
+    let normals = match normals {
+        Some(normals) => normals.to_vec(),
+        None => smooth_normals(positions, &indices),
+    };
+
+    let vertices = positions
+        .iter()
+        .enumerate()
+        .map(|(i, &position)| ModelVertex {
+            position,
+            normal: normals[i],
+            uv: uvs.map_or([0.0, 0.0], |uvs| uvs[i]),
+        })
+        .collect();
+    let indices = indices.into_iter().map(|i| i as u16).collect();
+
+    Ok(Mesh::new(vertices, indices))
+}
+
+// Area weighted average of the normals of the faces around each vertex
+fn smooth_normals(positions: &[[f32; 3]], indices: &[u32]) -> Vec<[f32; 3]> {
+    let mut sums = vec![na::Vector3::zeros(); positions.len()];
+    for triangle in indices.chunks(3) {
+        let corner = |i: usize| na::Vector3::from(positions[triangle[i] as usize]);
+        let normal = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+        for &i in triangle {
+            sums[i as usize] += normal;
+        }
+    }
+    sums.iter()
+        .map(|sum| {
+            sum.try_normalize(f32::EPSILON)
+                .unwrap_or_else(na::Vector3::y)
+                .into()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new("./resources/models/test").join(name)
+    }
+
+    fn assert_triangle(model: &ModelAsset) {
+        assert_eq!(model.meshes.len(), 1);
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.vertices.len(), 3);
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+        }
+        assert_eq!(mesh.vertices[1].uv, [1.0, 1.0]);
+    }
+
+    #[test]
+    fn loads_obj_with_materials() {
+        let model = load_model(&fixture("quad.obj")).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices.len(), 6);
+        // OBJ texture coordinates start at the bottom, ours at the top
+        assert_eq!(mesh.vertices[0].uv, [0.0, 1.0]);
+        // No normals in the file
+        for vertex in &mesh.vertices {
+            assert!((vertex.normal[1] - 1.0).abs() < 1e-6);
+        }
+
+        assert_eq!(model.materials.len(), 1);
+        let material = &model.materials[model.meshes[0].material.unwrap()];
+        assert_eq!(material.name, "stone");
+        let linear = [0.214, 0.133, 0.073, 0.75];
+        for (channel, expected) in material.base_color.iter().zip(&linear) {
+            assert!((channel - expected).abs() < 1e-3);
+        }
+        assert_eq!(material.shininess, 32.0);
+        match &material.diffuse_texture {
+            Some(TextureSource::File(path)) => assert!(path.ends_with("stone.png")),
+            texture => panic!("unexpected texture {:?}", texture),
+        }
+    }
+
+    #[test]
+    fn loads_gltf() {
+        let model = load_model(&fixture("triangle.gltf")).unwrap();
+        assert_triangle(&model);
+        assert_eq!(model.meshes[0].mesh.vertices[1].position, [1.0, 0.0, 0.0]);
+
+        let material = &model.materials[model.meshes[0].material.unwrap()];
+        assert_eq!(material.name, "red");
+        assert_eq!(material.base_color, [1.0, 0.0, 0.0, 1.0]);
+        assert!(material.diffuse_texture.is_none());
+    }
+
+    #[test]
+    fn loads_glb() {
+        let model = load_model(&fixture("triangle.glb")).unwrap();
+        assert_triangle(&model);
+        // Placed by its node's translation
+        assert_eq!(model.meshes[0].mesh.vertices[1].position, [1.0, 2.0, 0.0]);
+        assert!(model.materials.is_empty());
+        assert!(model.meshes[0].material.is_none());
+    }
+
+    #[test]
+    fn mirrored_nodes_keep_their_winding() {
+        let model = load_model(&fixture("mirrored.gltf")).unwrap();
+        let mesh = &model.meshes[0].mesh;
+        assert_eq!(mesh.vertices[1].position, [-1.0, 0.0, 0.0]);
+        assert_eq!(mesh.indices, vec![0, 2, 1]);
+
+        // Counter-clockwise triangles face along their normals
+        let corner = |i: usize| na::Vector3::from(mesh.vertices[mesh.indices[i] as usize].position);
+        let face = (corner(1) - corner(0)).cross(&(corner(2) - corner(0)));
+        assert!(face.dot(&na::Vector3::from(mesh.vertices[0].normal)) > 0.0);
+    }
+
+    #[test]
+    fn malformed_files_name_the_file() {
+        for name in &[
+            "bad_face.obj",
+            "missing_mtl.obj",
+            "truncated.gltf",
+            "bad.glb",
+        ] {
+            let error = load_model(&fixture(name)).err().unwrap().to_string();
+            assert!(error.contains(name), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_extensions() {
+        let error = load_model(Path::new("model.fbx")).err().unwrap();
+        assert!(error.to_string().contains("Unsupported model format"));
+    }
+}
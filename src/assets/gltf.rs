@@ -0,0 +1,193 @@
+use super::{build_mesh, Material, ModelAsset, ModelMesh, TextureSource};
+use crate::{na, Result};
+use ::gltf::{buffer, image::Format, mesh::Mode, Document, Node};
+use std::path::Path;
+
+struct Import {
+    document: Document,
+    buffers: Vec<buffer::Data>,
+    images: Vec<::gltf::image::Data>,
+}
+
+/// Loads a `.gltf` or `.glb`. Meshes are flattened into model space through the node
+/// hierarchy of the default scene.
+pub fn load(path: &Path) -> Result<ModelAsset> {
+    let (document, buffers, images) = ::gltf::import(path)?;
+    let import = Import {
+        document,
+        buffers,
+        images,
+    };
+
+    let materials = import
+        .document
+        .materials()
+        .map(|material| load_material(&import, &material))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut meshes = Vec::new();
+    match import
+        .document
+        .default_scene()
+        .or_else(|| import.document.scenes().next())
+    {
+        Some(scene) => {
+            for node in scene.nodes() {
+                load_node(&import, &node, &na::Matrix4::identity(), &mut meshes)?;
+            }
+        }
+        // Without a scene there is nothing to place the meshes, take them as they are
+        None => {
+            for mesh in import.document.meshes() {
+                load_mesh(&import, &mesh, &na::Matrix4::identity(), &mut meshes)?;
+            }
+        }
+    }
+
+    Ok(ModelAsset { meshes, materials })
+}
+
+fn load_node(
+    import: &Import,
+    node: &Node,
+    parent: &na::Matrix4<f32>,
+    meshes: &mut Vec<ModelMesh>,
+) -> Result<()> {
+    let transform = parent * na::Matrix4::from(node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        load_mesh(import, &mesh, &transform, meshes)?;
+    }
+    for child in node.children() {
+        load_node(import, &child, &transform, meshes)?;
+    }
+    Ok(())
+}
+
+fn load_mesh(
+    import: &Import,
+    mesh: &::gltf::Mesh,
+    transform: &na::Matrix4<f32>,
+    meshes: &mut Vec<ModelMesh>,
+) -> Result<()> {
+    let name = mesh
+        .name()
+        .map(String::from)
+        .unwrap_or_else(|| format!("mesh {}", mesh.index()));
+    let normal_matrix = transform
+        .fixed_slice::<na::U3, na::U3>(0, 0)
+        .try_inverse()
+        .ok_or_else(|| format!("Mesh '{}' has a singular transform", name))?
+        .transpose();
+    // Mirroring turns the triangles inside out
+    let mirrored = transform.fixed_slice::<na::U3, na::U3>(0, 0).determinant() < 0.0;
+
+    for primitive in mesh.primitives() {
+        if primitive.mode() != Mode::Triangles {
+            return Err(format!(
+                "Mesh '{}' uses {:?}, only triangles are supported",
+                name,
+                primitive.mode()
+            )
+            .into());
+        }
+
+        let reader = primitive.reader(|buffer| Some(&import.buffers[buffer.index()][..]));
+        let positions: Vec<[f32; 3]> = reader
+            .read_positions()
+            .ok_or_else(|| format!("Mesh '{}' has no positions", name))?
+            .map(|p| {
+                transform
+                    .transform_point(&na::Point3::from(p))
+                    .coords
+                    .into()
+            })
+            .collect();
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|normals| {
+            normals
+                .map(|n| (normal_matrix * na::Vector3::from(n)).normalize().into())
+                .collect()
+        });
+        let uvs: Option<Vec<[f32; 2]>> = reader
+            .read_tex_coords(0)
+            .map(|uvs| uvs.into_f32().collect());
+        let mut indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if mirrored {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let built = build_mesh(&positions, normals.as_deref(), uvs.as_deref(), indices)
+            .map_err(|e| format!("Mesh '{}': {}", name, e))?;
+
+        meshes.push(ModelMesh {
+            mesh: built,
+            material: primitive.material().index(),
+        });
+    }
+    Ok(())
+}
+
+fn load_material(import: &Import, material: &::gltf::Material) -> Result<Material> {
+    let name = material
+        .name()
+        .map(String::from)
+        .unwrap_or_else(|| format!("material {}", material.index().unwrap_or(0)));
+    let pbr = material.pbr_metallic_roughness();
+
+    let diffuse_texture = match pbr.base_color_texture() {
+        Some(info) => {
+            let image = &import.images[info.texture().source().index()];
+            let rgba = to_rgba(image)
+                .map_err(|e| format!("Material '{}' base colour texture: {}", name, e))?;
+            Some(TextureSource::Image(rgba))
+        }
+        None => None,
+    };
+
+    // Rough stand-in for the metallic-roughness model, smoother surfaces get tighter
+    // highlights
+    let roughness = pbr.roughness_factor();
+    let specular = 0.04 + 0.96 * pbr.metallic_factor() * (1.0 - roughness);
+    Ok(Material {
+        name,
+        base_color: pbr.base_color_factor(),
+        specular: [specular; 3],
+        shininess: 2.0 / (roughness * roughness).max(0.001) - 2.0,
+        diffuse_texture,
+    })
+}
+
+fn to_rgba(image: &::gltf::image::Data) -> Result<image::RgbaImage> {
+    let channels = match image.format {
+        Format::R8 => 1,
+        Format::R8G8 => 2,
+        Format::R8G8B8 | Format::B8G8R8 => 3,
+        Format::R8G8B8A8 | Format::B8G8R8A8 => 4,
+        format => return Err(format!("Unsupported pixel format {:?}", format).into()),
+    };
+    let bgr = matches!(image.format, Format::B8G8R8 | Format::B8G8R8A8);
+
+    let pixels = image
+        .pixels
+        .chunks_exact(channels)
+        .flat_map(|pixel| match channels {
+            1 => [pixel[0], pixel[0], pixel[0], 255],
+            2 => [pixel[0], pixel[0], pixel[0], pixel[1]],
+            _ => {
+                let alpha = if channels == 4 { pixel[3] } else { 255 };
+                if bgr {
+                    [pixel[2], pixel[1], pixel[0], alpha]
+                } else {
+                    [pixel[0], pixel[1], pixel[2], alpha]
+                }
+            }
+        })
+        .collect();
+
+    image::RgbaImage::from_raw(image.width, image.height, pixels)
+        .ok_or_else(|| "Image data is shorter than its size".into())
+}
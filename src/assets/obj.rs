@@ -0,0 +1,73 @@
+use super::{build_mesh, Material, ModelAsset, ModelMesh, TextureSource};
+use crate::renderer::srgb_float_to_linear;
+use crate::Result;
+use std::path::Path;
+
+pub fn load(path: &Path) -> Result<ModelAsset> {
+    let (models, materials) =
+        tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|e| e.to_string())?;
+    let materials = materials.map_err(|e| format!("Failed to load materials: {}", e))?;
+
+    // MTL texture paths are relative to the OBJ
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let materials = materials
+        .into_iter()
+        .map(|material| Material {
+            // MTL colours are picked in sRGB
+            base_color: [
+                srgb_float_to_linear(material.diffuse[0]),
+                srgb_float_to_linear(material.diffuse[1]),
+                srgb_float_to_linear(material.diffuse[2]),
+                material.dissolve,
+            ],
+            specular: material.specular,
+            shininess: material.shininess,
+            diffuse_texture: if material.diffuse_texture.is_empty() {
+                None
+            } else {
+                Some(TextureSource::File(
+                    directory.join(&material.diffuse_texture),
+                ))
+            },
+            name: material.name,
+        })
+        .collect();
+
+    let meshes = models
+        .into_iter()
+        .map(|model| {
+            let (name, mesh) = (model.name, model.mesh);
+            let positions: Vec<[f32; 3]> = mesh
+                .positions
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2]])
+                .collect();
+            let normals: Vec<[f32; 3]> = mesh
+                .normals
+                .chunks_exact(3)
+                .map(|n| [n[0], n[1], n[2]])
+                .collect();
+            // OBJ puts v = 0 at the bottom of the image
+            let uvs: Vec<[f32; 2]> = mesh
+                .texcoords
+                .chunks_exact(2)
+                .map(|uv| [uv[0], 1.0 - uv[1]])
+                .collect();
+
+            let built = build_mesh(
+                &positions,
+                Some(&normals[..]).filter(|normals| !normals.is_empty()),
+                Some(&uvs[..]).filter(|uvs| !uvs.is_empty()),
+                mesh.indices,
+            )
+            .map_err(|e| format!("Object '{}': {}", name, e))?;
+
+            Ok(ModelMesh {
+                mesh: built,
+                material: mesh.material_id,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(ModelAsset { meshes, materials })
+}
@@ -0,0 +1,449 @@
+use crate::climate::Climate;
+use crate::terrain::{fbm, FbmParams, Heightmap, Perlin};
+use crate::water::WaterMap;
+use crate::Result;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BiomeConfig {
+    pub seed: u64,
+    /// Sea level air temperature in °C on the equator and at the poles.
+    pub equator_temperature: f32,
+    pub pole_temperature: f32,
+    /// World z of the equator, and how far north or south of it the poles lie.
+    pub equator_z: f32,
+    pub pole_distance: f32,
+    /// °C lost per world unit of height above sea level.
+    pub lapse_rate: f32,
+    /// World distance from water at which the ground has dried out.
+    pub moisture_range: f32,
+    /// Most the moisture noise adds or takes away, and its frequency per world unit.
+    pub moisture_noise: f32,
+    pub moisture_frequency: f64,
+    /// Moisture added per cell of rainfall, where the climate pass worked out the rain.
+    pub rainfall_moisture: f32,
+    /// Moisture taken away per unit of wind speed, drying out exposed ground.
+    pub wind_drying: f32,
+    /// Temperatures in °C splitting the table's rows, coldest first.
+    pub temperature_bands: Vec<f32>,
+    /// Moisture levels in `[0, 1]` splitting the table's columns, driest first.
+    pub moisture_bands: Vec<f32>,
+    /// The Whittaker table, one row of biome names per temperature range from the coldest, one
+    /// column per moisture range from the driest.
+    pub table: Vec<Vec<String>>,
+}
+
+impl Default for BiomeConfig {
+    fn default() -> Self {
+        let row = |names: [&str; 4]| names.iter().map(|name| name.to_string()).collect();
+        BiomeConfig {
+            seed: 3,
+            equator_temperature: 26.0,
+            pole_temperature: -25.0,
+            equator_z: 0.0,
+            pole_distance: 512.0,
+            lapse_rate: 1.0,
+            moisture_range: 30.0,
+            moisture_noise: 0.25,
+            moisture_frequency: 0.015,
+            rainfall_moisture: 0.3,
+            wind_drying: 0.01,
+            temperature_bands: vec![-5.0, 5.0, 18.0],
+            moisture_bands: vec![0.2, 0.45, 0.7],
+            table: vec![
+                row(["rock", "rock", "tundra", "snow"]),
+                row(["tundra", "tundra", "taiga", "taiga"]),
+                row(["desert", "grassland", "forest", "forest"]),
+                row(["desert", "savanna", "forest", "rainforest"]),
+            ],
+        }
+    }
+}
+
+/// A config's Whittaker table with biome names swapped for small ids.
+#[derive(Clone, Debug)]
+pub struct BiomeTable {
+    pub names: Vec<String>,
+    temperature_bands: Vec<f32>,
+    moisture_bands: Vec<f32>,
+    ids: Vec<Vec<u8>>,
+}
+
+impl BiomeTable {
+    /// Fails unless the table has one more row and column than there are bands.
+    pub fn new(config: &BiomeConfig) -> Result<BiomeTable> {
+        let (rows, columns) = (
+            config.temperature_bands.len() + 1,
+            config.moisture_bands.len() + 1,
+        );
+        if config.table.len() != rows {
+            return Err(format!(
+                "Biome table has {} rows, {} temperature bands need {}",
+                config.table.len(),
+                config.temperature_bands.len(),
+                rows
+            )
+            .into());
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        let mut ids = Vec::with_capacity(rows);
+        for (i, row) in config.table.iter().enumerate() {
+            if row.len() != columns {
+                return Err(format!(
+                    "Biome table row {} has {} biomes, {} moisture bands need {}",
+                    i,
+                    row.len(),
+                    config.moisture_bands.len(),
+                    columns
+                )
+                .into());
+            }
+            let mut row_ids = Vec::with_capacity(columns);
+            for name in row {
+                let id = match names.iter().position(|known| known == name) {
+                    Some(id) => id,
+                    None => {
+                        names.push(name.clone());
+                        names.len() - 1
+                    }
+                };
+                if id > u8::MAX as usize {
+                    return Err("Biome table has more than 256 biomes".into());
+                }
+                row_ids.push(id as u8);
+            }
+            ids.push(row_ids);
+        }
+
+        Ok(BiomeTable {
+            names,
+            temperature_bands: config.temperature_bands.clone(),
+            moisture_bands: config.moisture_bands.clone(),
+            ids,
+        })
+    }
+
+    pub fn lookup(&self, temperature: f32, moisture: f32) -> u8 {
+        let row = band(&self.temperature_bands, temperature);
+        let column = band(&self.moisture_bands, moisture);
+        self.ids[row][column]
+    }
+}
+
+// Index of the range `value` falls in, counting the bands below it
+fn band(bands: &[f32], value: f32) -> usize {
+    bands.iter().filter(|&&edge| value >= edge).count()
+}
+
+/// Air temperature in °C for every cell of a `width` wide, row major height grid. Row 0 lies
+/// at world z `origin_z`. Warmest on the equator, cooling towards the poles and with height
+/// above `sea_level`.
+pub fn temperature(
+    heights: &[f32],
+    width: usize,
+    origin_z: f32,
+    cell_size: f32,
+    sea_level: f32,
+    config: &BiomeConfig,
+) -> Vec<f32> {
+    let pole_distance = config.pole_distance.max(f32::EPSILON);
+    heights
+        .iter()
+        .enumerate()
+        .map(|(i, &height)| {
+            let z = origin_z + (i / width) as f32 * cell_size;
+            let latitude = ((z - config.equator_z).abs() / pole_distance).min(1.0);
+            // Sunlight falls off with the cosine of latitude
+            let warmth = (latitude * std::f32::consts::FRAC_PI_2).cos();
+            let sea_level_temperature = config.pole_temperature
+                + (config.equator_temperature - config.pole_temperature) * warmth;
+            sea_level_temperature - config.lapse_rate * (height - sea_level).max(0.0)
+        })
+        .collect()
+}
+
+/// Moisture in `[0, 1]` for every cell of a `width` wide, row major grid of which cells hold
+/// water. 1 on water, drying out over `moisture_range`, then shifted by noise. Row 0, column
+/// 0 lies at world `origin`.
+pub fn moisture(
+    wet: &[bool],
+    width: usize,
+    origin: [f32; 2],
+    cell_size: f32,
+    config: &BiomeConfig,
+) -> Vec<f32> {
+    let distances = water_distance(wet, width);
+    let noise = Perlin::new(config.seed);
+    let params = FbmParams {
+        octaves: 3,
+        frequency: config.moisture_frequency,
+        lacunarity: 2.0,
+        persistence: 0.5,
+    };
+    let range = config.moisture_range.max(f32::EPSILON);
+
+    distances
+        .iter()
+        .enumerate()
+        .map(|(i, &distance)| {
+            let dryness = (distance * cell_size / range).min(1.0);
+            let (x, z) = (
+                origin[0] + (i % width) as f32 * cell_size,
+                origin[1] + (i / width) as f32 * cell_size,
+            );
+            let noise = if config.moisture_noise != 0.0 {
+                fbm(&noise, x as f64, z as f64, &params) as f32 * config.moisture_noise
+            } else {
+                0.0
+            };
+            (1.0 - dryness + noise).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+// Cells to the nearest wet cell, by two chamfer passes counting diagonals as √2. Infinite
+// when nothing is wet.
+fn water_distance(wet: &[bool], width: usize) -> Vec<f32> {
+    let depth = wet.len().checked_div(width).unwrap_or(0);
+    let mut distance: Vec<f32> = wet
+        .iter()
+        .map(|&wet| if wet { 0.0 } else { f32::INFINITY })
+        .collect();
+    let diagonal = std::f32::consts::SQRT_2;
+
+    let relax = |distance: &mut Vec<f32>, x: usize, z: usize, dx: isize, dz: isize, cost| {
+        let (nx, nz) = (x as isize + dx, z as isize + dz);
+        if nx < 0 || nz < 0 || nx >= width as isize || nz >= depth as isize {
+            return;
+        }
+        let through = distance[nz as usize * width + nx as usize] + cost;
+        let here = &mut distance[z * width + x];
+        if through < *here {
+            *here = through;
+        }
+    };
+    for z in 0..depth {
+        for x in 0..width {
+            relax(&mut distance, x, z, -1, 0, 1.0);
+            relax(&mut distance, x, z, 0, -1, 1.0);
+            relax(&mut distance, x, z, -1, -1, diagonal);
+            relax(&mut distance, x, z, 1, -1, diagonal);
+        }
+    }
+    for z in (0..depth).rev() {
+        for x in (0..width).rev() {
+            relax(&mut distance, x, z, 1, 0, 1.0);
+            relax(&mut distance, x, z, 0, 1, 1.0);
+            relax(&mut distance, x, z, 1, 1, diagonal);
+            relax(&mut distance, x, z, -1, 1, diagonal);
+        }
+    }
+    distance
+}
+
+/// Wets every cell by the rain falling on it, `rainfall_moisture` per cell of rainfall.
+pub fn add_rainfall(moisture: &mut [f32], precipitation: &[f32], config: &BiomeConfig) {
+    for (moisture, &rain) in moisture.iter_mut().zip(precipitation) {
+        *moisture = (*moisture + config.rainfall_moisture * rain.max(0.0)).min(1.0);
+    }
+}
+
+/// Looks every cell's temperature and moisture up in the table.
+pub fn classify(temperature: &[f32], moisture: &[f32], table: &BiomeTable) -> Vec<u8> {
+    temperature
+        .iter()
+        .zip(moisture)
+        .map(|(&temperature, &moisture)| table.lookup(temperature, moisture))
+        .collect()
+}
+
+/// Dries every cell by the wind blowing over it, `wind_drying` per unit of wind speed.
+pub fn add_wind_drying(moisture: &mut [f32], wind_speed: &[f32], config: &BiomeConfig) {
+    for (moisture, &speed) in moisture.iter_mut().zip(wind_speed) {
+        *moisture = (*moisture - config.wind_drying * speed.max(0.0)).max(0.0);
+    }
+}
+
+/// Biome of every cell of a heightmap, laid out like it.
+#[derive(Clone, Debug)]
+pub struct BiomeMap {
+    pub width: usize,
+    pub table: BiomeTable,
+    pub biomes: Vec<u8>,
+}
+
+impl BiomeMap {
+    /// Climate for a heightmap whose first sample sits at the world origin. `climate` is the
+    /// rain and wind worked out over the same heightmap.
+    pub fn new(
+        heightmap: &Heightmap,
+        water: &WaterMap,
+        climate: Option<&Climate>,
+        cell_size: f32,
+        config: &BiomeConfig,
+    ) -> Result<BiomeMap> {
+        let table = BiomeTable::new(config)?;
+        let (width, depth) = (heightmap.width, heightmap.depth);
+        let temperature = temperature(
+            &heightmap.samples,
+            width,
+            0.0,
+            cell_size,
+            water.sea_level,
+            config,
+        );
+        let wet: Vec<bool> = (0..depth)
+            .flat_map(|z| (0..width).map(move |x| (x, z)))
+            .map(|(x, z)| water.is_wet(x, z))
+            .collect();
+        let mut moisture = moisture(&wet, width, [0.0, 0.0], cell_size, config);
+        if let Some(climate) = climate {
+            add_rainfall(&mut moisture, &climate.precipitation.samples, config);
+            add_wind_drying(&mut moisture, &climate.wind_speed.samples, config);
+        }
+        let biomes = classify(&temperature, &moisture, &table);
+
+        Ok(BiomeMap {
+            width,
+            table,
+            biomes,
+        })
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> u8 {
+        self.biomes[z * self.width + x]
+    }
+
+    pub fn name(&self, x: usize, z: usize) -> &str {
+        &self.table.names[self.get(x, z) as usize]
+    }
+
+    /// Whether the cell's biome is one of `names`, any biome when `names` is empty.
+    pub fn allows(&self, x: usize, z: usize, names: &[String]) -> bool {
+        names.is_empty() || names.iter().any(|name| name == self.name(x, z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BiomeConfig {
+        BiomeConfig {
+            moisture_noise: 0.0,
+            ..BiomeConfig::default()
+        }
+    }
+
+    #[test]
+    fn temperature_cools_with_latitude_and_height() {
+        let config = config();
+        // Three rows of two cells, at the equator, half way and at the pole
+        let heights = [0.0, 10.0, 0.0, 10.0, 0.0, 10.0];
+        let temperature = temperature(&heights, 2, 0.0, 256.0, 0.0, &config);
+
+        assert!((temperature[0] - config.equator_temperature).abs() < 1e-4);
+        assert!((temperature[4] - config.pole_temperature).abs() < 1e-4);
+        assert!(temperature[2] < temperature[0] && temperature[2] > temperature[4]);
+        for row in temperature.chunks(2) {
+            assert!((row[0] - row[1] - 10.0 * config.lapse_rate).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn underwater_cells_are_not_cooled() {
+        let temperature = temperature(&[-5.0, 0.0], 2, 0.0, 1.0, 0.0, &config());
+        assert_eq!(temperature[0], temperature[1]);
+    }
+
+    #[test]
+    fn moisture_dries_out_away_from_water() {
+        let config = BiomeConfig {
+            moisture_range: 4.0,
+            ..config()
+        };
+        let mut wet = vec![false; 8];
+        wet[0] = true;
+        let moisture = moisture(&wet, 8, [0.0, 0.0], 1.0, &config);
+
+        assert_eq!(moisture[0], 1.0);
+        assert!((moisture[2] - 0.5).abs() < 1e-6);
+        assert!(moisture.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(moisture[7], 0.0);
+    }
+
+    #[test]
+    fn moisture_is_zero_without_water() {
+        let moisture = moisture(&[false; 4], 2, [0.0, 0.0], 1.0, &config());
+        assert_eq!(moisture, vec![0.0; 4]);
+    }
+
+    #[test]
+    fn rain_adds_moisture() {
+        let mut moisture = vec![0.0, 0.5, 0.9];
+        add_rainfall(&mut moisture, &[1.0, 0.0, 1.0], &config());
+        assert_eq!(moisture, vec![0.3, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn wind_dries_moisture() {
+        let config = BiomeConfig {
+            wind_drying: 0.1,
+            ..config()
+        };
+        let mut moisture = vec![0.5, 0.5, 0.1];
+        add_wind_drying(&mut moisture, &[0.0, 2.0, 4.0], &config);
+        assert!((moisture[1] - 0.3).abs() < 1e-6);
+        assert_eq!([moisture[0], moisture[2]], [0.5, 0.0]);
+    }
+
+    #[test]
+    fn diagonal_distance_is_longer() {
+        let mut wet = vec![false; 9];
+        wet[0] = true;
+        let distance = water_distance(&wet, 3);
+        assert_eq!(distance[1], 1.0);
+        assert!((distance[4] - std::f32::consts::SQRT_2).abs() < 1e-6);
+        assert!((distance[8] - 2.0 * std::f32::consts::SQRT_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn classifies_by_table() {
+        let table = BiomeTable::new(&config()).unwrap();
+        let name = |temperature, moisture| {
+            let biomes = classify(&[temperature], &[moisture], &table);
+            table.names[biomes[0] as usize].as_str()
+        };
+
+        assert_eq!(name(-20.0, 0.0), "rock");
+        assert_eq!(name(-20.0, 1.0), "snow");
+        assert_eq!(name(0.0, 0.5), "taiga");
+        assert_eq!(name(10.0, 0.1), "desert");
+        assert_eq!(name(30.0, 0.9), "rainforest");
+        // Band edges belong to the range above them
+        assert_eq!(name(18.0, 0.45), "forest");
+        assert_eq!(name(17.9, 0.44), "grassland");
+    }
+
+    #[test]
+    fn shares_ids_between_cells() {
+        let table = BiomeTable::new(&config()).unwrap();
+        assert_eq!(table.names.len(), 9);
+        assert_eq!(table.lookup(10.0, 0.0), table.lookup(30.0, 0.0));
+        assert_eq!(table.names[table.lookup(30.0, 0.0) as usize], "desert");
+    }
+
+    #[test]
+    fn rejects_misshapen_tables() {
+        let mut config = config();
+        config.table.pop();
+        assert!(BiomeTable::new(&config).is_err());
+
+        let mut config = self::config();
+        config.table[1].pop();
+        let error = BiomeTable::new(&config).err().unwrap().to_string();
+        assert!(error.contains("row 1"), "{}", error);
+    }
+}
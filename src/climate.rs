@@ -0,0 +1,217 @@
+use crate::terrain::Heightmap;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ClimateConfig {
+    /// Without the climate pass rain falls evenly, one cell of rainfall on every cell.
+    pub enabled: bool,
+    /// Degrees the prevailing wind blows towards, from +x turning towards +z.
+    pub wind_direction: f32,
+    pub wind_speed: f32,
+    /// Water the wind brings in over the map edge, and picks back up over the sea, in cells of
+    /// rainfall.
+    pub humidity: f32,
+    /// Advection passes. The wind moves one cell per pass, so crossing the map takes about as
+    /// many passes as the map is wide.
+    pub iterations: u32,
+    // Rain
+    /// Fraction of the air's water rained out on each flat cell crossed.
+    pub rain_rate: f32,
+    /// Scales rain by the slope the wind climbs, so it pours on windward slopes and dries up
+    /// coming down the lee side.
+    pub orographic_rate: f32,
+    /// Fraction of the air's missing water taken back up over each sea cell.
+    pub evaporation: f32,
+    // Wind speed
+    /// Speed gained at the highest point, as a fraction of `wind_speed`.
+    pub elevation_speedup: f32,
+    /// World distance upwind to look for higher ground sheltering a cell.
+    pub shelter_distance: f32,
+    /// How much sheltering slopes slow the wind.
+    pub shelter_strength: f32,
+}
+
+impl Default for ClimateConfig {
+    fn default() -> Self {
+        ClimateConfig {
+            enabled: true,
+            wind_direction: 0.0,
+            wind_speed: 8.0,
+            humidity: 200.0,
+            iterations: 384,
+            rain_rate: 0.008,
+            orographic_rate: 4.0,
+            evaporation: 0.1,
+            elevation_speedup: 1.0,
+            shelter_distance: 16.0,
+            shelter_strength: 2.0,
+        }
+    }
+}
+
+/// Rain and wind over a heightmap, from blowing the prevailing wind across it.
+#[derive(Debug)]
+pub struct Climate {
+    /// Rain on each cell, in the cells of rainfall `WaterMap::new` and `BiomeMap::new` take.
+    pub precipitation: Heightmap,
+    /// Wind speed on each cell, faster on high ground and slower in the shelter of hills. Dries
+    /// out the ground in `BiomeMap::new`.
+    pub wind_speed: Heightmap,
+}
+
+impl Climate {
+    /// Blows humid air in from the upwind edge. It rains out a little on every cell, more where
+    /// the ground forces it up and less where it sinks, and refills over the sea, which is
+    /// treated as flat at `sea_level`. `cell_size` is the world distance between samples.
+    pub fn new(
+        terrain: &Heightmap,
+        sea_level: f32,
+        cell_size: f32,
+        config: &ClimateConfig,
+    ) -> Climate {
+        let (width, depth) = (terrain.width, terrain.depth);
+        let angle = config.wind_direction.to_radians();
+        let wind = [angle.cos(), angle.sin()];
+        let surface = Heightmap::from_fn(width, depth, |x, z| terrain.get(x, z).max(sea_level));
+        let wind_speed = wind_speed(&surface, sea_level, wind, cell_size, config);
+
+        // How much of the air arriving at each cell rains out there. Constant over the passes,
+        // so worked out once.
+        let mut upwind = Vec::with_capacity(width * depth);
+        let mut rates = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let (ux, uz) = (x as f32 - wind[0], z as f32 - wind[1]);
+                let inside =
+                    ux >= 0.0 && uz >= 0.0 && ux <= (width - 1) as f32 && uz <= (depth - 1) as f32;
+                let height = surface.get(x, z);
+                let climb = if inside {
+                    (height - surface.sample(ux, uz)) / cell_size
+                } else {
+                    0.0
+                };
+                let rate = config.rain_rate * (1.0 + config.orographic_rate * climb).max(0.0);
+
+                upwind.push(if inside { Some((ux, uz)) } else { None });
+                rates.push(rate.min(1.0));
+            }
+        }
+
+        // Water carried by the air leaving each cell, starting everywhere as humid as the
+        // incoming wind
+        let mut air = Heightmap::from_fn(width, depth, |_, _| config.humidity);
+        let mut next = air.clone();
+        let mut precipitation = Heightmap::new(width, depth);
+        for _ in 0..config.iterations {
+            for i in 0..width * depth {
+                let arriving = match upwind[i] {
+                    Some((ux, uz)) => air.sample(ux, uz),
+                    None => config.humidity,
+                };
+                let rain = arriving * rates[i];
+                let mut leaving = arriving - rain;
+                if terrain.samples[i] < sea_level {
+                    leaving += config.evaporation * (config.humidity - leaving).max(0.0);
+                }
+                precipitation.samples[i] = rain;
+                next.samples[i] = leaving;
+            }
+            std::mem::swap(&mut air, &mut next);
+        }
+
+        Climate {
+            precipitation,
+            wind_speed,
+        }
+    }
+}
+
+// Faster the higher a cell is above the sea, and slower the steeper the ground rising upwind of
+// it within `shelter_distance`
+fn wind_speed(
+    surface: &Heightmap,
+    sea_level: f32,
+    wind: [f32; 2],
+    cell_size: f32,
+    config: &ClimateConfig,
+) -> Heightmap {
+    let range = (surface.min_max().1 - sea_level).max(f32::EPSILON);
+    let steps = (config.shelter_distance / cell_size).ceil().max(0.0) as usize;
+
+    Heightmap::from_fn(surface.width, surface.depth, |x, z| {
+        let height = surface.get(x, z);
+        let elevation = (height - sea_level) / range;
+        let shelter = (1..=steps)
+            .map(|step| {
+                let distance = step as f32;
+                let upwind =
+                    surface.sample(x as f32 - wind[0] * distance, z as f32 - wind[1] * distance);
+                (upwind - height) / (distance * cell_size)
+            })
+            .fold(0.0, f32::max);
+
+        config.wind_speed * (1.0 + config.elevation_speedup * elevation)
+            / (1.0 + config.shelter_strength * shelter)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A ridge running along z across the middle of a flat, wide map, with the wind blowing
+    // over it in +x
+    fn ridge() -> Heightmap {
+        Heightmap::from_fn(48, 8, |x, _| (8.0 - (x as f32 - 24.0).abs()).max(0.0))
+    }
+
+    fn config() -> ClimateConfig {
+        ClimateConfig {
+            iterations: 64,
+            ..ClimateConfig::default()
+        }
+    }
+
+    #[test]
+    fn rains_on_windward_slopes() {
+        let climate = Climate::new(&ridge(), -1.0, 1.0, &config());
+        let rain = |x| climate.precipitation.get(x, 4);
+
+        assert!(rain(20) > rain(10));
+        assert!(rain(20) > rain(28));
+        // The lee side is in the ridge's rain shadow
+        assert!(rain(28) < rain(10));
+        assert!(rain(40) < rain(10));
+    }
+
+    #[test]
+    fn wind_is_sheltered_behind_ridges() {
+        let climate = Climate::new(&ridge(), -1.0, 1.0, &config());
+        let speed = |x| climate.wind_speed.get(x, 4);
+
+        assert!(speed(24) > speed(10));
+        assert!(speed(34) < speed(10));
+    }
+
+    #[test]
+    fn follows_the_wind_direction() {
+        let config = ClimateConfig {
+            wind_direction: 180.0,
+            ..config()
+        };
+        let climate = Climate::new(&ridge(), -1.0, 1.0, &config);
+        let rain = |x| climate.precipitation.get(x, 4);
+
+        assert!(rain(28) > rain(20));
+        assert!(rain(10) < rain(40));
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let a = Climate::new(&ridge(), 0.5, 2.0, &config());
+        let b = Climate::new(&ridge(), 0.5, 2.0, &config());
+        assert_eq!(a.precipitation, b.precipitation);
+        assert_eq!(a.wind_speed, b.wind_speed);
+    }
+}
@@ -0,0 +1,192 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ClockConfig {
+    /// Seconds of simulated time per update.
+    pub timestep: f32,
+    /// Most updates run for one frame. A frame that falls further behind drops the rest rather
+    /// than spiralling into ever longer catch ups.
+    pub max_steps: u32,
+    /// Simulated seconds per real second.
+    pub time_scale: f32,
+    pub paused: bool,
+    /// Hours after midnight the clock starts at.
+    pub time_of_day: f32,
+    /// Simulated seconds in a full day, 0 stops the day moving on its own.
+    pub day_length: f32,
+    /// Hours the day moves on per second while the `time_of_day` axis is held.
+    pub scrub_speed: f32,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            timestep: 1.0 / 60.0,
+            max_steps: 8,
+            time_scale: 1.0,
+            paused: false,
+            time_of_day: 10.0,
+            day_length: 600.0,
+            scrub_speed: 1.0,
+        }
+    }
+}
+
+/// Simulation time, advanced by frame times but handed out in fixed steps so updates don't
+/// depend on the frame rate.
+#[derive(Debug)]
+pub struct Clock {
+    timestep: f32,
+    max_steps: u32,
+    time_scale: f32,
+    paused: bool,
+    day_length: f32,
+    pub scrub_speed: f32,
+    accumulator: f32,
+    elapsed: f64,
+    time_of_day: f32,
+}
+
+impl Clock {
+    pub fn new(config: &ClockConfig) -> Clock {
+        let mut clock = Clock {
+            timestep: config.timestep.max(f32::EPSILON),
+            max_steps: config.max_steps.max(1),
+            time_scale: 1.0,
+            paused: config.paused,
+            day_length: config.day_length,
+            scrub_speed: config.scrub_speed,
+            accumulator: 0.0,
+            elapsed: 0.0,
+            time_of_day: 0.0,
+        };
+        clock.set_time_scale(config.time_scale);
+        clock.set_time_of_day(config.time_of_day);
+        clock
+    }
+
+    /// Adds `frame_time` real seconds and returns how many updates of `timestep` are due.
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        if self.paused {
+            return 0;
+        }
+
+        self.accumulator += frame_time.max(0.0) * self.time_scale;
+        let due = (self.accumulator / self.timestep) as u32;
+        let steps = due.min(self.max_steps);
+        self.accumulator = if due > self.max_steps {
+            0.0
+        } else {
+            self.accumulator - steps as f32 * self.timestep
+        };
+
+        let simulated = steps as f32 * self.timestep;
+        self.elapsed += simulated as f64;
+        if self.day_length > 0.0 {
+            self.set_time_of_day(self.time_of_day + simulated / self.day_length * 24.0);
+        }
+        steps
+    }
+
+    pub fn timestep(&self) -> f32 {
+        self.timestep
+    }
+
+    /// Simulated seconds so far.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    pub fn time_of_day(&self) -> f32 {
+        self.time_of_day
+    }
+
+    /// Wraps into 0..24 hours.
+    pub fn set_time_of_day(&mut self, hours: f32) {
+        self.time_of_day = hours.rem_euclid(24.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quarter second steps and a 24 second day, so an hour passes every second
+    fn clock() -> Clock {
+        Clock::new(&ClockConfig {
+            timestep: 0.25,
+            max_steps: 4,
+            time_of_day: 0.0,
+            day_length: 24.0,
+            ..ClockConfig::default()
+        })
+    }
+
+    #[test]
+    fn carries_leftover_time_to_the_next_frame() {
+        let mut clock = clock();
+        assert_eq!(clock.advance(0.375), 1);
+        assert_eq!(clock.advance(0.125), 1);
+        assert_eq!(clock.advance(0.125), 0);
+        assert_eq!(clock.elapsed(), 0.5);
+    }
+
+    #[test]
+    fn drops_the_backlog_past_max_steps() {
+        let mut clock = clock();
+        assert_eq!(clock.advance(10.0), 4);
+        assert_eq!(clock.elapsed(), 1.0);
+        assert_eq!(clock.advance(0.0), 0);
+    }
+
+    #[test]
+    fn pausing_stops_time() {
+        let mut clock = clock();
+        clock.set_paused(true);
+        assert_eq!(clock.advance(1.0), 0);
+        assert_eq!((clock.elapsed(), clock.time_of_day()), (0.0, 0.0));
+
+        clock.set_paused(false);
+        assert_eq!(clock.advance(0.5), 2);
+    }
+
+    #[test]
+    fn time_scale_speeds_up_simulation() {
+        let mut clock = clock();
+        clock.set_time_scale(2.0);
+        assert_eq!(clock.advance(0.5), 4);
+        clock.set_time_scale(-1.0);
+        assert_eq!(clock.time_scale(), 0.0);
+        assert_eq!(clock.advance(1.0), 0);
+    }
+
+    #[test]
+    fn time_of_day_wraps_at_midnight() {
+        let mut clock = clock();
+        clock.set_time_of_day(23.5);
+        clock.advance(1.0);
+        assert_eq!(clock.time_of_day(), 0.5);
+
+        clock.set_time_of_day(-1.0);
+        assert_eq!(clock.time_of_day(), 23.0);
+        clock.set_time_of_day(48.0);
+        assert_eq!(clock.time_of_day(), 0.0);
+    }
+}
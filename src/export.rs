@@ -0,0 +1,451 @@
+use crate::na;
+use crate::renderer::{linear_to_srgb, srgb_to_linear};
+use crate::terrain::{Heightmap, SplatConfig, SplatMap, MAX_SPLAT_LAYERS};
+use crate::water::{water_levels, WaterMap};
+use crate::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+mod gltf;
+mod obj;
+mod stl;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    /// Keeps every nth heightmap sample along each side, and always the last. 1 keeps them all.
+    pub decimation: usize,
+    /// Multiplies every height, water included.
+    pub vertical_exaggeration: f32,
+    /// Adds the water surface as a second mesh, except to STL files and solids with a `base`,
+    /// where a sheet of water with no thickness would leave the print non-manifold.
+    pub water: bool,
+    /// Closes the terrain into a solid, with walls down to a flat base, for 3D printing.
+    pub base: bool,
+    /// How far the base sits below the lowest point, in world units.
+    pub base_depth: f32,
+    /// Bakes the material layers into a colour texture for OBJ and glTF files.
+    pub texture: bool,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        ExportConfig {
+            decimation: 1,
+            vertical_exaggeration: 1.0,
+            water: true,
+            base: false,
+            base_depth: 2.0,
+            texture: true,
+        }
+    }
+}
+
+impl ExportConfig {
+    /// Whether the water surface goes into an export to `path`, see `water`.
+    pub fn includes_water(&self, path: &Path) -> bool {
+        self.water && !self.base && extension(path) != "stl"
+    }
+}
+
+/// A triangle mesh ready to write out, with a single material.
+#[derive(Clone, Debug)]
+pub struct ExportMesh {
+    pub name: String,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// Texture coordinates with v running down the image, as glTF has them.
+    pub uvs: Vec<[f32; 2]>,
+    /// Counter-clockwise triangles seen from the front.
+    pub indices: Vec<u32>,
+    /// Linear RGBA, multiplied into the texture.
+    pub color: [f32; 4],
+    /// sRGB colour texture.
+    pub texture: Option<image::RgbaImage>,
+}
+
+impl ExportMesh {
+    fn new(name: &str, color: [f32; 4]) -> ExportMesh {
+        ExportMesh {
+            name: name.to_string(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+            color,
+            texture: None,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn push_vertex(&mut self, position: [f32; 3], normal: [f32; 3], uv: [f32; 2]) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        (self.positions.len() - 1) as u32
+    }
+
+    // Adds a triangle wound to face along `facing`
+    fn push_triangle(&mut self, corners: [u32; 3], facing: na::Vector3<f32>) {
+        let [a, b, c] = corners.map(|i| na::Vector3::from(self.positions[i as usize]));
+        if (b - a).cross(&(c - a)).dot(&facing) < 0.0 {
+            self.indices
+                .extend_from_slice(&[corners[0], corners[2], corners[1]]);
+        } else {
+            self.indices.extend_from_slice(&corners);
+        }
+    }
+}
+
+/// Grids `heightmap` like `terrain::build_mesh`, keeping only the samples `config` decimates
+/// down to, and closes it into a solid when `config.base` is set.
+pub fn terrain_mesh(
+    heightmap: &Heightmap,
+    cell_size: f32,
+    config: &ExportConfig,
+) -> Result<ExportMesh> {
+    let (width, depth) = (heightmap.width, heightmap.depth);
+    if width < 2 || depth < 2 {
+        return Err(format!("Heightmap {}x{} is too small to mesh", width, depth).into());
+    }
+
+    let exaggeration = config.vertical_exaggeration;
+    let heights = Heightmap::from_fn(width, depth, |x, z| heightmap.get(x, z) * exaggeration);
+    let (columns, rows) = (
+        samples(width, config.decimation),
+        samples(depth, config.decimation),
+    );
+
+    let mut mesh = ExportMesh::new("terrain", [1.0, 1.0, 1.0, 1.0]);
+    for &z in &rows {
+        for &x in &columns {
+            let normal = heights.normal(x, z, cell_size);
+            mesh.push_vertex(
+                [
+                    x as f32 * cell_size,
+                    heights.get(x, z),
+                    z as f32 * cell_size,
+                ],
+                normal.into(),
+                [x as f32 / (width - 1) as f32, z as f32 / (depth - 1) as f32],
+            );
+        }
+    }
+    grid_indices(&mut mesh, columns.len(), rows.len(), |_, _| true);
+
+    if config.base {
+        let lowest = heights.samples.iter().cloned().fold(f32::MAX, f32::min);
+        add_base(
+            &mut mesh,
+            columns.len(),
+            rows.len(),
+            lowest - config.base_depth,
+        );
+    }
+    Ok(mesh)
+}
+
+/// The water surface over the same decimated grid as `terrain_mesh`, over every quad with
+/// water anywhere inside it. `None` when the map is dry.
+pub fn water_mesh(
+    water: &WaterMap,
+    cell_size: f32,
+    color: [f32; 4],
+    config: &ExportConfig,
+) -> Option<ExportMesh> {
+    let levels = water_levels(water);
+    let (width, depth) = (levels.width, levels.depth);
+    let (columns, rows) = (
+        samples(width, config.decimation),
+        samples(depth, config.decimation),
+    );
+
+    let mut mesh = ExportMesh::new("water", color);
+    for &z in &rows {
+        for &x in &columns {
+            mesh.push_vertex(
+                [
+                    x as f32 * cell_size,
+                    levels.get(x, z) * config.vertical_exaggeration,
+                    z as f32 * cell_size,
+                ],
+                [0.0, 1.0, 0.0],
+                [x as f32 / (width - 1) as f32, z as f32 / (depth - 1) as f32],
+            );
+        }
+    }
+    grid_indices(&mut mesh, columns.len(), rows.len(), |column, row| {
+        (rows[row]..=rows[row + 1])
+            .any(|z| (columns[column]..=columns[column + 1]).any(|x| water.is_wet(x, z)))
+    });
+
+    if mesh.indices.is_empty() {
+        None
+    } else {
+        Some(mesh)
+    }
+}
+
+/// Paints every heightmap sample with its blend of the material layers' colours, one pixel per
+/// sample. Textured layers use their texture's average colour, tinted.
+pub fn bake_texture(splat: &SplatMap, materials: &SplatConfig) -> Result<image::RgbaImage> {
+    let layers = &materials.layers[..materials.layers.len().min(MAX_SPLAT_LAYERS)];
+    let mut colors = [[0.0; 3]; MAX_SPLAT_LAYERS];
+    for (color, layer) in colors.iter_mut().zip(layers) {
+        let average = match &layer.texture {
+            Some(path) => average_color(path)?,
+            None => [1.0; 3],
+        };
+        for channel in 0..3 {
+            color[channel] = layer.color[channel] * average[channel];
+        }
+    }
+
+    Ok(image::RgbaImage::from_fn(
+        splat.width as u32,
+        splat.depth as u32,
+        |x, z| {
+            let weights = splat.get(x as usize, z as usize);
+            let mut pixel = [0, 0, 0, 255];
+            for channel in 0..3 {
+                let linear: f32 = (0..MAX_SPLAT_LAYERS)
+                    .map(|layer| weights[layer] * colors[layer][channel])
+                    .sum();
+                pixel[channel] = linear_to_srgb(linear);
+            }
+            image::Rgba(pixel)
+        },
+    ))
+}
+
+/// Writes `meshes` in the format the extension names, `.obj`, `.stl`, `.gltf` or `.glb`. OBJ
+/// files get a `.mtl` and PNG textures beside them, glTF files embed their textures, and STL
+/// files have no materials at all.
+pub fn write_meshes(meshes: &[ExportMesh], path: &Path) -> Result<()> {
+    match extension(path).as_str() {
+        "obj" => obj::write(meshes, path),
+        "stl" => stl::write(meshes, path),
+        "gltf" => gltf::write(meshes, path, false),
+        "glb" => gltf::write(meshes, path, true),
+        _ => Err(format!("Unknown mesh format: {}", path.display()).into()),
+    }
+}
+
+// Sample indices `step` apart, always ending on the last
+fn samples(count: usize, step: usize) -> Vec<usize> {
+    let mut samples: Vec<usize> = (0..count).step_by(step.max(1)).collect();
+    if samples.last() != Some(&(count - 1)) {
+        samples.push(count - 1);
+    }
+    samples
+}
+
+// Two upward facing triangles for every grid quad `keep` allows, with vertices laid out in rows
+fn grid_indices(
+    mesh: &mut ExportMesh,
+    columns: usize,
+    rows: usize,
+    keep: impl Fn(usize, usize) -> bool,
+) {
+    for row in 0..rows - 1 {
+        for column in 0..columns - 1 {
+            if !keep(column, row) {
+                continue;
+            }
+            let i00 = (row * columns + column) as u32;
+            let i10 = i00 + 1;
+            let i01 = i00 + columns as u32;
+            let i11 = i01 + 1;
+            mesh.indices
+                .extend_from_slice(&[i00, i01, i10, i10, i01, i11]);
+        }
+    }
+}
+
+// Walls from the grid's edge down to `base`, and a floor across the bottom. Walls and floor get
+// their own vertices so their normals stay flat.
+fn add_base(mesh: &mut ExportMesh, columns: usize, rows: usize, base: f32) {
+    // The grid's edge vertices, once around
+    let mut edge: Vec<u32> = Vec::with_capacity(2 * (columns + rows));
+    edge.extend((0..columns).map(|column| column as u32));
+    edge.extend((1..rows).map(|row| (row * columns + columns - 1) as u32));
+    edge.extend(
+        (0..columns - 1)
+            .rev()
+            .map(|column| ((rows - 1) * columns + column) as u32),
+    );
+    edge.extend((1..rows - 1).rev().map(|row| (row * columns) as u32));
+
+    let first = na::Vector3::from(mesh.positions[0]);
+    let last = na::Vector3::from(mesh.positions[mesh.positions.len() - 1]);
+    let center = (first + last) / 2.0;
+
+    let mut floor = Vec::with_capacity(edge.len());
+    for (i, &a) in edge.iter().enumerate() {
+        let b = edge[(i + 1) % edge.len()];
+        let (top_a, top_b) = (mesh.positions[a as usize], mesh.positions[b as usize]);
+        let (uv_a, uv_b) = (mesh.uvs[a as usize], mesh.uvs[b as usize]);
+        // Each stretch of edge runs along x or z, facing away from the middle
+        let outward = if top_a[2] == top_b[2] {
+            na::Vector3::new(0.0, 0.0, (top_a[2] - center.z).signum())
+        } else {
+            na::Vector3::new((top_a[0] - center.x).signum(), 0.0, 0.0)
+        };
+        let normal = outward.into();
+
+        let corners = [
+            mesh.push_vertex(top_a, normal, uv_a),
+            mesh.push_vertex(top_b, normal, uv_b),
+            mesh.push_vertex([top_b[0], base, top_b[2]], normal, uv_b),
+            mesh.push_vertex([top_a[0], base, top_a[2]], normal, uv_a),
+        ];
+        mesh.push_triangle([corners[0], corners[1], corners[2]], outward);
+        mesh.push_triangle([corners[0], corners[2], corners[3]], outward);
+        floor.push(([top_a[0], base, top_a[2]], uv_a));
+    }
+
+    // A fan from the middle of the floor out to every edge vertex
+    let down = na::Vector3::new(0.0, -1.0, 0.0);
+    let middle = mesh.push_vertex([center.x, base, center.z], down.into(), [0.5, 0.5]);
+    let ring: Vec<u32> = floor
+        .iter()
+        .map(|&(position, uv)| mesh.push_vertex(position, down.into(), uv))
+        .collect();
+    for (i, &a) in ring.iter().enumerate() {
+        mesh.push_triangle([middle, a, ring[(i + 1) % ring.len()]], down);
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+fn average_color(path: &Path) -> Result<[f32; 3]> {
+    let texture = image::open(path)
+        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?
+        .into_rgb8();
+    let mut total = [0.0f64; 3];
+    for pixel in texture.pixels() {
+        for channel in 0..3 {
+            total[channel] += srgb_to_linear(pixel[channel]) as f64;
+        }
+    }
+    let count = (texture.width() * texture.height()).max(1) as f64;
+    Ok([
+        (total[0] / count) as f32,
+        (total[1] / count) as f32,
+        (total[2] / count) as f32,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn hill() -> Heightmap {
+        Heightmap::from_fn(7, 5, |x, z| {
+            4.0 - (x as f32 - 3.0).abs() - (z as f32 - 2.0).abs()
+        })
+    }
+
+    // Every edge of a closed surface is shared by exactly two triangles, running opposite ways
+    fn assert_closed(mesh: &ExportMesh) {
+        let key = |i: u32| {
+            let [x, y, z] = mesh.positions[i as usize];
+            (x.to_bits(), y.to_bits(), z.to_bits())
+        };
+        let mut edges = HashMap::new();
+        for triangle in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                let edge = (key(triangle[i]), key(triangle[(i + 1) % 3]));
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge used {} times", count);
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge has no twin");
+        }
+    }
+
+    #[test]
+    fn decimation_keeps_the_edges() {
+        assert_eq!(samples(7, 1), vec![0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(samples(7, 4), vec![0, 4, 6]);
+        assert_eq!(samples(7, 6), vec![0, 6]);
+        assert_eq!(samples(7, 10), vec![0, 6]);
+
+        let config = ExportConfig {
+            decimation: 2,
+            ..ExportConfig::default()
+        };
+        let mesh = terrain_mesh(&hill(), 2.0, &config).unwrap();
+        assert_eq!(mesh.positions.len(), 4 * 3);
+        assert_eq!(mesh.triangle_count(), 2 * 3 * 2);
+        assert_eq!(mesh.positions[11], [12.0, -1.0, 8.0]);
+    }
+
+    #[test]
+    fn leaves_water_out_of_prints() {
+        let config = ExportConfig::default();
+        assert!(config.includes_water(Path::new("terrain.glb")));
+        assert!(!config.includes_water(Path::new("terrain.STL")));
+
+        let solid = ExportConfig {
+            base: true,
+            ..ExportConfig::default()
+        };
+        assert!(!solid.includes_water(Path::new("terrain.obj")));
+    }
+
+    #[test]
+    fn exaggerates_heights() {
+        let config = ExportConfig {
+            vertical_exaggeration: 2.5,
+            ..ExportConfig::default()
+        };
+        let mesh = terrain_mesh(&hill(), 1.0, &config).unwrap();
+        assert_eq!(mesh.positions[2 * 7 + 3][1], 10.0);
+    }
+
+    #[test]
+    fn base_closes_the_mesh() {
+        let config = ExportConfig {
+            base: true,
+            base_depth: 1.0,
+            decimation: 2,
+            ..ExportConfig::default()
+        };
+        let mesh = terrain_mesh(&hill(), 1.0, &config).unwrap();
+        assert_closed(&mesh);
+
+        let lowest = mesh.positions.iter().map(|p| p[1]).fold(f32::MAX, f32::min);
+        assert_eq!(lowest, -2.0);
+    }
+
+    #[test]
+    fn faces_point_outwards() {
+        let config = ExportConfig {
+            base: true,
+            ..ExportConfig::default()
+        };
+        let mesh = terrain_mesh(&hill(), 1.0, &config).unwrap();
+        // A closed surface wound outwards has positive volume
+        let volume: f32 = mesh
+            .indices
+            .chunks(3)
+            .map(|triangle| {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| na::Vector3::from(mesh.positions[triangle[i] as usize]));
+                a.dot(&b.cross(&c)) / 6.0
+            })
+            .sum();
+        assert!(volume > 0.0, "{}", volume);
+    }
+}
@@ -0,0 +1,308 @@
+use super::ExportMesh;
+use crate::Result;
+use image::ImageEncoder;
+use std::{fs, path::Path};
+
+// Buffer view targets and accessor component types
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+/// Writes the meshes as one glTF 2.0 scene, each its own node and material. The buffer and PNG
+/// textures are embedded, as the binary chunk of a `.glb` when `binary` is set and as a base64
+/// data URI otherwise.
+pub fn write(meshes: &[ExportMesh], path: &Path, binary: bool) -> Result<()> {
+    let bytes = encode(meshes, binary)?;
+    fs::write(path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(())
+}
+
+// The JSON is simple and fixed enough to write by hand, every name comes from this crate
+fn encode(meshes: &[ExportMesh], binary: bool) -> Result<Vec<u8>> {
+    let mut buffer = Buffer::default();
+    let (mut nodes, mut gltf_meshes, mut materials) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut textures, mut images) = (Vec::new(), Vec::new());
+
+    for (i, mesh) in meshes.iter().enumerate() {
+        let count = mesh.positions.len();
+        let (min, max) = bounds(&mesh.positions);
+        let position = buffer.accessor(
+            bytemuck::cast_slice(&mesh.positions),
+            ARRAY_BUFFER,
+            FLOAT,
+            count,
+            "VEC3",
+            Some(format!(",\"min\":{:?},\"max\":{:?}", min, max)),
+        );
+        let normal = buffer.accessor(
+            bytemuck::cast_slice(&mesh.normals),
+            ARRAY_BUFFER,
+            FLOAT,
+            count,
+            "VEC3",
+            None,
+        );
+        let uv = buffer.accessor(
+            bytemuck::cast_slice(&mesh.uvs),
+            ARRAY_BUFFER,
+            FLOAT,
+            count,
+            "VEC2",
+            None,
+        );
+        let indices = buffer.accessor(
+            bytemuck::cast_slice(&mesh.indices),
+            ELEMENT_ARRAY_BUFFER,
+            UNSIGNED_INT,
+            mesh.indices.len(),
+            "SCALAR",
+            None,
+        );
+
+        nodes.push(format!("{{\"name\":\"{}\",\"mesh\":{}}}", mesh.name, i));
+        gltf_meshes.push(format!(
+            "{{\"name\":\"{}\",\"primitives\":[{{\"attributes\":{{\"POSITION\":{},\"NORMAL\":{},\
+             \"TEXCOORD_0\":{}}},\"indices\":{},\"material\":{}}}]}}",
+            mesh.name, position, normal, uv, indices, i
+        ));
+
+        let texture = match &mesh.texture {
+            Some(texture) => {
+                let mut png = Vec::new();
+                image::png::PngEncoder::new(&mut png)
+                    .write_image(
+                        texture,
+                        texture.width(),
+                        texture.height(),
+                        image::ColorType::Rgba8,
+                    )
+                    .map_err(|e| format!("Failed to encode texture: {}", e))?;
+                let view = buffer.view(&png, None);
+                images.push(format!(
+                    "{{\"bufferView\":{},\"mimeType\":\"image/png\"}}",
+                    view
+                ));
+                textures.push(format!("{{\"sampler\":0,\"source\":{}}}", images.len() - 1));
+                format!(",\"baseColorTexture\":{{\"index\":{}}}", textures.len() - 1)
+            }
+            None => String::new(),
+        };
+        let blend = if mesh.color[3] < 1.0 {
+            ",\"alphaMode\":\"BLEND\",\"doubleSided\":true"
+        } else {
+            ""
+        };
+        materials.push(format!(
+            "{{\"name\":\"{}\",\"pbrMetallicRoughness\":{{\"baseColorFactor\":{:?},\
+             \"metallicFactor\":0,\"roughnessFactor\":1{}}}{}}}",
+            mesh.name, mesh.color, texture, blend
+        ));
+    }
+
+    let buffer_json = if binary {
+        format!("{{\"byteLength\":{}}}", buffer.data.len())
+    } else {
+        format!(
+            "{{\"byteLength\":{},\"uri\":\"data:application/octet-stream;base64,{}\"}}",
+            buffer.data.len(),
+            base64(&buffer.data)
+        )
+    };
+    let mut fields = vec![
+        "\"asset\":{\"version\":\"2.0\",\"generator\":\"rock_and_water\"}".to_string(),
+        "\"scene\":0".to_string(),
+        format!(
+            "\"scenes\":[{{\"nodes\":[{}]}}]",
+            (0..meshes.len())
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        format!("\"nodes\":[{}]", nodes.join(",")),
+        format!("\"meshes\":[{}]", gltf_meshes.join(",")),
+        format!("\"materials\":[{}]", materials.join(",")),
+        format!("\"accessors\":[{}]", buffer.accessors.join(",")),
+        format!("\"bufferViews\":[{}]", buffer.views.join(",")),
+        format!("\"buffers\":[{}]", buffer_json),
+    ];
+    // glTF doesn't allow empty arrays
+    if !images.is_empty() {
+        fields.push(format!("\"images\":[{}]", images.join(",")));
+        fields.push(format!("\"textures\":[{}]", textures.join(",")));
+        // Linear filtering with mipmaps, clamped to the edges
+        fields.push(
+            "\"samplers\":[{\"magFilter\":9729,\"minFilter\":9987,\"wrapS\":33071,\
+             \"wrapT\":33071}]"
+                .to_string(),
+        );
+    }
+    let json = format!("{{{}}}", fields.join(","));
+
+    if binary {
+        Ok(glb(json.into_bytes(), buffer.data))
+    } else {
+        Ok(json.into_bytes())
+    }
+}
+
+// One binary buffer shared by every mesh, with the JSON describing its views and accessors
+#[derive(Default)]
+struct Buffer {
+    data: Vec<u8>,
+    views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl Buffer {
+    fn view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        // Accessors need their data aligned to its component size
+        while self.data.len() % 4 != 0 {
+            self.data.push(0);
+        }
+        let target = target.map_or(String::new(), |target| format!(",\"target\":{}", target));
+        self.views.push(format!(
+            "{{\"buffer\":0,\"byteOffset\":{},\"byteLength\":{}{}}}",
+            self.data.len(),
+            bytes.len(),
+            target
+        ));
+        self.data.extend_from_slice(bytes);
+        self.views.len() - 1
+    }
+
+    fn accessor(
+        &mut self,
+        bytes: &[u8],
+        target: u32,
+        component_type: u32,
+        count: usize,
+        kind: &str,
+        extra: Option<String>,
+    ) -> usize {
+        let view = self.view(bytes, Some(target));
+        self.accessors.push(format!(
+            "{{\"bufferView\":{},\"componentType\":{},\"count\":{},\"type\":\"{}\"{}}}",
+            view,
+            component_type,
+            count,
+            kind,
+            extra.unwrap_or_default()
+        ));
+        self.accessors.len() - 1
+    }
+}
+
+fn bounds(positions: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for position in positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(position[axis]);
+            max[axis] = max[axis].max(position[axis]);
+        }
+    }
+    (min, max)
+}
+
+// A 12 byte header, then the JSON chunk padded with spaces and the binary chunk padded with
+// zeros, both to 4 bytes
+fn glb(mut json: Vec<u8>, mut bin: Vec<u8>) -> Vec<u8> {
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    while bin.len() % 4 != 0 {
+        bin.push(0);
+    }
+    let length = 12 + 8 + json.len() + 8 + bin.len();
+
+    let mut glb = Vec::with_capacity(length);
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2_u32.to_le_bytes());
+    glb.extend_from_slice(&(length as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&bin);
+    glb
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{terrain_mesh, ExportConfig};
+    use crate::terrain::Heightmap;
+
+    fn meshes() -> Vec<ExportMesh> {
+        let heightmap = Heightmap::from_fn(4, 3, |x, z| (x + z) as f32);
+        let config = ExportConfig {
+            base: true,
+            ..ExportConfig::default()
+        };
+        let mut terrain = terrain_mesh(&heightmap, 1.0, &config).unwrap();
+        terrain.texture = Some(image::RgbaImage::from_pixel(
+            4,
+            3,
+            image::Rgba([10, 200, 30, 255]),
+        ));
+        let mut water = terrain_mesh(&heightmap, 1.0, &ExportConfig::default()).unwrap();
+        water.name = "water".to_string();
+        water.color = [0.1, 0.4, 0.5, 0.7];
+        vec![terrain, water]
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn loads_back() {
+        let meshes = meshes();
+        for &binary in &[false, true] {
+            // Only file imports read data URIs
+            let path = std::env::temp_dir().join(format!("rock_and_water_{}.gltf", binary));
+            write(&meshes, &path, binary).unwrap();
+            let (document, buffers, images) = ::gltf::import(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(document.meshes().count(), 2);
+            assert_eq!(images.len(), 1);
+            assert_eq!((images[0].width, images[0].height), (4, 3));
+
+            for (mesh, original) in document.meshes().zip(&meshes) {
+                let primitive = mesh.primitives().next().unwrap();
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
+                let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
+                assert_eq!(positions, original.positions);
+                assert_eq!(indices, original.indices);
+            }
+            let water = document.materials().nth(1).unwrap();
+            assert_eq!(water.alpha_mode(), ::gltf::material::AlphaMode::Blend);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use super::ExportMesh;
+use crate::Result;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Writes each mesh as an object in one OBJ file. Materials go in a `.mtl` of the same name,
+/// and each texture in a PNG named after the file and the mesh.
+pub fn write(meshes: &[ExportMesh], path: &Path) -> Result<()> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("terrain");
+    let material_path = path.with_extension("mtl");
+    let mut obj = create(path)?;
+    let mut mtl = create(&material_path)?;
+
+    writeln!(obj, "mtllib {}", file_name(&material_path))?;
+    // OBJ indices count from 1 across the whole file
+    let mut first = 1;
+    for mesh in meshes {
+        writeln!(obj, "o {}", mesh.name)?;
+        for [x, y, z] in &mesh.positions {
+            writeln!(obj, "v {} {} {}", x, y, z)?;
+        }
+        // OBJ texture coordinates start at the bottom of the image
+        for [u, v] in &mesh.uvs {
+            writeln!(obj, "vt {} {}", u, 1.0 - v)?;
+        }
+        for [x, y, z] in &mesh.normals {
+            writeln!(obj, "vn {} {} {}", x, y, z)?;
+        }
+        writeln!(obj, "usemtl {}", mesh.name)?;
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [
+                triangle[0] + first,
+                triangle[1] + first,
+                triangle[2] + first,
+            ];
+            writeln!(obj, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?;
+        }
+        first += mesh.positions.len() as u32;
+
+        let [r, g, b, a] = mesh.color;
+        writeln!(mtl, "newmtl {}", mesh.name)?;
+        writeln!(mtl, "Kd {} {} {}", r, g, b)?;
+        writeln!(mtl, "Ks 0 0 0")?;
+        if a < 1.0 {
+            writeln!(mtl, "d {}", a)?;
+        }
+        if let Some(texture) = &mesh.texture {
+            let texture_path = path.with_file_name(format!("{}_{}.png", stem, mesh.name));
+            texture
+                .save(&texture_path)
+                .map_err(|e| format!("Failed to save {}: {}", texture_path.display(), e))?;
+            writeln!(mtl, "map_Kd {}", file_name(&texture_path))?;
+        }
+        writeln!(mtl)?;
+    }
+
+    obj.flush()?;
+    mtl.flush()?;
+    Ok(())
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    Ok(BufWriter::new(file))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,78 @@
+use super::ExportMesh;
+use crate::{na, Result};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Writes every mesh's triangles into one binary STL. Printers and slicers expect +z up, so
+/// the terrain's +y is turned to +z.
+pub fn write(meshes: &[ExportMesh], path: &Path) -> Result<()> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut writer = BufWriter::new(file);
+    write_to(meshes, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_to(meshes: &[ExportMesh], writer: &mut impl Write) -> Result<()> {
+    let mut header = [0; 80];
+    let title = b"rock_and_water terrain";
+    header[..title.len()].copy_from_slice(title);
+    writer.write_all(&header)?;
+
+    let triangles: usize = meshes.iter().map(ExportMesh::triangle_count).sum();
+    writer.write_all(&(triangles as u32).to_le_bytes())?;
+
+    // A quarter turn about x, which keeps the winding
+    let z_up = |[x, y, z]: [f32; 3]| na::Vector3::new(x, -z, y);
+    for mesh in meshes {
+        for triangle in mesh.indices.chunks(3) {
+            let [a, b, c] = [
+                z_up(mesh.positions[triangle[0] as usize]),
+                z_up(mesh.positions[triangle[1] as usize]),
+                z_up(mesh.positions[triangle[2] as usize]),
+            ];
+            let normal = (b - a)
+                .cross(&(c - a))
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(na::Vector3::zeros);
+            for vector in &[normal, a, b, c] {
+                for value in vector.iter() {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            // Attribute byte count, unused
+            writer.write_all(&[0, 0])?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_one_record_per_triangle() {
+        let mut mesh = ExportMesh::new("test", [1.0; 4]);
+        for position in &[[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]] {
+            mesh.push_vertex(*position, [0.0, 1.0, 0.0], [0.0, 0.0]);
+        }
+        mesh.indices = vec![0, 1, 2, 0, 1, 2];
+
+        let mut bytes = Vec::new();
+        write_to(&[mesh.clone(), mesh], &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 80 + 4 + 4 * 50);
+        assert_eq!(&bytes[80..84], &4_u32.to_le_bytes());
+
+        // The upward facing triangle's normal now points along +z
+        let normal: Vec<f32> = bytes[84..96]
+            .chunks(4)
+            .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+            .collect();
+        assert_eq!(normal, vec![0.0, 0.0, 1.0]);
+    }
+}
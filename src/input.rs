@@ -1,39 +1,421 @@
-use winit::event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode};
+use log::warn;
+use winit::{
+    dpi::PhysicalPosition,
+    event::{
+        DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+        WindowEvent,
+    },
+    window::Window,
+};
 
 use std::collections::HashSet;
+use std::hash::Hash;
+
+mod bindings;
+
+pub use bindings::{Bindings, InputConfig};
+
+// Pixel scroll deltas (touchpads) are scaled down to roughly one line per notch
+const PIXELS_PER_LINE: f64 = 20.0;
+
+/// Held buttons plus the ones that changed since the last `end_frame`.
+#[derive(Debug)]
+struct ButtonSet<T: Eq + Hash> {
+    held: HashSet<T>,
+    just_pressed: HashSet<T>,
+    just_released: HashSet<T>,
+}
+
+impl<T: Copy + Eq + Hash> ButtonSet<T> {
+    fn set(&mut self, button: T, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                // Key repeat sends presses for held keys, which are not new presses
+                if self.held.insert(button) {
+                    self.just_pressed.insert(button);
+                }
+            }
+            ElementState::Released => {
+                if self.held.remove(&button) {
+                    self.just_released.insert(button);
+                }
+            }
+        }
+    }
+
+    fn end_frame(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+}
+
+impl<T: Eq + Hash> Default for ButtonSet<T> {
+    fn default() -> Self {
+        ButtonSet {
+            held: HashSet::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// Stick axes run from -1 to 1 with +y up, triggers from 0 to 1.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+const GAMEPAD_AXES: usize = 6;
 
 #[derive(Default, Debug)]
 pub struct InputState {
-    keys_pressed: HashSet<VirtualKeyCode>,
+    keys: ButtonSet<VirtualKeyCode>,
+    mouse_buttons: ButtonSet<MouseButton>,
+    gamepad_buttons: ButtonSet<GamepadButton>,
+    gamepad_axes: [f32; GAMEPAD_AXES],
+    mouse_delta: (f64, f64),
+    scroll_delta: f32,
+    cursor_position: Option<(f32, f32)>,
+    // Where the cursor was when mouse-look grabbed it
+    grab_position: Option<(f32, f32)>,
+    cursor_grabbed: bool,
+    bindings: Bindings,
+    #[cfg(feature = "gamepad")]
+    gilrs: Option<gilrs::Gilrs>,
 }
 
 impl InputState {
-    pub fn new() -> Self {
-        let keys_pressed = HashSet::new();
+    pub fn new(bindings: Bindings) -> Self {
+        InputState {
+            bindings,
+            #[cfg(feature = "gamepad")]
+            gilrs: gilrs::Gilrs::new()
+                .map_err(|e| warn!("Gamepad support unavailable: {}", e))
+                .ok(),
+            ..Default::default()
+        }
+    }
 
-        InputState { keys_pressed }
+    /// True while any binding of the named action is held.
+    pub fn is_action_pressed(&self, action: &str) -> bool {
+        self.bindings.is_held(self, action)
     }
 
+    pub fn is_action_just_pressed(&self, action: &str) -> bool {
+        self.bindings.is_just_pressed(self, action)
+    }
+
+    pub fn is_action_just_released(&self, action: &str) -> bool {
+        self.bindings.is_just_released(self, action)
+    }
+
+    /// Value of the named axis in [-1, 1].
+    pub fn axis(&self, axis: &str) -> f32 {
+        self.bindings.axis(self, axis)
+    }
+
+    /// True while the key is held down.
     pub fn is_key_pressed(&self, code: VirtualKeyCode) -> bool {
-        self.keys_pressed.contains(&code)
+        self.keys.held.contains(&code)
+    }
+
+    pub fn is_key_just_pressed(&self, code: VirtualKeyCode) -> bool {
+        self.keys.just_pressed.contains(&code)
+    }
+
+    pub fn is_key_just_released(&self, code: VirtualKeyCode) -> bool {
+        self.keys.just_released.contains(&code)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.held.contains(&button)
+    }
+
+    pub fn is_button_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.just_pressed.contains(&button)
+    }
+
+    pub fn is_button_just_released(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.just_released.contains(&button)
+    }
+
+    pub fn is_gamepad_pressed(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.held.contains(&button)
+    }
+
+    pub fn is_gamepad_just_pressed(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.just_pressed.contains(&button)
+    }
+
+    pub fn is_gamepad_just_released(&self, button: GamepadButton) -> bool {
+        self.gamepad_buttons.just_released.contains(&button)
+    }
+
+    /// Current value of a gamepad axis, zero when no gamepad is connected.
+    pub fn gamepad_axis(&self, axis: GamepadAxis) -> f32 {
+        self.gamepad_axes[axis as usize]
+    }
+
+    /// Raw mouse motion since the last `end_frame`.
+    pub fn mouse_delta(&self) -> (f32, f32) {
+        (self.mouse_delta.0 as f32, self.mouse_delta.1 as f32)
+    }
+
+    /// Scroll wheel lines since the last `end_frame`, positive away from the user.
+    pub fn scroll_delta(&self) -> f32 {
+        self.scroll_delta
+    }
+
+    pub fn is_cursor_grabbed(&self) -> bool {
+        self.cursor_grabbed
+    }
+
+    /// Confines and hides the cursor for mouse-look, or releases it again where it was grabbed.
+    pub fn set_cursor_grab(&mut self, window: &Window, grab: bool) {
+        if grab == self.cursor_grabbed {
+            return;
+        }
+        if let Err(e) = window.set_cursor_grab(grab) {
+            warn!("Unable to change cursor grab: {}", e);
+        }
+        window.set_cursor_visible(!grab);
+        self.cursor_grabbed = grab;
+
+        if grab {
+            self.grab_position = self.cursor_position;
+        } else if let Some((x, y)) = self.grab_position.take() {
+            if let Err(e) = window.set_cursor_position(PhysicalPosition::new(x, y)) {
+                warn!("Unable to restore cursor position: {}", e);
+            }
+        }
+    }
+
+    /// Clears the per-frame state once everything has read it.
+    pub fn end_frame(&mut self) {
+        self.keys.end_frame();
+        self.mouse_buttons.end_frame();
+        self.gamepad_buttons.end_frame();
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_delta = 0.0;
     }
 
     pub fn update(&mut self, event: &DeviceEvent) {
-        #![allow(clippy::single_match)]
         match event {
             DeviceEvent::Key(KeyboardInput {
                 virtual_keycode: Some(code),
                 state,
                 ..
-            }) => match state {
-                ElementState::Pressed => {
-                    self.keys_pressed.insert(code.clone());
+            }) => self.keys.set(*code, *state),
+            DeviceEvent::MouseMotion { delta } => {
+                self.mouse_delta.0 += delta.0;
+                self.mouse_delta.1 += delta.1;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn update_window(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some((position.x as f32, position.y as f32));
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+            WindowEvent::MouseInput { state, button, .. } => {
+                self.mouse_buttons.set(*button, *state)
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => (position.y / PIXELS_PER_LINE) as f32,
+                }
+            }
+            // Released buttons are never reported once the window loses focus
+            WindowEvent::Focused(false) => {
+                for code in self.keys.held.drain().collect::<Vec<_>>() {
+                    self.keys.just_released.insert(code);
                 }
-                ElementState::Released => {
-                    self.keys_pressed.remove(code);
+                for button in self.mouse_buttons.held.drain().collect::<Vec<_>>() {
+                    self.mouse_buttons.just_released.insert(button);
                 }
-            },
+            }
             _ => {}
         }
     }
+
+    /// Drains pending gamepad events. Does nothing unless built with the `gamepad` feature.
+    pub fn poll_gamepads(&mut self) {
+        #[cfg(feature = "gamepad")]
+        {
+            use gilrs::EventType;
+
+            let gilrs = match self.gilrs.as_mut() {
+                Some(gilrs) => gilrs,
+                None => return,
+            };
+            while let Some(gilrs::Event { event, .. }) = gilrs.next_event() {
+                match event {
+                    EventType::ButtonPressed(button, _) => {
+                        if let Some(button) = gamepad_button(button) {
+                            self.gamepad_buttons.set(button, ElementState::Pressed);
+                        }
+                    }
+                    EventType::ButtonReleased(button, _) => {
+                        if let Some(button) = gamepad_button(button) {
+                            self.gamepad_buttons.set(button, ElementState::Released);
+                        }
+                    }
+                    // Analog triggers come through as buttons with a value
+                    EventType::ButtonChanged(gilrs::Button::LeftTrigger2, value, _) => {
+                        self.gamepad_axes[GamepadAxis::LeftTrigger as usize] = value;
+                    }
+                    EventType::ButtonChanged(gilrs::Button::RightTrigger2, value, _) => {
+                        self.gamepad_axes[GamepadAxis::RightTrigger as usize] = value;
+                    }
+                    EventType::AxisChanged(axis, value, _) => {
+                        if let Some(axis) = gamepad_axis(axis) {
+                            self.gamepad_axes[axis as usize] = value;
+                        }
+                    }
+                    EventType::Disconnected => {
+                        self.gamepad_axes = [0.0; GAMEPAD_AXES];
+                        for button in self.gamepad_buttons.held.drain().collect::<Vec<_>>() {
+                            self.gamepad_buttons.just_released.insert(button);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn gamepad_button(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button;
+
+    Some(match button {
+        Button::South => GamepadButton::South,
+        Button::East => GamepadButton::East,
+        Button::North => GamepadButton::North,
+        Button::West => GamepadButton::West,
+        Button::LeftTrigger => GamepadButton::LeftBumper,
+        Button::RightTrigger => GamepadButton::RightBumper,
+        Button::Select => GamepadButton::Select,
+        Button::Start => GamepadButton::Start,
+        Button::LeftThumb => GamepadButton::LeftThumb,
+        Button::RightThumb => GamepadButton::RightThumb,
+        Button::DPadUp => GamepadButton::DPadUp,
+        Button::DPadDown => GamepadButton::DPadDown,
+        Button::DPadLeft => GamepadButton::DPadLeft,
+        Button::DPadRight => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+#[cfg(feature = "gamepad")]
+fn gamepad_axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+    use gilrs::Axis;
+
+    Some(match axis {
+        Axis::LeftStickX => GamepadAxis::LeftStickX,
+        Axis::LeftStickY => GamepadAxis::LeftStickY,
+        Axis::RightStickX => GamepadAxis::RightStickX,
+        Axis::RightStickY => GamepadAxis::RightStickY,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+impl InputState {
+    /// Feeds a key event through `update` as winit would deliver it.
+    #[allow(deprecated)]
+    pub fn send_key(&mut self, code: VirtualKeyCode, state: ElementState) {
+        self.update(&DeviceEvent::Key(KeyboardInput {
+            scancode: 0,
+            state,
+            virtual_keycode: Some(code),
+            modifiers: Default::default(),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::DeviceId;
+
+    #[test]
+    fn reports_key_edges_once() {
+        let mut input = InputState::default();
+        input.send_key(VirtualKeyCode::A, ElementState::Pressed);
+        assert!(input.is_key_pressed(VirtualKeyCode::A));
+        assert!(input.is_key_just_pressed(VirtualKeyCode::A));
+
+        // Key repeat while held is not another press
+        input.end_frame();
+        input.send_key(VirtualKeyCode::A, ElementState::Pressed);
+        assert!(input.is_key_pressed(VirtualKeyCode::A));
+        assert!(!input.is_key_just_pressed(VirtualKeyCode::A));
+
+        input.send_key(VirtualKeyCode::A, ElementState::Released);
+        assert!(!input.is_key_pressed(VirtualKeyCode::A));
+        assert!(input.is_key_just_released(VirtualKeyCode::A));
+        input.end_frame();
+        assert!(!input.is_key_just_released(VirtualKeyCode::A));
+    }
+
+    #[test]
+    fn end_frame_clears_mouse_motion() {
+        let mut input = InputState::default();
+        for _ in 0..2 {
+            input.update(&DeviceEvent::MouseMotion { delta: (3.0, -2.0) });
+        }
+        assert_eq!(input.mouse_delta(), (6.0, -4.0));
+        input.end_frame();
+        assert_eq!(input.mouse_delta(), (0.0, 0.0));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn tracks_the_cursor_and_focus() {
+        let device_id = unsafe { DeviceId::dummy() };
+        let mut input = InputState::default();
+        input.update_window(&WindowEvent::CursorMoved {
+            device_id,
+            position: PhysicalPosition::new(12.0, 34.0),
+            modifiers: Default::default(),
+        });
+        assert_eq!(input.cursor_position, Some((12.0, 34.0)));
+        input.update_window(&WindowEvent::CursorLeft { device_id });
+        assert_eq!(input.cursor_position, None);
+
+        // Keys held when focus goes are released, their release is never reported
+        input.send_key(VirtualKeyCode::W, ElementState::Pressed);
+        input.update_window(&WindowEvent::Focused(false));
+        assert!(!input.is_key_pressed(VirtualKeyCode::W));
+        assert!(input.is_key_just_released(VirtualKeyCode::W));
+    }
 }
@@ -0,0 +1,529 @@
+use super::{GamepadAxis, GamepadButton, InputState};
+use crate::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+use winit::event::{MouseButton, VirtualKeyCode};
+
+/// Overrides for the default bindings, read from the `[input]` table. Each action takes a list
+/// of bindings and each binding is one input name or a chord such as `"LControl+Q"`. Keys use
+/// winit's `VirtualKeyCode` names, mouse buttons are `MouseLeft`, `MouseRight` and
+/// `MouseMiddle` and gamepad buttons are prefixed with `Gamepad`, e.g. `GamepadSouth`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    pub actions: HashMap<String, Vec<String>>,
+    pub axes: HashMap<String, AxisConfig>,
+}
+
+/// An axis is the positive bindings minus the negative ones plus any gamepad axes, clamped to
+/// [-1, 1]. Gamepad axes prefixed with `-` count against the axis.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AxisConfig {
+    pub positive: Vec<String>,
+    pub negative: Vec<String>,
+    pub gamepad: Vec<String>,
+}
+
+const DEFAULT_ACTIONS: &[(&str, &[&str])] = &[
+    ("quit", &["Escape"]),
+    ("look", &["MouseRight"]),
+    ("sprint", &["LShift", "GamepadLeftThumb"]),
+    ("pause", &["P"]),
+    ("time_faster", &["RBracket"]),
+    ("time_slower", &["LBracket"]),
+];
+
+// (name, positive, negative, gamepad)
+type AxisDefault = (
+    &'static str,
+    &'static [&'static str],
+    &'static [&'static str],
+    &'static [&'static str],
+);
+
+const DEFAULT_AXES: &[AxisDefault] = &[
+    ("move_forward", &["W"], &["S"], &["LeftStickY"]),
+    ("move_right", &["D"], &["A"], &["LeftStickX"]),
+    (
+        "move_up",
+        &["Space"],
+        &["C"],
+        &["RightTrigger", "-LeftTrigger"],
+    ),
+    ("look_right", &[], &[], &["RightStickX"]),
+    ("look_up", &[], &[], &["RightStickY"]),
+    ("zoom", &["R"], &["F"], &[]),
+    ("time_of_day", &["Period"], &["Comma"], &[]),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+impl Button {
+    fn parse(name: &str) -> Result<Button> {
+        if let Some(key) = parse_key(name) {
+            return Ok(Button::Key(key));
+        }
+        let lower = name.to_ascii_lowercase();
+        let button = match lower.as_str() {
+            "mouseleft" => Some(Button::Mouse(MouseButton::Left)),
+            "mouseright" => Some(Button::Mouse(MouseButton::Right)),
+            "mousemiddle" => Some(Button::Mouse(MouseButton::Middle)),
+            _ => lower
+                .strip_prefix("gamepad")
+                .and_then(parse_gamepad_button)
+                .map(Button::Gamepad),
+        };
+        if let Some(button) = button {
+            return Ok(button);
+        }
+        Err(format!("Unknown key name '{}'", name).into())
+    }
+
+    fn held(self, input: &InputState) -> bool {
+        match self {
+            Button::Key(code) => input.is_key_pressed(code),
+            Button::Mouse(button) => input.is_button_pressed(button),
+            Button::Gamepad(button) => input.is_gamepad_pressed(button),
+        }
+    }
+
+    fn just_pressed(self, input: &InputState) -> bool {
+        match self {
+            Button::Key(code) => input.is_key_just_pressed(code),
+            Button::Mouse(button) => input.is_button_just_pressed(button),
+            Button::Gamepad(button) => input.is_gamepad_just_pressed(button),
+        }
+    }
+
+    fn just_released(self, input: &InputState) -> bool {
+        match self {
+            Button::Key(code) => input.is_key_just_released(code),
+            Button::Mouse(button) => input.is_button_just_released(button),
+            Button::Gamepad(button) => input.is_gamepad_just_released(button),
+        }
+    }
+}
+
+/// Buttons that must all be held together.
+#[derive(Clone, Debug, PartialEq)]
+struct Chord(Vec<Button>);
+
+impl Chord {
+    fn parse(binding: &str) -> Result<Chord> {
+        let buttons = binding
+            .split('+')
+            .map(|name| Button::parse(name.trim()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Chord(buttons))
+    }
+
+    fn held(&self, input: &InputState) -> bool {
+        self.0.iter().all(|button| button.held(input))
+    }
+
+    // Completing the chord activates it, whichever button went down last
+    fn just_pressed(&self, input: &InputState) -> bool {
+        self.held(input) && self.0.iter().any(|button| button.just_pressed(input))
+    }
+
+    fn just_released(&self, input: &InputState) -> bool {
+        self.0.iter().any(|button| button.just_released(input))
+            && self
+                .0
+                .iter()
+                .all(|button| button.held(input) || button.just_released(input))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct AxisBinding {
+    positive: Vec<Chord>,
+    negative: Vec<Chord>,
+    gamepad: Vec<(GamepadAxis, f32)>,
+}
+
+/// Named actions and axes mapped onto raw input.
+#[derive(Clone, Debug)]
+pub struct Bindings {
+    actions: HashMap<String, Vec<Chord>>,
+    axes: HashMap<String, AxisBinding>,
+}
+
+impl Bindings {
+    /// The default bindings with any overrides from `config` applied.
+    pub fn new(config: &InputConfig) -> Result<Bindings> {
+        let mut bindings = Bindings::default();
+
+        for (name, chords) in &config.actions {
+            let action = bindings
+                .actions
+                .get_mut(name)
+                .ok_or_else(|| format!("Unknown input action '{}'", name))?;
+            *action = parse_chords(chords).map_err(|e| format!("Action '{}': {}", name, e))?;
+        }
+
+        for (name, axis_config) in &config.axes {
+            let axis = bindings
+                .axes
+                .get_mut(name)
+                .ok_or_else(|| format!("Unknown input axis '{}'", name))?;
+            *axis = parse_axis(axis_config).map_err(|e| format!("Axis '{}': {}", name, e))?;
+        }
+
+        Ok(bindings)
+    }
+
+    fn chords(&self, action: &str) -> &[Chord] {
+        match self.actions.get(action) {
+            Some(chords) => chords,
+            None => panic!("No input action named '{}'", action),
+        }
+    }
+
+    pub fn is_held(&self, input: &InputState, action: &str) -> bool {
+        self.chords(action).iter().any(|chord| chord.held(input))
+    }
+
+    pub fn is_just_pressed(&self, input: &InputState, action: &str) -> bool {
+        self.chords(action)
+            .iter()
+            .any(|chord| chord.just_pressed(input))
+    }
+
+    pub fn is_just_released(&self, input: &InputState, action: &str) -> bool {
+        self.chords(action)
+            .iter()
+            .any(|chord| chord.just_released(input))
+    }
+
+    pub fn axis(&self, input: &InputState, axis: &str) -> f32 {
+        let binding = match self.axes.get(axis) {
+            Some(binding) => binding,
+            None => panic!("No input axis named '{}'", axis),
+        };
+
+        let mut value = 0.0;
+        if binding.positive.iter().any(|chord| chord.held(input)) {
+            value += 1.0;
+        }
+        if binding.negative.iter().any(|chord| chord.held(input)) {
+            value -= 1.0;
+        }
+        for &(gamepad_axis, sign) in &binding.gamepad {
+            value += input.gamepad_axis(gamepad_axis) * sign;
+        }
+        value.clamp(-1.0, 1.0)
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let parse = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| Chord::parse(name).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let actions = DEFAULT_ACTIONS
+            .iter()
+            .map(|(name, chords)| (name.to_string(), parse(chords)))
+            .collect();
+        let axes = DEFAULT_AXES
+            .iter()
+            .map(|(name, positive, negative, gamepad)| {
+                let gamepad = gamepad
+                    .iter()
+                    .map(|name| parse_gamepad_axis(name).unwrap())
+                    .collect();
+                let binding = AxisBinding {
+                    positive: parse(positive),
+                    negative: parse(negative),
+                    gamepad,
+                };
+                (name.to_string(), binding)
+            })
+            .collect();
+
+        Bindings { actions, axes }
+    }
+}
+
+fn parse_chords(bindings: &[String]) -> Result<Vec<Chord>> {
+    bindings
+        .iter()
+        .map(|binding| Chord::parse(binding))
+        .collect()
+}
+
+fn parse_axis(config: &AxisConfig) -> Result<AxisBinding> {
+    Ok(AxisBinding {
+        positive: parse_chords(&config.positive)?,
+        negative: parse_chords(&config.negative)?,
+        gamepad: config
+            .gamepad
+            .iter()
+            .map(|name| parse_gamepad_axis(name))
+            .collect::<Result<_>>()?,
+    })
+}
+
+fn parse_gamepad_axis(name: &str) -> Result<(GamepadAxis, f32)> {
+    let (name, sign) = match name.strip_prefix('-') {
+        Some(name) => (name, -1.0),
+        None => (name, 1.0),
+    };
+    let axis = match name.to_ascii_lowercase().as_str() {
+        "leftstickx" => GamepadAxis::LeftStickX,
+        "leftsticky" => GamepadAxis::LeftStickY,
+        "rightstickx" => GamepadAxis::RightStickX,
+        "rightsticky" => GamepadAxis::RightStickY,
+        "lefttrigger" => GamepadAxis::LeftTrigger,
+        "righttrigger" => GamepadAxis::RightTrigger,
+        _ => return Err(format!("Unknown gamepad axis '{}'", name).into()),
+    };
+    Ok((axis, sign))
+}
+
+fn parse_gamepad_button(name: &str) -> Option<GamepadButton> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "south" => GamepadButton::South,
+        "east" => GamepadButton::East,
+        "north" => GamepadButton::North,
+        "west" => GamepadButton::West,
+        "leftbumper" => GamepadButton::LeftBumper,
+        "rightbumper" => GamepadButton::RightBumper,
+        "select" => GamepadButton::Select,
+        "start" => GamepadButton::Start,
+        "leftthumb" => GamepadButton::LeftThumb,
+        "rightthumb" => GamepadButton::RightThumb,
+        "dpadup" => GamepadButton::DPadUp,
+        "dpaddown" => GamepadButton::DPadDown,
+        "dpadleft" => GamepadButton::DPadLeft,
+        "dpadright" => GamepadButton::DPadRight,
+        _ => return None,
+    })
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+            $(
+                if name.eq_ignore_ascii_case(stringify!($key)) {
+                    return Some(VirtualKeyCode::$key);
+                }
+            )*
+            None
+        }
+    };
+}
+
+key_names!(
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Key0,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    Snapshot,
+    Scroll,
+    Pause,
+    Insert,
+    Home,
+    Delete,
+    End,
+    PageDown,
+    PageUp,
+    Left,
+    Up,
+    Right,
+    Down,
+    Back,
+    Return,
+    Space,
+    Compose,
+    Caret,
+    Numlock,
+    Numpad0,
+    Numpad1,
+    Numpad2,
+    Numpad3,
+    Numpad4,
+    Numpad5,
+    Numpad6,
+    Numpad7,
+    Numpad8,
+    Numpad9,
+    Add,
+    Apostrophe,
+    Apps,
+    At,
+    Backslash,
+    Capital,
+    Colon,
+    Comma,
+    Decimal,
+    Divide,
+    Equals,
+    Grave,
+    LAlt,
+    LBracket,
+    LControl,
+    LShift,
+    LWin,
+    Minus,
+    Multiply,
+    NumpadComma,
+    NumpadEnter,
+    NumpadEquals,
+    Period,
+    RAlt,
+    RBracket,
+    RControl,
+    RShift,
+    RWin,
+    Semicolon,
+    Slash,
+    Subtract,
+    Tab,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::ElementState;
+
+    fn bindings(toml: &str) -> Result<Bindings> {
+        let config: InputConfig = toml::from_str(toml).unwrap();
+        Bindings::new(&config)
+    }
+
+    #[test]
+    fn parses_chords() {
+        let chord = Chord::parse("LControl+Q").unwrap();
+        let keys = vec![
+            Button::Key(VirtualKeyCode::LControl),
+            Button::Key(VirtualKeyCode::Q),
+        ];
+        assert_eq!(chord, Chord(keys));
+
+        let mut input = InputState::default();
+        input.send_key(VirtualKeyCode::Q, ElementState::Pressed);
+        assert!(!chord.held(&input));
+        input.end_frame();
+        input.send_key(VirtualKeyCode::LControl, ElementState::Pressed);
+        assert!(chord.held(&input));
+        assert!(chord.just_pressed(&input));
+    }
+
+    #[test]
+    fn parses_negated_gamepad_axes() {
+        assert_eq!(
+            parse_gamepad_axis("-LeftTrigger").unwrap(),
+            (GamepadAxis::LeftTrigger, -1.0)
+        );
+        assert_eq!(
+            parse_gamepad_axis("rightsticky").unwrap(),
+            (GamepadAxis::RightStickY, 1.0)
+        );
+        assert!(parse_gamepad_axis("-LeftWheel").is_err());
+    }
+
+    #[test]
+    fn overrides_replace_defaults() {
+        let bindings =
+            bindings("[actions]\nquit = [\"Q\"]\n[axes.zoom]\npositive = [\"Up\"]").unwrap();
+        let mut input = InputState::default();
+        input.send_key(VirtualKeyCode::Escape, ElementState::Pressed);
+        input.send_key(VirtualKeyCode::R, ElementState::Pressed);
+        assert!(!bindings.is_held(&input, "quit"));
+        assert_eq!(bindings.axis(&input, "zoom"), 0.0);
+
+        input.send_key(VirtualKeyCode::Q, ElementState::Pressed);
+        input.send_key(VirtualKeyCode::Up, ElementState::Pressed);
+        assert!(bindings.is_held(&input, "quit"));
+        assert_eq!(bindings.axis(&input, "zoom"), 1.0);
+        // Untouched bindings keep their defaults
+        assert!(!bindings.is_held(&input, "pause"));
+        input.send_key(VirtualKeyCode::P, ElementState::Pressed);
+        assert!(bindings.is_held(&input, "pause"));
+    }
+
+    #[test]
+    fn names_unknown_inputs() {
+        let error = bindings("[actions]\nquit = [\"LControl+Nope\"]")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Action 'quit': Unknown key name 'Nope'");
+        let error = bindings("[actions]\njump = [\"Space\"]").err().unwrap();
+        assert_eq!(error.to_string(), "Unknown input action 'jump'");
+        let error = bindings("[axes.zoom]\ngamepad = [\"-Wheel\"]")
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Axis 'zoom': Unknown gamepad axis 'Wheel'"
+        );
+    }
+}
@@ -2,16 +2,24 @@ use log::{info, warn};
 use nalgebra as na;
 use serde::Deserialize;
 use simplelog as sl;
-use std::{error::Error, fs::File, io::prelude::*};
+use std::{env, error::Error, fs::File, io::prelude::*, path::PathBuf};
 use toml;
 use futures::executor::block_on;
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
 mod app;
+mod assets;
+mod biome;
+mod climate;
+mod clock;
+mod export;
 mod input;
 mod objects;
 mod renderer;
+mod scene;
+mod terrain;
+mod water;
 
 // pub use renderer::Renderer;
 use app::App;
@@ -21,6 +29,38 @@ use renderer::Renderer;
 pub struct Config {
     window: WindowConfig,
     application: AppConfig,
+    #[serde(default)]
+    terrain: terrain::TerrainConfig,
+    #[serde(default)]
+    erosion: terrain::ErosionConfig,
+    #[serde(default)]
+    materials: terrain::SplatConfig,
+    #[serde(default)]
+    water: water::WaterConfig,
+    #[serde(default)]
+    climate: climate::ClimateConfig,
+    #[serde(default)]
+    biomes: biome::BiomeConfig,
+    #[serde(default)]
+    chunks: terrain::ChunkConfig,
+    #[serde(default)]
+    camera: objects::CameraConfig,
+    #[serde(default)]
+    controls: objects::ControlsConfig,
+    #[serde(default)]
+    props: Vec<objects::PropConfig>,
+    #[serde(default)]
+    models: Vec<objects::ModelConfig>,
+    #[serde(default)]
+    input: input::InputConfig,
+    #[serde(default)]
+    renderer: renderer::RendererConfig,
+    #[serde(default)]
+    lighting: renderer::LightingConfig,
+    #[serde(default)]
+    clock: clock::ClockConfig,
+    #[serde(default)]
+    export: export::ExportConfig,
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,6 +79,27 @@ struct AppConfig {
 }
 
 fn main() -> Result<()> {
+    // `--render <image>` draws a single frame offscreen and exits, no window needed.
+    // `--export-heightmap <file>` writes the terrain's heightmap and exits.
+    // `--export-mesh <file>` writes the terrain, and its water, as a mesh and exits.
+    let mut args = env::args().skip(1);
+    let (render_path, export_path, mesh_path) = match (args.next(), args.next()) {
+        (None, _) => (None, None, None),
+        (Some(flag), Some(path)) if flag == "--render" => (Some(PathBuf::from(path)), None, None),
+        (Some(flag), Some(path)) if flag == "--export-heightmap" => {
+            (None, Some(PathBuf::from(path)), None)
+        }
+        (Some(flag), Some(path)) if flag == "--export-mesh" => {
+            (None, None, Some(PathBuf::from(path)))
+        }
+        _ => {
+            return Err("Usage: rock_and_water [--render <image.png|image.exr> | \
+                        --export-heightmap <terrain.png|r16|r32|asc|hgt> | \
+                        --export-mesh <terrain.obj|stl|gltf|glb>]"
+                .into())
+        }
+    };
+
     let mut file = File::open("app_settings.toml")?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
@@ -58,6 +119,16 @@ fn main() -> Result<()> {
     warn!("Logging working");
     info!("Config: {:#?}", config);
 
+    if let Some(path) = render_path {
+        return block_on(app::render_headless(config, &path));
+    }
+    if let Some(path) = export_path {
+        return app::export_heightmap(&config, &path);
+    }
+    if let Some(path) = mesh_path {
+        return app::export_mesh(&config, &path);
+    }
+
     let app = block_on(App::new(config))?;
     app.run();
 
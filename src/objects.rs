@@ -1,16 +1,69 @@
 use crate::na;
+use crate::renderer::Light;
+use std::any::Any;
+
+mod camera;
+mod camera_controller;
+mod chunked_terrain;
 mod cube;
+mod instanced;
 mod lamp;
 mod mesh;
+mod model;
+mod props;
+mod terrain;
+mod terrain_material;
+mod water;
 
-// pub use lamp::{Lamp, LampVertex};
+pub use camera::{Camera, CameraConfig};
+pub use camera_controller::{create_controller, CameraController, ControlsConfig};
+pub use chunked_terrain::ChunkedTerrain;
 pub use cube::Cube;
+pub use instanced::{Instance, InstancedMesh, PropVertex};
+pub use lamp::Lamp;
 pub use mesh::Mesh;
 pub use mesh::VertexAttribute;
+pub use model::{Model, ModelConfig};
+pub use props::{build_props, PropConfig};
+pub use terrain::Terrain;
+pub use terrain_material::TerrainMaterial;
+pub use water::Water;
+
+pub type Transform = na::Similarity3<f32>;
+
+/// Lets the scene hand back an object as its concrete type.
+pub trait AsAny: Any {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub trait Object: AsAny {
+    /// Pipeline the renderer binds before calling `render`, objects sharing one are drawn
+    /// together.
+    fn pipeline(&self) -> &wgpu::RenderPipeline;
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>);
+    /// Called once per simulation step of `dt` seconds, free to move the object through its
+    /// `transform`. A frame can run several steps or none.
+    fn update(&mut self, transform: &mut Transform, dt: f32);
+
+    /// Transparent objects are drawn after the opaque ones, furthest first.
+    fn is_transparent(&self) -> bool {
+        false
+    }
 
-type Transform = na::Similarity3<f32>;
+    /// Depth-only pipeline drawing the object into shadow maps, `None` for objects that
+    /// don't cast shadows. `render` issues the same draws for it as for `pipeline`.
+    fn shadow_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        None
+    }
 
-pub trait Object {
-    fn render<'a>(&'a self, render_pass: &'a mut wgpu::RenderPass);
-    fn update(&mut self);
+    /// A light the object casts, placed by the object's transform.
+    fn light(&self) -> Option<&Light> {
+        None
+    }
 }
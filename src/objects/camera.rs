@@ -1,3 +1,159 @@
-stuct Camera {
-	
-}
\ No newline at end of file
+use crate::na;
+use serde::Deserialize;
+
+// Perspective3 maps depth to OpenGL's [-1, 1], wgpu expects [0, 1]
+#[rustfmt::skip]
+fn opengl_to_wgpu_matrix() -> na::Matrix4<f32> {
+    na::Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, 0.5, 0.5,
+        0.0, 0.0, 0.0, 1.0,
+    )
+}
+
+// As above but with the near plane at 1 and the far plane at 0
+#[rustfmt::skip]
+fn opengl_to_reverse_z_matrix() -> na::Matrix4<f32> {
+    na::Matrix4::new(
+        1.0, 0.0,  0.0, 0.0,
+        0.0, 1.0,  0.0, 0.0,
+        0.0, 0.0, -0.5, 0.5,
+        0.0, 0.0,  0.0, 1.0,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CameraConfig {
+    /// Vertical field of view in degrees.
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    pub position: [f32; 3],
+    pub target: [f32; 3],
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            fovy: 60.0,
+            znear: 0.1,
+            zfar: 2000.0,
+            position: [-40.0, 60.0, -40.0],
+            target: [128.0, 0.0, 128.0],
+        }
+    }
+}
+
+pub struct Camera {
+    pub position: na::Point3<f32>,
+    /// Rotation from camera space, which looks down -z with +y up, to world space.
+    pub orientation: na::UnitQuaternion<f32>,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    pub aspect: f32,
+    /// Map the near plane to depth 1 and the far plane to 0, set by the renderer.
+    pub reverse_z: bool,
+}
+
+impl Camera {
+    pub fn new(config: &CameraConfig, aspect: f32) -> Camera {
+        let mut camera = Camera {
+            position: config.position.into(),
+            orientation: na::UnitQuaternion::identity(),
+            fovy: config.fovy.to_radians(),
+            znear: config.znear,
+            zfar: config.zfar,
+            aspect,
+            reverse_z: false,
+        };
+        camera.look_at(&config.target.into());
+        camera
+    }
+
+    pub fn look_at(&mut self, target: &na::Point3<f32>) {
+        let direction = target - self.position;
+        if direction.norm() > f32::EPSILON {
+            self.orientation = na::UnitQuaternion::face_towards(&-direction, &na::Vector3::y());
+        }
+    }
+
+    pub fn set_aspect(&mut self, width: u32, height: u32) {
+        if height > 0 {
+            self.aspect = width as f32 / height as f32;
+        }
+    }
+
+    pub fn forward(&self) -> na::Vector3<f32> {
+        self.orientation * -na::Vector3::z()
+    }
+
+    pub fn right(&self) -> na::Vector3<f32> {
+        self.orientation * na::Vector3::x()
+    }
+
+    pub fn up(&self) -> na::Vector3<f32> {
+        self.orientation * na::Vector3::y()
+    }
+
+    /// World to camera transform.
+    pub fn view(&self) -> na::Isometry3<f32> {
+        na::Isometry3::from_parts(self.position.coords.into(), self.orientation).inverse()
+    }
+
+    pub fn projection(&self) -> na::Perspective3<f32> {
+        na::Perspective3::new(self.aspect, self.fovy, self.znear, self.zfar)
+    }
+
+    pub fn view_projection(&self) -> na::Matrix4<f32> {
+        let depth = if self.reverse_z {
+            opengl_to_reverse_z_matrix()
+        } else {
+            opengl_to_wgpu_matrix()
+        };
+        depth * self.projection().as_matrix() * self.view().to_homogeneous()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Normalised device depth of a point straight ahead of the camera
+    fn depth(camera: &Camera, distance: f32) -> f32 {
+        let clip = camera.view_projection() * na::Vector4::new(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    }
+
+    fn camera() -> Camera {
+        let config = CameraConfig {
+            znear: 0.5,
+            zfar: 500.0,
+            position: [0.0, 0.0, 0.0],
+            target: [0.0, 0.0, -1.0],
+            ..CameraConfig::default()
+        };
+        Camera::new(&config, 1.5)
+    }
+
+    #[test]
+    fn projects_depth_to_zero_one() {
+        let camera = camera();
+        assert!(depth(&camera, 0.5).abs() < 1e-5);
+        assert!((depth(&camera, 500.0) - 1.0).abs() < 1e-5);
+        assert!(depth(&camera, 10.0) > depth(&camera, 5.0));
+    }
+
+    #[test]
+    fn reverse_z_projects_near_to_one() {
+        let camera = Camera {
+            reverse_z: true,
+            ..camera()
+        };
+        assert!((depth(&camera, 0.5) - 1.0).abs() < 1e-5);
+        assert!(depth(&camera, 500.0).abs() < 1e-5);
+        assert!(depth(&camera, 10.0) < depth(&camera, 5.0));
+    }
+}
@@ -0,0 +1,341 @@
+use super::Camera;
+use crate::input::InputState;
+use crate::na;
+use serde::Deserialize;
+use std::f32::consts::FRAC_PI_2;
+
+// Keeps the view from flipping over at the poles
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+// Radians per second with the right stick fully over
+const STICK_LOOK_SPEED: f32 = 2.5;
+// Fraction of the orbit distance zoomed per line of scroll
+const SCROLL_ZOOM: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlMode {
+    Fly,
+    Orbit,
+    Walk,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ControlsConfig {
+    pub mode: ControlMode,
+    /// World units per second.
+    pub move_speed: f32,
+    pub sprint_multiplier: f32,
+    /// Radians per pixel of mouse motion.
+    pub mouse_sensitivity: f32,
+    /// Fraction of the orbit distance zoomed per second.
+    pub zoom_speed: f32,
+    pub walk_speed: f32,
+    pub eye_height: f32,
+}
+
+impl Default for ControlsConfig {
+    fn default() -> Self {
+        ControlsConfig {
+            mode: ControlMode::Fly,
+            move_speed: 20.0,
+            sprint_multiplier: 4.0,
+            mouse_sensitivity: 0.003,
+            zoom_speed: 1.5,
+            walk_speed: 5.0,
+            eye_height: 1.8,
+        }
+    }
+}
+
+pub trait CameraController {
+    /// Moves `camera` from this frame's input. `dt` is the frame time in seconds.
+    fn update(&mut self, camera: &mut Camera, input: &InputState, dt: f32);
+}
+
+/// Builds the controller selected in `config`, starting from the camera's current pose.
+/// `ground` gives the terrain height at a world `(x, z)` for walk mode.
+pub fn create_controller(
+    config: &ControlsConfig,
+    camera: &Camera,
+    ground: Box<dyn Fn(f32, f32) -> f32>,
+) -> Box<dyn CameraController> {
+    match config.mode {
+        ControlMode::Fly => Box::new(FlyController::new(config, camera)),
+        ControlMode::Orbit => Box::new(OrbitController::new(config, camera)),
+        ControlMode::Walk => Box::new(WalkController::new(config, camera, ground)),
+    }
+}
+
+// Yaw around +y, with zero looking down -z, and pitch up from the horizon
+#[derive(Clone, Copy, Debug)]
+struct Look {
+    yaw: f32,
+    pitch: f32,
+}
+
+impl Look {
+    fn from_camera(camera: &Camera) -> Look {
+        let forward = camera.forward();
+        Look {
+            yaw: (-forward.x).atan2(-forward.z),
+            pitch: forward.y.clamp(-1.0, 1.0).asin(),
+        }
+    }
+
+    // The mouse only steers while the cursor is grabbed, the look axes always do
+    fn turn(&mut self, input: &InputState, sensitivity: f32, dt: f32) {
+        let (mut dx, mut dy) = if input.is_cursor_grabbed() {
+            let (dx, dy) = input.mouse_delta();
+            (dx * sensitivity, dy * sensitivity)
+        } else {
+            (0.0, 0.0)
+        };
+        dx += input.axis("look_right") * STICK_LOOK_SPEED * dt;
+        dy -= input.axis("look_up") * STICK_LOOK_SPEED * dt;
+
+        self.yaw -= dx;
+        self.pitch = (self.pitch - dy).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    fn orientation(&self) -> na::UnitQuaternion<f32> {
+        na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), self.yaw)
+            * na::UnitQuaternion::from_axis_angle(&na::Vector3::x_axis(), self.pitch)
+    }
+
+    // Forward and right along the ground plane
+    fn flat_axes(&self) -> (na::Vector3<f32>, na::Vector3<f32>) {
+        let forward = na::Vector3::new(-self.yaw.sin(), 0.0, -self.yaw.cos());
+        let right = na::Vector3::new(self.yaw.cos(), 0.0, -self.yaw.sin());
+        (forward, right)
+    }
+}
+
+// Forward and right amounts from the movement axes
+fn move_axes(input: &InputState) -> (f32, f32) {
+    (input.axis("move_forward"), input.axis("move_right"))
+}
+
+// Full speed along a key direction, partial speed for a partly pushed stick
+fn clamp_length(direction: na::Vector3<f32>) -> na::Vector3<f32> {
+    let length = direction.norm();
+    if length > 1.0 {
+        direction / length
+    } else {
+        direction
+    }
+}
+
+fn speed(input: &InputState, base: f32, sprint_multiplier: f32) -> f32 {
+    if input.is_action_pressed("sprint") {
+        base * sprint_multiplier
+    } else {
+        base
+    }
+}
+
+/// Free flight: WASD or the left stick moves along the view direction, Space and C or the
+/// triggers move up and down, the mouse or right stick looks around and left shift sprints.
+pub struct FlyController {
+    look: Look,
+    move_speed: f32,
+    sprint_multiplier: f32,
+    sensitivity: f32,
+}
+
+impl FlyController {
+    pub fn new(config: &ControlsConfig, camera: &Camera) -> FlyController {
+        FlyController {
+            look: Look::from_camera(camera),
+            move_speed: config.move_speed,
+            sprint_multiplier: config.sprint_multiplier,
+            sensitivity: config.mouse_sensitivity,
+        }
+    }
+}
+
+impl CameraController for FlyController {
+    fn update(&mut self, camera: &mut Camera, input: &InputState, dt: f32) {
+        self.look.turn(input, self.sensitivity, dt);
+        camera.orientation = self.look.orientation();
+
+        let (ahead, across) = move_axes(input);
+        let direction = camera.forward() * ahead
+            + camera.right() * across
+            + na::Vector3::y() * input.axis("move_up");
+        let speed = speed(input, self.move_speed, self.sprint_multiplier);
+        camera.position += clamp_length(direction) * speed * dt;
+    }
+}
+
+/// Orbits a target point: the mouse or right stick swings around it, R and F or the scroll wheel
+/// zoom in and out and WASD or the left stick pan the target across the ground plane.
+pub struct OrbitController {
+    target: na::Point3<f32>,
+    distance: f32,
+    look: Look,
+    move_speed: f32,
+    sprint_multiplier: f32,
+    sensitivity: f32,
+    zoom_speed: f32,
+}
+
+impl OrbitController {
+    pub fn new(config: &ControlsConfig, camera: &Camera) -> OrbitController {
+        // Orbit whatever point the camera is looking at on the ground plane
+        let forward = camera.forward();
+        let distance = if forward.y < -0.01 {
+            (camera.position.y / -forward.y).max(1.0)
+        } else {
+            50.0
+        };
+
+        OrbitController {
+            target: camera.position + forward * distance,
+            distance,
+            look: Look::from_camera(camera),
+            move_speed: config.move_speed,
+            sprint_multiplier: config.sprint_multiplier,
+            sensitivity: config.mouse_sensitivity,
+            zoom_speed: config.zoom_speed,
+        }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn update(&mut self, camera: &mut Camera, input: &InputState, dt: f32) {
+        self.look.turn(input, self.sensitivity, dt);
+
+        let zoom = input.axis("zoom") * self.zoom_speed * dt + input.scroll_delta() * SCROLL_ZOOM;
+        self.distance = (self.distance * (1.0 - zoom.min(0.9))).max(1.0);
+
+        let (forward, right) = self.look.flat_axes();
+        let (ahead, across) = move_axes(input);
+        let pan = forward * ahead + right * across;
+        // Pan faster when zoomed out so the target moves at a steady rate on screen
+        let speed = speed(input, self.move_speed, self.sprint_multiplier);
+        self.target += clamp_length(pan) * speed * dt * (self.distance / 50.0).max(0.2);
+
+        camera.orientation = self.look.orientation();
+        camera.position = self.target - camera.forward() * self.distance;
+    }
+}
+
+/// First person on foot: WASD or the left stick walks across the terrain with the eye kept
+/// `eye_height` above the ground.
+pub struct WalkController {
+    look: Look,
+    walk_speed: f32,
+    sprint_multiplier: f32,
+    sensitivity: f32,
+    eye_height: f32,
+    ground: Box<dyn Fn(f32, f32) -> f32>,
+}
+
+impl WalkController {
+    pub fn new(
+        config: &ControlsConfig,
+        camera: &Camera,
+        ground: Box<dyn Fn(f32, f32) -> f32>,
+    ) -> WalkController {
+        WalkController {
+            look: Look::from_camera(camera),
+            walk_speed: config.walk_speed,
+            sprint_multiplier: config.sprint_multiplier,
+            sensitivity: config.mouse_sensitivity,
+            eye_height: config.eye_height,
+            ground,
+        }
+    }
+}
+
+impl CameraController for WalkController {
+    fn update(&mut self, camera: &mut Camera, input: &InputState, dt: f32) {
+        self.look.turn(input, self.sensitivity, dt);
+        camera.orientation = self.look.orientation();
+
+        let (forward, right) = self.look.flat_axes();
+        let (ahead, across) = move_axes(input);
+        let direction = forward * ahead + right * across;
+        let speed = speed(input, self.walk_speed, self.sprint_multiplier);
+        camera.position += clamp_length(direction) * speed * dt;
+
+        camera.position.y = (self.ground)(camera.position.x, camera.position.z) + self.eye_height;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Bindings, InputConfig};
+    use crate::objects::CameraConfig;
+    use winit::event::{ElementState, VirtualKeyCode};
+
+    fn camera(position: [f32; 3], target: [f32; 3]) -> Camera {
+        let config = CameraConfig {
+            position,
+            target,
+            ..CameraConfig::default()
+        };
+        Camera::new(&config, 1.0)
+    }
+
+    // The default bindings with the look axis on the arrow keys
+    fn input() -> InputState {
+        let config: InputConfig =
+            toml::from_str("[axes.look_up]\npositive = [\"Up\"]\nnegative = [\"Down\"]").unwrap();
+        InputState::new(Bindings::new(&config).unwrap())
+    }
+
+    #[test]
+    fn orbit_zoom_stops_short_of_the_target() {
+        let config = ControlsConfig::default();
+        let mut camera = camera([0.0, 10.0, 0.0], [0.0, 0.0, -10.0]);
+        let mut controller = OrbitController::new(&config, &camera);
+        let target = na::Point3::new(0.0, 0.0, -10.0);
+        assert!((controller.target - target).norm() < 1e-4);
+
+        let mut input = input();
+        input.send_key(VirtualKeyCode::R, ElementState::Pressed);
+        // A long frame zooms at most 90% of the way in
+        controller.update(&mut camera, &input, 1.0);
+        assert!((na::distance(&camera.position, &target) - 200.0_f32.sqrt() * 0.1).abs() < 1e-4);
+        controller.update(&mut camera, &input, 1.0);
+        assert!((na::distance(&camera.position, &target) - 1.0).abs() < 1e-4);
+        assert!((camera.forward() - (target - camera.position)).norm() < 1e-4);
+    }
+
+    #[test]
+    fn pitch_stops_short_of_the_poles() {
+        let config = ControlsConfig::default();
+        let mut camera = camera([0.0, 0.0, 0.0], [0.0, 0.0, -1.0]);
+        let mut controller = FlyController::new(&config, &camera);
+
+        let mut input = input();
+        input.send_key(VirtualKeyCode::Up, ElementState::Pressed);
+        controller.update(&mut camera, &input, 10.0);
+        assert!((camera.forward().y - MAX_PITCH.sin()).abs() < 1e-5);
+        assert!(camera.up().y > 0.0);
+
+        input.send_key(VirtualKeyCode::Up, ElementState::Released);
+        input.send_key(VirtualKeyCode::Down, ElementState::Pressed);
+        controller.update(&mut camera, &input, 10.0);
+        assert!((camera.forward().y + MAX_PITCH.sin()).abs() < 1e-5);
+        assert!(camera.up().y > 0.0);
+        assert!(camera.position.coords.norm() < 1e-5);
+    }
+
+    #[test]
+    fn walk_keeps_eye_height_over_the_ground() {
+        let config = ControlsConfig::default();
+        let mut camera = camera([0.0, 10.0, 0.0], [10.0, 10.0, 0.0]);
+        let ground = Box::new(|x: f32, _z: f32| 0.1 * x + 2.0);
+        let mut controller = WalkController::new(&config, &camera, ground);
+
+        let mut input = input();
+        input.send_key(VirtualKeyCode::W, ElementState::Pressed);
+        controller.update(&mut camera, &input, 1.0);
+        let expected = na::Point3::new(config.walk_speed, 0.1 * config.walk_speed + 2.0, 0.0);
+        assert!((camera.position - expected - na::Vector3::y() * config.eye_height).norm() < 1e-4);
+    }
+}
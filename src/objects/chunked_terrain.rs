@@ -0,0 +1,141 @@
+use super::{Object, TerrainMaterial, Transform, VertexAttribute};
+use crate::na;
+use crate::terrain::{
+    ChunkConfig, ChunkCoord, ChunkManager, SplatConfig, TerrainConfig, TerrainGenerator,
+    TerrainVertex,
+};
+use crate::{renderer::PipelineOptions, Renderer, Result};
+use std::{collections::HashMap, path::Path};
+
+struct LodBuffers {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+struct GpuChunk {
+    lods: Vec<LodBuffers>,
+    lod: usize,
+}
+
+/// Streams terrain chunks around a focus point and draws each at a level of detail picked from
+/// its distance to that point.
+pub struct ChunkedTerrain {
+    manager: ChunkManager,
+    pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+    material: TerrainMaterial,
+    chunks: HashMap<ChunkCoord, GpuChunk>,
+    lod_distance: f32,
+}
+
+impl ChunkedTerrain {
+    pub fn new(
+        renderer: &Renderer,
+        terrain_config: &TerrainConfig,
+        chunk_config: ChunkConfig,
+        materials: &SplatConfig,
+    ) -> Result<ChunkedTerrain> {
+        let vert_path = Path::new("./resources/shaders/terrain.vert");
+        let frag_path = Path::new("./resources/shaders/terrain.frag");
+
+        chunk_config.validate()?;
+
+        let material = TerrainMaterial::new(renderer, materials)?;
+        let pipeline = renderer.create_pipeline(
+            vert_path,
+            frag_path,
+            &[TerrainVertex::description()],
+            &material.layouts(),
+            &PipelineOptions::default(),
+        )?;
+        let shadow_pipeline = renderer.create_shadow_pipeline(
+            Path::new("./resources/shaders/shadow.vert"),
+            &[TerrainVertex::description()],
+            &material.layouts(),
+        )?;
+
+        let lod_distance = chunk_config.lod_distance;
+        let generator = TerrainGenerator::new(terrain_config)?;
+        let manager = ChunkManager::new(
+            generator,
+            chunk_config,
+            materials.clone(),
+            terrain_config.cell_size,
+        );
+
+        Ok(ChunkedTerrain {
+            manager,
+            pipeline,
+            shadow_pipeline,
+            material,
+            chunks: HashMap::new(),
+            lod_distance,
+        })
+    }
+
+    /// Whether chunks queued by `update_focus` are still being generated.
+    pub fn is_loading(&self) -> bool {
+        !self.manager.is_idle()
+    }
+
+    /// Uploads finished chunks, drops evicted ones and re-picks every chunk's LOD. Never waits
+    /// on chunk generation.
+    pub fn update_focus(&mut self, renderer: &Renderer, focus: &na::Point3<f32>) {
+        let update = self.manager.update(focus.x, focus.z);
+
+        for coord in update.evicted {
+            self.chunks.remove(&coord);
+        }
+
+        for chunk in update.loaded {
+            let lods = chunk
+                .lods
+                .iter()
+                .map(|mesh| LodBuffers {
+                    vertex_buffer: renderer.device.create_buffer_with_data(
+                        bytemuck::cast_slice(&mesh.vertices),
+                        wgpu::BufferUsage::VERTEX,
+                    ),
+                    index_buffer: renderer.device.create_buffer_with_data(
+                        bytemuck::cast_slice(&mesh.indices),
+                        wgpu::BufferUsage::INDEX,
+                    ),
+                    num_indices: mesh.indices.len() as u32,
+                })
+                .collect();
+            self.chunks.insert(chunk.coord, GpuChunk { lods, lod: 0 });
+        }
+
+        let size = self.manager.chunk_world_size();
+        for (coord, chunk) in self.chunks.iter_mut() {
+            let center =
+                na::Point2::new((coord.x as f32 + 0.5) * size, (coord.z as f32 + 0.5) * size);
+            let distance = na::distance(&center, &na::Point2::new(focus.x, focus.z));
+            let lod = (distance / self.lod_distance) as usize;
+            chunk.lod = lod.min(chunk.lods.len() - 1);
+        }
+    }
+}
+
+impl Object for ChunkedTerrain {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn shadow_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        Some(&self.shadow_pipeline)
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.material.bind(render_pass);
+        for chunk in self.chunks.values() {
+            let lod = &chunk.lods[chunk.lod];
+            render_pass.set_vertex_buffer(0, &lod.vertex_buffer, 0, 0);
+            render_pass.set_index_buffer(&lod.index_buffer, 0, 0);
+            render_pass.draw_indexed(0..lod.num_indices, 0, 0..1);
+        }
+    }
+
+    fn update(&mut self, _transform: &mut Transform, _dt: f32) {}
+}
@@ -1,13 +1,11 @@
 #![warn(clippy::all)]
 use super::{Mesh, Object, Transform, VertexAttribute};
-use crate::na;
-use crate::{Renderer, Result};
+use crate::{renderer::PipelineOptions, Renderer, Result};
 use std::{mem, path::Path};
 
 pub struct Cube {
     mesh: Mesh<CubeVertex>,
     pub pipeline: wgpu::RenderPipeline,
-    transform: Transform,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
@@ -20,12 +18,13 @@ impl Cube {
 
         let mesh = create_cube_mesh();
 
-        let position = na::Vector3::<f32>::zeros();
-        let scale = 1.0;
-        let orientation = na::UnitQuaternion::identity();
-
-        let transform = Transform::from_parts(position.into(), orientation, scale);
-        let pipeline = renderer.create_pipeline(vert_path, frag_path, CubeVertex::description())?;
+        let pipeline = renderer.create_pipeline(
+            vert_path,
+            frag_path,
+            &[CubeVertex::description()],
+            &[],
+            &PipelineOptions::default(),
+        )?;
 
         let vertex_buffer = renderer.device.create_buffer_with_data(
             bytemuck::cast_slice(&mesh.vertices),
@@ -42,7 +41,6 @@ impl Cube {
 
         Ok(Cube {
             mesh,
-            transform,
             pipeline,
             vertex_buffer,
             index_buffer,
@@ -51,6 +49,19 @@ impl Cube {
     }
 }
 
+impl Object for Cube {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+        render_pass.set_index_buffer(&self.index_buffer, 0, 0);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+
+    fn update(&mut self, _transform: &mut Transform, _dt: f32) {}
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
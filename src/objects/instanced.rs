@@ -0,0 +1,193 @@
+use super::{Mesh, Object, Transform, VertexAttribute};
+use crate::{renderer::PipelineOptions, Renderer, Result};
+use std::{mem, path::Path};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct PropVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub color: [f32; 3],
+}
+
+unsafe impl bytemuck::Pod for PropVertex {}
+unsafe impl bytemuck::Zeroable for PropVertex {}
+
+impl VertexAttribute for PropVertex {
+    fn description<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<PropVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float3,
+                },
+            ],
+        }
+    }
+}
+
+/// Per-instance data, stepped once per instance rather than per vertex.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Instance {
+    pub model: [[f32; 4]; 4],
+    pub tint: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for Instance {}
+unsafe impl bytemuck::Zeroable for Instance {}
+
+impl Instance {
+    pub fn new(transform: &Transform, tint: [f32; 3]) -> Instance {
+        Instance {
+            model: transform.to_homogeneous().into(),
+            tint: [tint[0], tint[1], tint[2], 1.0],
+        }
+    }
+}
+
+impl VertexAttribute for Instance {
+    fn description<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<Instance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            // The model matrix goes over as four column attributes
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+/// One mesh drawn many times in a single call, each copy placed and tinted by an `Instance`.
+pub struct InstancedMesh {
+    pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    instance_buffer: Option<wgpu::Buffer>,
+    num_indices: u32,
+    num_instances: u32,
+}
+
+impl InstancedMesh {
+    pub fn new(
+        renderer: &Renderer,
+        mesh: &Mesh<PropVertex>,
+        instances: &[Instance],
+    ) -> Result<InstancedMesh> {
+        let vert_path = Path::new("./resources/shaders/instanced.vert");
+        let frag_path = Path::new("./resources/shaders/instanced.frag");
+
+        let pipeline = renderer.create_pipeline(
+            vert_path,
+            frag_path,
+            &[PropVertex::description(), Instance::description()],
+            &[],
+            &PipelineOptions::default(),
+        )?;
+        let shadow_pipeline = renderer.create_shadow_pipeline(
+            Path::new("./resources/shaders/shadow_instanced.vert"),
+            &[PropVertex::description(), Instance::description()],
+            &[],
+        )?;
+
+        let vertex_buffer = renderer.device.create_buffer_with_data(
+            bytemuck::cast_slice(&mesh.vertices),
+            wgpu::BufferUsage::VERTEX,
+        );
+
+        let index_buffer = renderer.device.create_buffer_with_data(
+            bytemuck::cast_slice(&mesh.indices),
+            wgpu::BufferUsage::INDEX,
+        );
+
+        let mut instanced = InstancedMesh {
+            pipeline,
+            shadow_pipeline,
+            vertex_buffer,
+            index_buffer,
+            instance_buffer: None,
+            num_indices: mesh.indices.len() as u32,
+            num_instances: 0,
+        };
+        instanced.set_instances(renderer, instances);
+        Ok(instanced)
+    }
+
+    /// Replaces every instance.
+    pub fn set_instances(&mut self, renderer: &Renderer, instances: &[Instance]) {
+        self.num_instances = instances.len() as u32;
+        // wgpu won't create an empty buffer
+        self.instance_buffer = if instances.is_empty() {
+            None
+        } else {
+            Some(renderer.device.create_buffer_with_data(
+                bytemuck::cast_slice(instances),
+                wgpu::BufferUsage::VERTEX,
+            ))
+        };
+    }
+
+    pub fn num_instances(&self) -> usize {
+        self.num_instances as usize
+    }
+}
+
+impl Object for InstancedMesh {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn shadow_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        Some(&self.shadow_pipeline)
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(instance_buffer) = &self.instance_buffer {
+            render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+            render_pass.set_vertex_buffer(1, instance_buffer, 0, 0);
+            render_pass.set_index_buffer(&self.index_buffer, 0, 0);
+            render_pass.draw_indexed(0..self.num_indices, 0, 0..self.num_instances);
+        }
+    }
+
+    fn update(&mut self, _transform: &mut Transform, _dt: f32) {}
+}
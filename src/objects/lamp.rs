@@ -1,24 +1,148 @@
-use super::VertexAttribute;
-use std::mem;
+use super::{Mesh, Object, Transform, VertexAttribute};
+use crate::na;
+use crate::renderer::{Light, LightKind, PipelineOptions};
+use crate::{Renderer, Result};
+use std::{mem, path::Path};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct LampVertex {
     pub position: [f32; 3],
+    pub color: [f32; 3],
 }
 
+unsafe impl bytemuck::Pod for LampVertex {}
+unsafe impl bytemuck::Zeroable for LampVertex {}
+
 impl VertexAttribute for LampVertex {
     fn description<'a>() -> wgpu::VertexBufferDescriptor<'a> {
         wgpu::VertexBufferDescriptor {
             stride: mem::size_of::<LampVertex>() as wgpu::BufferAddress,
             step_mode: wgpu::InputStepMode::Vertex,
-            attributes: &[wgpu::VertexAttributeDescriptor {
-                offset: 0,
-                shader_location: 0,
-                format: wgpu::VertexFormat::Float3,
-            }],
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float3,
+                },
+            ],
         }
     }
 }
 
-pub struct Lamp {}
+/// Casts a light from wherever its transform puts it, drawn as a small unlit marker in the
+/// light's colour. Markers of directional and spot lights point the way the light shines.
+pub struct Lamp {
+    light: Light,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+impl Lamp {
+    pub fn new(renderer: &Renderer, light: Light) -> Result<Lamp> {
+        let vert_path = Path::new("./resources/shaders/lamp.vert");
+        let frag_path = Path::new("./resources/shaders/lamp.frag");
+
+        let pipeline = renderer.create_pipeline(
+            vert_path,
+            frag_path,
+            &[LampVertex::description()],
+            &[],
+            &PipelineOptions::default(),
+        )?;
+
+        let mesh = lamp_mesh(&light);
+        let vertex_buffer = renderer.device.create_buffer_with_data(
+            bytemuck::cast_slice(&mesh.vertices),
+            wgpu::BufferUsage::VERTEX,
+        );
+        let index_buffer = renderer.device.create_buffer_with_data(
+            bytemuck::cast_slice(&mesh.indices),
+            wgpu::BufferUsage::INDEX,
+        );
+
+        Ok(Lamp {
+            light,
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            num_indices: mesh.indices.len() as u32,
+        })
+    }
+
+    /// The marker is built once, so steer the light by turning the lamp's transform rather than
+    /// changing its direction here. Colour changes don't reach the marker either.
+    pub fn light_mut(&mut self) -> &mut Light {
+        &mut self.light
+    }
+}
+
+// An octahedron half a unit across, with one tip stretched along the light's direction
+fn lamp_mesh(light: &Light) -> Mesh<LampVertex> {
+    #[rustfmt::skip]
+    const FACES: [u16; 24] = [
+        0, 2, 4, 2, 1, 4, 1, 3, 4, 3, 0, 4,
+        2, 0, 5, 1, 2, 5, 3, 1, 5, 0, 3, 5,
+    ];
+
+    let direction = na::Vector3::from(light.direction)
+        .try_normalize(f32::EPSILON)
+        .unwrap_or_else(|| -na::Vector3::y());
+    let pointer = match light.kind {
+        LightKind::Point => -na::Vector3::y(),
+        LightKind::Directional | LightKind::Spot => direction * 3.0,
+    };
+
+    // Any perpendicular pair will do for the other four corners
+    let up = -pointer.normalize();
+    let side = if up.x.abs() < 0.9 {
+        na::Vector3::x()
+    } else {
+        na::Vector3::z()
+    };
+    let across = up.cross(&side).normalize();
+    let side = across.cross(&up);
+    let corners = [side, -side, across, -across, up, pointer];
+
+    // Brightest channel at full strength whatever the intensity
+    let brightest = light.color.iter().cloned().fold(f32::EPSILON, f32::max);
+    let color = [
+        light.color[0] / brightest,
+        light.color[1] / brightest,
+        light.color[2] / brightest,
+    ];
+
+    let vertices = corners
+        .iter()
+        .map(|corner| LampVertex {
+            position: (corner * 0.25).into(),
+            color,
+        })
+        .collect();
+    Mesh::new(vertices, FACES.to_vec())
+}
+
+impl Object for Lamp {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+        render_pass.set_index_buffer(&self.index_buffer, 0, 0);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+
+    fn update(&mut self, _transform: &mut Transform, _dt: f32) {}
+
+    fn light(&self) -> Option<&Light> {
+        Some(&self.light)
+    }
+}
@@ -1,55 +1,266 @@
-mod mesh;
-mod transform;
+use super::{Mesh, Object, Transform, VertexAttribute};
+use crate::assets::{load_model, Material, ModelAsset, ModelVertex, TextureSource};
+use crate::renderer::{linear_to_srgb, srgb_to_linear, PipelineOptions, Renderer, Texture};
+use crate::{na, Result};
+use serde::Deserialize;
+use std::{
+    mem,
+    path::{Path, PathBuf},
+};
 
-pub use mesh::Mesh;
-pub use mesh::Vertex;
-pub use transform::Transform;
+/// A model file placed in the world.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    pub path: PathBuf,
+    pub position: [f32; 3],
+    /// Rotation about +y in degrees.
+    pub yaw: f32,
+    pub scale: f32,
+}
 
-use crate::renderer::{Pipeline, Renderer, Texture};
+impl Default for ModelConfig {
+    fn default() -> Self {
+        ModelConfig {
+            path: PathBuf::new(),
+            position: [0.0, 0.0, 0.0],
+            yaw: 0.0,
+            scale: 1.0,
+        }
+    }
+}
 
-pub struct Model {
-    transform: Transform,
-    pub mesh: Mesh,
-    pub pipeline: Pipeline,
+impl ModelConfig {
+    pub fn transform(&self) -> Transform {
+        Transform::new(
+            self.position.into(),
+            na::Vector3::y() * self.yaw.to_radians(),
+            self.scale,
+        )
+    }
+}
+
+// Highlight colour and, in w, the shininess
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct MaterialUniforms {
+    specular: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for MaterialUniforms {}
+unsafe impl bytemuck::Zeroable for MaterialUniforms {}
+
+struct GpuMaterial {
+    texture: Texture,
+    _uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+}
 
-    pub vertex_buffer: wgpu::Buffer,
-    pub index_buffer: wgpu::Buffer,
-    pub num_indices: u32,
+struct Part {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    // Index into `Model::materials`
+    material: usize,
+}
 
-    pub texture: Texture,
+/// Textured meshes sharing one pipeline. Each mesh's material binds its diffuse texture at
+/// set 2 and its specular terms at set 3.
+pub struct Model {
+    pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+    parts: Vec<Part>,
+    materials: Vec<GpuMaterial>,
 }
 
 impl Model {
-    pub fn new(
-        transform: Transform,
-        mesh: Mesh,
-        pipeline: Pipeline,
-        texture: Texture,
-        renderer: &Renderer,
-    ) -> Model {
-        let vertex_buffer = create_vertex_buffer(&mesh.vertices, &renderer.device);
-        let index_buffer = create_index_buffer(&mesh.indices, &renderer.device);
-        let num_indices = mesh.indices.len() as u32;
-        Model {
-            transform,
-            mesh,
+    /// Loads an OBJ or glTF file, see `assets::load_model`.
+    pub fn load(renderer: &Renderer, path: &Path) -> Result<Model> {
+        let asset = load_model(path)?;
+        Model::from_asset(renderer, &asset)
+    }
+
+    pub fn from_asset(renderer: &Renderer, asset: &ModelAsset) -> Result<Model> {
+        let vert_path = Path::new("./resources/shaders/model.vert");
+        let frag_path = Path::new("./resources/shaders/model.frag");
+
+        let layouts = MaterialLayouts {
+            texture: Texture::bind_group_layout(&renderer.device),
+            uniforms: material_uniforms_layout(&renderer.device),
+        };
+        let pipeline = renderer.create_pipeline(
+            vert_path,
+            frag_path,
+            &[ModelVertex::description()],
+            &[&layouts.texture, &layouts.uniforms],
+            &PipelineOptions::default(),
+        )?;
+        let shadow_pipeline = renderer.create_shadow_pipeline(
+            Path::new("./resources/shaders/shadow.vert"),
+            &[ModelVertex::description()],
+            &[&layouts.texture, &layouts.uniforms],
+        )?;
+
+        // Plus a plain white material for meshes without one
+        let mut materials = asset
+            .materials
+            .iter()
+            .map(|material| create_material(renderer, material, &layouts))
+            .collect::<Result<Vec<_>>>()?;
+        let default_material = materials.len();
+        materials.push(create_material(renderer, &Material::default(), &layouts)?);
+
+        let parts = asset
+            .meshes
+            .iter()
+            .map(|model_mesh| {
+                create_part(
+                    renderer,
+                    &model_mesh.mesh,
+                    model_mesh.material.unwrap_or(default_material),
+                )
+            })
+            .collect();
+
+        Ok(Model {
             pipeline,
-            vertex_buffer,
-            index_buffer,
-            num_indices,
-            texture,
-        }
+            shadow_pipeline,
+            parts,
+            materials,
+        })
+    }
+}
+
+fn create_part(renderer: &Renderer, mesh: &Mesh<ModelVertex>, material: usize) -> Part {
+    let vertex_buffer = renderer.device.create_buffer_with_data(
+        bytemuck::cast_slice(&mesh.vertices),
+        wgpu::BufferUsage::VERTEX,
+    );
+    let index_buffer = renderer.device.create_buffer_with_data(
+        bytemuck::cast_slice(&mesh.indices),
+        wgpu::BufferUsage::INDEX,
+    );
+
+    Part {
+        vertex_buffer,
+        index_buffer,
+        num_indices: mesh.indices.len() as u32,
+        material,
     }
 }
 
-fn create_vertex_buffer(vertices: &Vec<Vertex>, device: &wgpu::Device) -> wgpu::Buffer {
-    device
-        .create_buffer_mapped(vertices.len(), wgpu::BufferUsage::VERTEX)
-        .fill_from_slice(vertices)
+// Bind groups only fit pipelines made with the same layout objects, so every material of a
+// model shares these
+struct MaterialLayouts {
+    texture: wgpu::BindGroupLayout,
+    uniforms: wgpu::BindGroupLayout,
+}
+
+fn material_uniforms_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        bindings: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStage::FRAGMENT,
+            ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+        }],
+        label: Some("material"),
+    })
+}
+
+fn create_material(
+    renderer: &Renderer,
+    material: &Material,
+    layouts: &MaterialLayouts,
+) -> Result<GpuMaterial> {
+    let texture = material_texture(renderer, material, &layouts.texture)?;
+
+    let uniforms = MaterialUniforms {
+        specular: [
+            material.specular[0],
+            material.specular[1],
+            material.specular[2],
+            material.shininess.max(1.0),
+        ],
+    };
+    let uniform_buffer = renderer
+        .device
+        .create_buffer_with_data(bytemuck::bytes_of(&uniforms), wgpu::BufferUsage::UNIFORM);
+    let uniform_bind_group = renderer
+        .device
+        .create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layouts.uniforms,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &uniform_buffer,
+                    range: 0..mem::size_of::<MaterialUniforms>() as wgpu::BufferAddress,
+                },
+            }],
+            label: Some("material"),
+        });
+
+    Ok(GpuMaterial {
+        texture,
+        _uniform_buffer: uniform_buffer,
+        uniform_bind_group,
+    })
+}
+
+// The base colour multiplies the image, or stands in for it when there is none
+fn material_texture(
+    renderer: &Renderer,
+    material: &Material,
+    layout: &wgpu::BindGroupLayout,
+) -> Result<Texture> {
+    let (device, queue) = (&renderer.device, &renderer.queue);
+    let color = material.base_color;
+
+    let mut image = match &material.diffuse_texture {
+        None => return Ok(Texture::from_color(color, layout, device, queue)),
+        Some(TextureSource::File(path)) => image::open(path)
+            .map_err(|e| {
+                format!(
+                    "Material '{}' texture {}: {}",
+                    material.name,
+                    path.display(),
+                    e
+                )
+            })?
+            .into_rgba8(),
+        Some(TextureSource::Image(image)) => image.clone(),
+    };
+
+    // The texels are sRGB and the factor linear, alpha is linear in both
+    if color != [1.0; 4] {
+        for pixel in image.pixels_mut() {
+            for channel in 0..3 {
+                pixel[channel] = linear_to_srgb(srgb_to_linear(pixel[channel]) * color[channel]);
+            }
+            pixel[3] = (pixel[3] as f32 * color[3]).round() as u8;
+        }
+    }
+    Ok(Texture::from_image(&image, layout, device, queue))
 }
 
-fn create_index_buffer(indices: &Vec<u16>, device: &wgpu::Device) -> wgpu::Buffer {
-    device
-        .create_buffer_mapped(indices.len(), wgpu::BufferUsage::INDEX)
-        .fill_from_slice(indices)
+impl Object for Model {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn shadow_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        Some(&self.shadow_pipeline)
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        for part in &self.parts {
+            let material = &self.materials[part.material];
+            render_pass.set_bind_group(2, &material.texture.diffuse_bind_group, &[]);
+            render_pass.set_bind_group(3, &material.uniform_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, &part.vertex_buffer, 0, 0);
+            render_pass.set_index_buffer(&part.index_buffer, 0, 0);
+            render_pass.draw_indexed(0..part.num_indices, 0, 0..1);
+        }
+    }
+
+    fn update(&mut self, _transform: &mut Transform, _dt: f32) {}
 }
@@ -0,0 +1,178 @@
+use super::{Instance, InstancedMesh, Mesh, PropVertex, Transform};
+use crate::na;
+use crate::terrain::{scatter, Heightmap, Rng, ScatterConfig, ScatterPoint};
+use crate::{Renderer, Result};
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PropShape {
+    Rock,
+    Tree,
+}
+
+/// One kind of prop scattered over the terrain.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct PropConfig {
+    pub shape: PropShape,
+    /// Multiplied into the mesh's own colours.
+    pub color: [f32; 3],
+    /// How far each instance's brightness may stray from `color`, 0 to 1.
+    pub color_variation: f32,
+    /// Biomes the props grow in, anywhere when empty. Only checked where there's a biome map.
+    pub biomes: Vec<String>,
+    #[serde(flatten)]
+    pub scatter: ScatterConfig,
+}
+
+impl Default for PropConfig {
+    fn default() -> Self {
+        PropConfig {
+            shape: PropShape::Rock,
+            color: [1.0, 1.0, 1.0],
+            color_variation: 0.3,
+            biomes: Vec::new(),
+            scatter: ScatterConfig::default(),
+        }
+    }
+}
+
+/// Scatters `config`'s props over `heightmap`, skipping cells where `keep` is false.
+pub fn build_props(
+    renderer: &Renderer,
+    config: &PropConfig,
+    heightmap: &Heightmap,
+    cell_size: f32,
+    keep: impl Fn(usize, usize) -> bool,
+) -> Result<InstancedMesh> {
+    let mesh = match config.shape {
+        PropShape::Rock => rock_mesh(config.scatter.seed),
+        PropShape::Tree => tree_mesh(),
+    };
+
+    let points = scatter(heightmap, cell_size, &config.scatter, keep);
+    let instances: Vec<Instance> = points
+        .iter()
+        .map(|point| prop_instance(point, config))
+        .collect();
+
+    InstancedMesh::new(renderer, &mesh, &instances)
+}
+
+fn prop_instance(point: &ScatterPoint, config: &PropConfig) -> Instance {
+    let spin = na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), point.yaw);
+    let (rotation, position) = match config.shape {
+        // Rocks lean with the ground and sink into it a little
+        PropShape::Rock => {
+            let lean = na::UnitQuaternion::rotation_between(&na::Vector3::y(), &point.normal)
+                .unwrap_or_else(na::UnitQuaternion::identity);
+            let sink = point.normal * 0.3 * point.scale;
+            (lean * spin, point.position - sink)
+        }
+        PropShape::Tree => (spin, point.position),
+    };
+
+    let transform = Transform::from_parts(position.coords.into(), rotation, point.scale);
+    let brightness = 1.0 + config.color_variation * (point.variation - 0.5);
+    let tint = [
+        config.color[0] * brightness,
+        config.color[1] * brightness,
+        config.color[2] * brightness,
+    ];
+    Instance::new(&transform, tint)
+}
+
+// Pushes a flat shaded triangle, wound counter-clockwise seen from outside
+fn push_triangle(mesh: &mut Mesh<PropVertex>, corners: [na::Point3<f32>; 3], color: [f32; 3]) {
+    let normal = (corners[1] - corners[0])
+        .cross(&(corners[2] - corners[0]))
+        .normalize();
+    for corner in &corners {
+        mesh.indices.push(mesh.vertices.len() as u16);
+        mesh.vertices.push(PropVertex {
+            position: corner.coords.into(),
+            normal: normal.into(),
+            color,
+        });
+    }
+}
+
+/// A lumpy icosahedron about a unit across, resting on y = 0. `seed` picks the lumps.
+pub fn rock_mesh(seed: u64) -> Mesh<PropVertex> {
+    #[rustfmt::skip]
+    const FACES: [[usize; 3]; 20] = [
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    #[rustfmt::skip]
+    let corners = [
+        [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+        [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+        [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+    ];
+
+    let mut rng = Rng::new(seed);
+    let points: Vec<na::Point3<f32>> = corners
+        .iter()
+        .map(|&[x, y, z]| {
+            let radius = 0.5 * rng.range_f32(0.75, 1.1);
+            let direction = na::Vector3::new(x, y, z).normalize();
+            // Flatten the rock so it sits low on the ground
+            na::Point3::new(direction.x, direction.y * 0.6, direction.z) * radius
+                + na::Vector3::new(0.0, 0.3, 0.0)
+        })
+        .collect();
+
+    let mut mesh = Mesh::new(Vec::new(), Vec::new());
+    for face in &FACES {
+        let shade = rng.range_f32(0.42, 0.5);
+        let corners = [points[face[0]], points[face[1]], points[face[2]]];
+        push_triangle(&mut mesh, corners, [shade, shade * 0.97, shade * 0.93]);
+    }
+    mesh
+}
+
+/// A conifer about three units tall with its trunk base at the origin.
+pub fn tree_mesh() -> Mesh<PropVertex> {
+    const SIDES: usize = 8;
+    const TRUNK: [f32; 3] = [0.35, 0.25, 0.15];
+    const LEAVES: [f32; 3] = [0.15, 0.35, 0.12];
+
+    let ring = |radius: f32, y: f32| -> Vec<na::Point3<f32>> {
+        (0..SIDES)
+            .map(|i| {
+                let angle = 2.0 * PI * i as f32 / SIDES as f32;
+                na::Point3::new(radius * angle.cos(), y, -radius * angle.sin())
+            })
+            .collect()
+    };
+
+    let mut mesh = Mesh::new(Vec::new(), Vec::new());
+
+    let trunk_bottom = ring(0.12, 0.0);
+    let trunk_top = ring(0.12, 0.9);
+    for i in 0..SIDES {
+        let j = (i + 1) % SIDES;
+        let quad = [trunk_bottom[i], trunk_bottom[j], trunk_top[j], trunk_top[i]];
+        push_triangle(&mut mesh, [quad[0], quad[1], quad[2]], TRUNK);
+        push_triangle(&mut mesh, [quad[0], quad[2], quad[3]], TRUNK);
+    }
+
+    // Two stacked cones
+    for &(radius, bottom, top) in &[(0.9, 0.7, 2.2), (0.6, 1.6, 3.0)] {
+        let base = ring(radius, bottom);
+        let center = na::Point3::new(0.0, bottom, 0.0);
+        let tip = na::Point3::new(0.0, top, 0.0);
+        for i in 0..SIDES {
+            let j = (i + 1) % SIDES;
+            push_triangle(&mut mesh, [base[i], base[j], tip], LEAVES);
+            push_triangle(&mut mesh, [base[j], base[i], center], LEAVES);
+        }
+    }
+    mesh
+}
@@ -0,0 +1,81 @@
+use super::{Object, TerrainMaterial, Transform, VertexAttribute};
+use crate::terrain::{build_mesh, Heightmap, SplatConfig, SplatMap, TerrainVertex};
+use crate::{renderer::PipelineOptions, Renderer, Result};
+use std::path::Path;
+
+pub struct Terrain {
+    pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+    material: TerrainMaterial,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+}
+
+impl Terrain {
+    pub fn new(
+        renderer: &Renderer,
+        heightmap: &Heightmap,
+        splat: &SplatMap,
+        materials: &SplatConfig,
+        cell_size: f32,
+    ) -> Result<Terrain> {
+        let vert_path = Path::new("./resources/shaders/terrain.vert");
+        let frag_path = Path::new("./resources/shaders/terrain.frag");
+
+        let mesh = build_mesh(heightmap, splat, cell_size)?;
+        let material = TerrainMaterial::new(renderer, materials)?;
+        let pipeline = renderer.create_pipeline(
+            vert_path,
+            frag_path,
+            &[TerrainVertex::description()],
+            &material.layouts(),
+            &PipelineOptions::default(),
+        )?;
+        let shadow_pipeline = renderer.create_shadow_pipeline(
+            Path::new("./resources/shaders/shadow.vert"),
+            &[TerrainVertex::description()],
+            &material.layouts(),
+        )?;
+
+        let vertex_buffer = renderer.device.create_buffer_with_data(
+            bytemuck::cast_slice(&mesh.vertices),
+            wgpu::BufferUsage::VERTEX,
+        );
+
+        let index_buffer = renderer.device.create_buffer_with_data(
+            bytemuck::cast_slice(&mesh.indices),
+            wgpu::BufferUsage::INDEX,
+        );
+
+        let num_indices = mesh.indices.len() as u32;
+
+        Ok(Terrain {
+            pipeline,
+            shadow_pipeline,
+            material,
+            vertex_buffer,
+            index_buffer,
+            num_indices,
+        })
+    }
+}
+
+impl Object for Terrain {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn shadow_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        Some(&self.shadow_pipeline)
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        self.material.bind(render_pass);
+        render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+        render_pass.set_index_buffer(&self.index_buffer, 0, 0);
+        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    }
+
+    fn update(&mut self, _transform: &mut Transform, _dt: f32) {}
+}
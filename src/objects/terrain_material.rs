@@ -0,0 +1,148 @@
+use crate::renderer::TextureArray;
+use crate::terrain::{SplatConfig, MAX_SPLAT_LAYERS};
+use crate::{Renderer, Result};
+use log::info;
+use std::mem;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct TerrainMaterialUniforms {
+    // Texture repeats per world unit
+    layer_scale: [f32; 4],
+    layer_specular: [f32; 4],
+    layer_color: [[f32; 4]; MAX_SPLAT_LAYERS],
+    // Layer count, triplanar sharpness and anti-tiling strength
+    params: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for TerrainMaterialUniforms {}
+unsafe impl bytemuck::Zeroable for TerrainMaterialUniforms {}
+
+/// The terrain's splat layers, a texture array at set 2 and each layer's tint, scale and
+/// specular at set 3. Vertices carry the weights blending them, see `terrain::splat_weights`.
+pub struct TerrainMaterial {
+    pub texture_layout: wgpu::BindGroupLayout,
+    pub uniform_layout: wgpu::BindGroupLayout,
+    layers: TextureArray,
+    _uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+}
+
+impl TerrainMaterial {
+    pub fn new(renderer: &Renderer, config: &SplatConfig) -> Result<TerrainMaterial> {
+        let layers = &config.layers[..config.layers.len().min(MAX_SPLAT_LAYERS)];
+        if layers.is_empty() {
+            return Err("Terrain materials need at least one layer".into());
+        }
+
+        let images = layer_images(config)?;
+        let texture_layout = TextureArray::bind_group_layout(&renderer.device);
+        let texture_array =
+            TextureArray::from_images(&images, &texture_layout, &renderer.device, &renderer.queue)?;
+
+        let mut uniforms = TerrainMaterialUniforms {
+            layer_scale: [1.0; 4],
+            layer_specular: [0.0; 4],
+            layer_color: [[1.0; 4]; MAX_SPLAT_LAYERS],
+            params: [
+                layers.len() as f32,
+                config.triplanar_sharpness,
+                config.anti_tiling,
+                0.0,
+            ],
+        };
+        for (i, layer) in layers.iter().enumerate() {
+            uniforms.layer_scale[i] = 1.0 / layer.scale.max(f32::EPSILON);
+            uniforms.layer_specular[i] = layer.specular;
+            uniforms.layer_color[i] = [layer.color[0], layer.color[1], layer.color[2], 1.0];
+        }
+
+        let uniform_layout =
+            renderer
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    bindings: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                    }],
+                    label: Some("terrain_material"),
+                });
+        let uniform_buffer = renderer
+            .device
+            .create_buffer_with_data(bytemuck::bytes_of(&uniforms), wgpu::BufferUsage::UNIFORM);
+        let uniform_bind_group = renderer
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &uniform_layout,
+                bindings: &[wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &uniform_buffer,
+                        range: 0..mem::size_of::<TerrainMaterialUniforms>() as wgpu::BufferAddress,
+                    },
+                }],
+                label: Some("terrain_material"),
+            });
+
+        Ok(TerrainMaterial {
+            texture_layout,
+            uniform_layout,
+            layers: texture_array,
+            _uniform_buffer: uniform_buffer,
+            uniform_bind_group,
+        })
+    }
+
+    /// Sets 2 and 3, for pipelines drawing with this material.
+    pub fn layouts(&self) -> [&wgpu::BindGroupLayout; 2] {
+        [&self.texture_layout, &self.uniform_layout]
+    }
+
+    pub fn bind<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_bind_group(2, &self.layers.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.uniform_bind_group, &[]);
+    }
+}
+
+// One image per layer, all at the first texture's size. Layers without a texture are white so
+// only their tint shows.
+fn layer_images(config: &SplatConfig) -> Result<Vec<image::RgbaImage>> {
+    let layers = &config.layers[..config.layers.len().min(MAX_SPLAT_LAYERS)];
+    let mut loaded = Vec::with_capacity(layers.len());
+    for layer in layers {
+        let image = match &layer.texture {
+            Some(path) => {
+                let image = image::open(path)
+                    .map_err(|e| format!("Failed to open texture {}: {}", path.display(), e))?;
+                info!(
+                    "Loaded terrain layer '{}' from {}",
+                    layer.name,
+                    path.display()
+                );
+                Some(image.into_rgba8())
+            }
+            None => None,
+        };
+        loaded.push(image);
+    }
+
+    let (width, height) = loaded
+        .iter()
+        .flatten()
+        .next()
+        .map_or((1, 1), |image| image.dimensions());
+    Ok(loaded
+        .into_iter()
+        .map(|image| match image {
+            Some(image) if image.dimensions() == (width, height) => image,
+            Some(image) => image::imageops::resize(
+                &image,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            ),
+            None => image::RgbaImage::from_pixel(width, height, image::Rgba([255; 4])),
+        })
+        .collect())
+}
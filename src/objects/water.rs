@@ -0,0 +1,108 @@
+use super::{Object, Transform, VertexAttribute};
+use crate::renderer::{PipelineOptions, Uniform};
+use crate::water::{build_water_mesh, WaterConfig, WaterMap, WaterVertex};
+use crate::{Renderer, Result};
+use std::path::Path;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct WaterUniforms {
+    shallow_color: [f32; 4],
+    deep_color: [f32; 4],
+    // amplitude, wave length, speed, opacity
+    wave: [f32; 4],
+    // deep depth, unused x3
+    depth: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for WaterUniforms {}
+unsafe impl bytemuck::Zeroable for WaterUniforms {}
+
+pub struct Water {
+    pipeline: wgpu::RenderPipeline,
+    uniforms: Uniform<WaterUniforms>,
+    // `None` when the map has no water to draw
+    buffers: Option<(wgpu::Buffer, wgpu::Buffer)>,
+    num_indices: u32,
+}
+
+impl Water {
+    pub fn new(
+        renderer: &Renderer,
+        water: &WaterMap,
+        cell_size: f32,
+        config: &WaterConfig,
+    ) -> Result<Water> {
+        let vert_path = Path::new("./resources/shaders/water.vert");
+        let frag_path = Path::new("./resources/shaders/water.frag");
+
+        let uniforms = Uniform::new(
+            &renderer.device,
+            WaterUniforms {
+                shallow_color: config.shallow_color,
+                deep_color: config.deep_color,
+                wave: [
+                    config.wave_amplitude,
+                    config.wave_length,
+                    config.wave_speed,
+                    config.opacity,
+                ],
+                depth: [config.deep_depth, 0.0, 0.0, 0.0],
+            },
+            wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+            "water_uniforms",
+        );
+
+        let pipeline = renderer.create_pipeline(
+            vert_path,
+            frag_path,
+            &[WaterVertex::description()],
+            &[&uniforms.bind_group_layout],
+            &PipelineOptions::transparent(),
+        )?;
+
+        let mesh = build_water_mesh(water, cell_size)?;
+        let num_indices = mesh.indices.len() as u32;
+        let buffers = if num_indices > 0 {
+            let vertex_buffer = renderer.device.create_buffer_with_data(
+                bytemuck::cast_slice(&mesh.vertices),
+                wgpu::BufferUsage::VERTEX,
+            );
+            let index_buffer = renderer.device.create_buffer_with_data(
+                bytemuck::cast_slice(&mesh.indices),
+                wgpu::BufferUsage::INDEX,
+            );
+            Some((vertex_buffer, index_buffer))
+        } else {
+            None
+        };
+
+        Ok(Water {
+            pipeline,
+            uniforms,
+            buffers,
+            num_indices,
+        })
+    }
+}
+
+impl Object for Water {
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some((vertex_buffer, index_buffer)) = &self.buffers {
+            render_pass.set_bind_group(2, &self.uniforms.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, vertex_buffer, 0, 0);
+            render_pass.set_index_buffer(index_buffer, 0, 0);
+            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+        }
+    }
+
+    fn update(&mut self, _transform: &mut Transform, _dt: f32) {}
+
+    fn is_transparent(&self) -> bool {
+        true
+    }
+}
@@ -1,29 +1,74 @@
-use crate::{
-    objects::{Cube, Object},
-    Result,
-};
-use std::path::Path;
+use crate::{objects::Camera, scene::Scene, Result};
+use log::info;
+use serde::Deserialize;
+use std::{path::Path, rc::Rc};
 use winit::{dpi::PhysicalSize, window::Window};
 
+pub mod capture;
+pub mod color;
+pub mod lights;
 pub mod pipeline;
+pub mod shadows;
+pub mod sky;
 pub mod texture;
-pub use texture::Texture;
+pub mod uniforms;
+pub use capture::{save_image, OffscreenTarget};
+pub use color::{linear_to_srgb, srgb_float_to_linear, srgb_to_linear};
+pub use lights::{Light, LightKind, LightingConfig, Lights};
+pub use pipeline::PipelineOptions;
+pub use shadows::{ShadowConfig, ShadowMap, ShadowUniforms};
+pub use sky::{Atmosphere, Sky, SkyConfig};
+pub use texture::{DepthTexture, Texture, TextureArray};
+pub use uniforms::{FrameUniforms, Globals, Model, ModelBuffer, Uniform};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RendererConfig {
+    /// Store depth reversed, with the near plane at 1, for better precision far away.
+    pub reverse_z: bool,
+    /// Render on a CPU adapter such as lavapipe or SwiftShader when running without a window,
+    /// so headless output matches between machines.
+    pub software: bool,
+    pub shadows: ShadowConfig,
+    pub sky: SkyConfig,
+}
+
+// Where frames end up, a window's swap chain or a texture for reading back
+enum Target {
+    Window {
+        surface: wgpu::Surface,
+        sc_desc: wgpu::SwapChainDescriptor,
+        swap_chain: wgpu::SwapChain,
+    },
+    // Shared so a frame can be drawn into it while the renderer is borrowed
+    Offscreen(Rc<OffscreenTarget>),
+}
 
 pub struct Renderer {
-    surface: wgpu::Surface,
     _adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    sc_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
+    target: Target,
+    format: wgpu::TextureFormat,
+    depth_texture: DepthTexture,
+    reverse_z: bool,
     size: PhysicalSize<u32>,
     bg_color: wgpu::Color,
+    frame: FrameUniforms,
+    shadow_map: ShadowMap,
+    pub sky: Sky,
+    models: ModelBuffer,
+    pub camera: Camera,
 }
 
 impl Renderer {
-    pub async fn new(window: &Window, bg_color: [f32; 4]) -> Self {
+    pub async fn new(
+        window: &Window,
+        bg_color: [f32; 4],
+        camera: Camera,
+        config: &RendererConfig,
+    ) -> Result<Self> {
         let size = window.inner_size();
-
         let surface = wgpu::Surface::create(window);
         let adapter = wgpu::Adapter::request(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::HighPerformance,
@@ -31,12 +76,7 @@ impl Renderer {
         }, wgpu::BackendBit::VULKAN)
         .await.unwrap();
 
-        let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
-            extensions: wgpu::Extensions {
-                anisotropic_filtering: false,
-            },
-            limits: Default::default(),
-        }).await;
+        let (device, queue) = request_device(&adapter).await;
 
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
@@ -46,69 +86,330 @@ impl Renderer {
             present_mode: wgpu::PresentMode::Immediate,
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
+        let format = sc_desc.format;
+        let target = Target::Window {
+            surface,
+            sc_desc,
+            swap_chain,
+        };
+
+        Self::with_target(adapter, device, queue, target, format, size, bg_color, camera, config)
+    }
+
+    /// A renderer without a window, drawing into an offscreen texture for `render_to_image`.
+    /// Takes the first adapter it can get, or a CPU one with `RendererConfig::software`.
+    pub async fn headless(
+        width: u32,
+        height: u32,
+        bg_color: [f32; 4],
+        camera: Camera,
+        config: &RendererConfig,
+    ) -> Result<Self> {
+        let adapter = if config.software {
+            wgpu::Adapter::enumerate(wgpu::BackendBit::PRIMARY)
+                .into_iter()
+                .find(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu)
+                .ok_or("No software adapter found, install lavapipe or SwiftShader")?
+        } else {
+            let adapter = wgpu::Adapter::request(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::Default,
+                    compatible_surface: None,
+                },
+                wgpu::BackendBit::PRIMARY,
+            )
+            .await;
+            // Fall back on anything at all, CPU adapters included
+            adapter
+                .or_else(|| wgpu::Adapter::enumerate(wgpu::BackendBit::PRIMARY).pop())
+                .ok_or("No graphics adapter found")?
+        };
+        let info = adapter.get_info();
+        info!("Rendering offscreen on {} ({:?})", info.name, info.device_type);
+
+        let (device, queue) = request_device(&adapter).await;
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let target = Target::Offscreen(Rc::new(OffscreenTarget::new(
+            &device, width, height, format,
+        )));
+        let size = PhysicalSize::new(width, height);
+
+        Self::with_target(
+            adapter, device, queue, target, format, size, bg_color, camera, config,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn with_target(
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        target: Target,
+        format: wgpu::TextureFormat,
+        size: PhysicalSize<u32>,
+        bg_color: [f32; 4],
+        mut camera: Camera,
+        config: &RendererConfig,
+    ) -> Result<Self> {
+        camera.set_aspect(size.width, size.height);
+        camera.reverse_z = config.reverse_z;
+
+        let depth_texture = DepthTexture::new(&device, size.width, size.height);
         let bg_color = wgpu::Color {
             r: bg_color[0] as f64,
             g: bg_color[1] as f64,
             b: bg_color[2] as f64,
             a: bg_color[3] as f64,
         };
+        let shadow_map = ShadowMap::new(&device, &config.shadows);
+        let frame =
+            FrameUniforms::new(&device, Globals::new(), Lights::new([0.0; 3]), &shadow_map);
+        let models = ModelBuffer::new(&device, 64);
+        let sky = Sky::new(
+            &config.sky,
+            &frame.bind_group_layout,
+            format,
+            config.reverse_z,
+            &device,
+        )?;
 
-        Self {
-            surface,
+        Ok(Self {
             _adapter: adapter,
             device,
             queue,
-            sc_desc,
-            swap_chain,
+            target,
+            format,
+            depth_texture,
+            reverse_z: config.reverse_z,
             size,
             bg_color,
-        }
+            frame,
+            shadow_map,
+            sky,
+            models,
+            camera,
+        })
     }
 
     pub fn create_pipeline(
         &self,
         vert_file: &Path,
         frag_file: &Path,
-        vertex_buffer_descriptor: wgpu::VertexBufferDescriptor,
+        vertex_buffers: &[wgpu::VertexBufferDescriptor],
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        options: &PipelineOptions,
+    ) -> Result<wgpu::RenderPipeline> {
+        // Every pipeline sees the shared globals and lights at set 0 and its object's model at
+        // set 1
+        let mut layouts = vec![
+            &self.frame.bind_group_layout,
+            &self.models.bind_group_layout,
+        ];
+        layouts.extend_from_slice(bind_group_layouts);
+
+        let options = if self.reverse_z {
+            options.reverse_z()
+        } else {
+            options.clone()
+        };
+
+        pipeline::create_pipeline(
+            vert_file,
+            Some(frag_file),
+            vertex_buffers,
+            &layouts,
+            &options,
+            self.format,
+            &self.device,
+        )
+    }
+
+    /// Depth-only pipeline for drawing an object into the shadow map. `bind_group_layouts`
+    /// should match the object's main pipeline so `Object::render` works with either, set 0
+    /// holds the shadow cascade instead of the globals.
+    pub fn create_shadow_pipeline(
+        &self,
+        vert_file: &Path,
+        vertex_buffers: &[wgpu::VertexBufferDescriptor],
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
     ) -> Result<wgpu::RenderPipeline> {
+        let mut layouts = vec![&self.shadow_map.pass_layout, &self.models.bind_group_layout];
+        layouts.extend_from_slice(bind_group_layouts);
+
+        // Shadow maps always use standard depth, whatever the main pass does
+        let options =
+            PipelineOptions::shadow(self.shadow_map.depth_bias, self.shadow_map.slope_bias);
         pipeline::create_pipeline(
             vert_file,
-            frag_file,
-            vertex_buffer_descriptor,
-            self.sc_desc.format,
+            None,
+            vertex_buffers,
+            &layouts,
+            &options,
+            self.format,
             &self.device,
         )
     }
 
+    /// Simulation seconds the shaders see as `time`, so animation stops with the clock.
+    pub fn set_time(&mut self, seconds: f32) {
+        self.frame.globals.time = seconds;
+    }
+
+    /// Light added to every lit surface, linear RGB.
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.frame.lights.ambient = [ambient[0], ambient[1], ambient[2], 1.0];
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        // Minimised windows report a zero size, keep the old targets until they're restored
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
         self.size = new_size;
-        self.sc_desc.width = new_size.width;
-        self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        self.camera.set_aspect(new_size.width, new_size.height);
+        match &mut self.target {
+            Target::Window {
+                surface,
+                sc_desc,
+                swap_chain,
+            } => {
+                sc_desc.width = new_size.width;
+                sc_desc.height = new_size.height;
+                *swap_chain = self.device.create_swap_chain(surface, sc_desc);
+            }
+            Target::Offscreen(target) => {
+                *target = Rc::new(OffscreenTarget::new(
+                    &self.device,
+                    new_size.width,
+                    new_size.height,
+                    self.format,
+                ));
+            }
+        }
+        self.depth_texture = DepthTexture::new(&self.device, new_size.width, new_size.height);
+    }
+
+    // Depth the attachment is cleared to, the far plane
+    fn clear_depth(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
     }
 
     pub fn init_clear_screen(&mut self) {
+        let swap_chain = match &mut self.target {
+            Target::Window { swap_chain, .. } => swap_chain,
+            Target::Offscreen(_) => return,
+        };
         // TODO: fix unwrap
-        let frame = self.swap_chain.get_next_texture().unwrap();
+        let frame = swap_chain.get_next_texture().unwrap();
 
         let mut encoder = get_command_encoder(&self.device);
         {
-            let _render_pass = begin_render_pass(&mut encoder, &frame, self.bg_color);
+            let _render_pass = begin_render_pass(
+                &mut encoder,
+                &frame.view,
+                &self.depth_texture.view,
+                self.bg_color,
+                self.clear_depth(),
+            );
         }
         submit_frame(&mut self.queue, encoder);
     }
 
-    pub fn render(&mut self, cube: &Cube) {
-        // TODO: fix unwrap
-        let frame = self.swap_chain.get_next_texture().unwrap();
+    pub fn render(&mut self, scene: &Scene) {
+        match &mut self.target {
+            Target::Window { swap_chain, .. } => {
+                // TODO: fix unwrap
+                let frame = swap_chain.get_next_texture().unwrap();
+                self.draw(scene, &frame.view);
+            }
+            Target::Offscreen(target) => {
+                let target = Rc::clone(target);
+                self.draw(scene, &target.view);
+            }
+        }
+    }
+
+    /// Draws `scene` and reads the frame back. Windowed renderers draw an extra frame into a
+    /// texture the size of the window, leaving the window as it was.
+    pub async fn read_frame(&mut self, scene: &Scene) -> Result<image::RgbaImage> {
+        let target = match &self.target {
+            Target::Offscreen(target) => Rc::clone(target),
+            Target::Window { .. } => Rc::new(OffscreenTarget::new(
+                &self.device,
+                self.size.width,
+                self.size.height,
+                self.format,
+            )),
+        };
+        self.draw(scene, &target.view);
+        target.read(&self.device, &self.queue).await
+    }
+
+    /// Draws `scene` into an image file, see `capture::save_image` for the formats.
+    pub async fn render_to_image(&mut self, scene: &Scene, path: &Path) -> Result<()> {
+        let image = self.read_frame(scene).await?;
+        save_image(&image, path)?;
+        info!("Saved frame to {}", path.display());
+        Ok(())
+    }
+
+    fn draw(&mut self, scene: &Scene, view: &wgpu::TextureView) {
         let mut encoder = get_command_encoder(&self.device);
+
+        self.frame.globals.update_camera(&self.camera);
+        self.frame.lights.set(scene.nodes().filter_map(|node| {
+            node.object.light().map(|light| (light, &node.transform))
+        }));
+        self.shadow_map.update(&self.camera, self.frame.lights.sun());
+        self.frame.shadows = self.shadow_map.uniforms;
+        let sun_direction = self
+            .frame
+            .lights
+            .sun()
+            .map_or_else(|| self.sky.sun_direction(), |(_, direction)| direction);
+        self.frame.atmosphere = self.sky.atmosphere(&self.camera, sun_direction);
+        self.frame.update(&self.device, &mut encoder);
+        self.shadow_map.upload(&self.device, &mut encoder);
+
+        let nodes = scene.draw_order(&self.camera.position);
+        let models: Vec<Model> = nodes
+            .iter()
+            .map(|node| Model::new(&node.transform))
+            .collect();
+        self.models.update(&self.device, &mut encoder, &models);
+
+        self.shadow_map.render(&mut encoder, &nodes, &self.models);
+
         {
-            let mut render_pass = begin_render_pass(&mut encoder, &frame, self.bg_color);
-            // cube.render(&mut render_pass);
-            render_pass.set_pipeline(&cube.pipeline);
-            render_pass.set_vertex_buffer(0, &cube.vertex_buffer, 0, 0);
-            render_pass.set_index_buffer(&cube.index_buffer, 0, 0);
-            render_pass.draw_indexed(0..cube.num_indices, 0, 0..1);
+            let mut render_pass = begin_render_pass(
+                &mut encoder,
+                view,
+                &self.depth_texture.view,
+                self.bg_color,
+                self.clear_depth(),
+            );
+            render_pass.set_bind_group(0, &self.frame.bind_group, &[]);
+            self.sky.render(&mut render_pass);
+
+            // Only switch pipelines when the sorted objects move on to a new one
+            let mut bound: Option<&wgpu::RenderPipeline> = None;
+            for (index, node) in nodes.iter().enumerate() {
+                let pipeline = node.object.pipeline();
+                if !matches!(bound, Some(bound) if std::ptr::eq(bound, pipeline)) {
+                    render_pass.set_pipeline(pipeline);
+                    bound = Some(pipeline);
+                }
+                render_pass.set_bind_group(
+                    1,
+                    &self.models.bind_group,
+                    &[ModelBuffer::offset(index)],
+                );
+                node.object.render(&mut render_pass);
+            }
         }
         submit_frame(&mut self.queue, encoder);
     }
@@ -150,24 +451,45 @@ impl Renderer {
     // }
 }
 
+async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            extensions: wgpu::Extensions {
+                anisotropic_filtering: false,
+            },
+            limits: Default::default(),
+        })
+        .await
+}
+
 fn get_command_encoder(device: &wgpu::Device) -> wgpu::CommandEncoder {
     device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("comand_encoder")})
 }
 
 fn begin_render_pass<'a>(
     encoder: &'a mut wgpu::CommandEncoder,
-    frame: &'a wgpu::SwapChainOutput,
+    view: &'a wgpu::TextureView,
+    depth_view: &'a wgpu::TextureView,
     bg_color: wgpu::Color,
+    clear_depth: f32,
 ) -> wgpu::RenderPass<'a> {
     encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-            attachment: &frame.view,
+            attachment: view,
             resolve_target: None,
             load_op: wgpu::LoadOp::Clear,
             store_op: wgpu::StoreOp::Store,
             clear_color: bg_color,
         }],
-        depth_stencil_attachment: None,
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+            attachment: depth_view,
+            depth_load_op: wgpu::LoadOp::Clear,
+            depth_store_op: wgpu::StoreOp::Store,
+            clear_depth,
+            stencil_load_op: wgpu::LoadOp::Clear,
+            stencil_store_op: wgpu::StoreOp::Store,
+            clear_stencil: 0,
+        }),
     })
 }
 
@@ -0,0 +1,273 @@
+use super::srgb_to_linear;
+use crate::Result;
+use std::{fs::File, io::BufWriter, io::Write, path::Path};
+
+// Texture to buffer copies need every row to start on this many bytes
+const ROW_ALIGNMENT: u32 = 256;
+
+/// A colour texture to draw frames into without a window, which can be read back to the CPU.
+pub struct OffscreenTarget {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OffscreenTarget {
+    /// `format` has to be 8 bit RGBA or BGRA, the formats frames are drawn in.
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> OffscreenTarget {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = texture.create_default_view();
+
+        OffscreenTarget {
+            texture,
+            view,
+            format,
+            width,
+            height,
+        }
+    }
+
+    /// Copies the texture back once the queue has finished drawing into it. Frames are drawn
+    /// in an sRGB format, so the pixels come back sRGB encoded, ready for an 8 bit image file.
+    pub async fn read(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<image::RgbaImage> {
+        let row_bytes = 4 * self.width;
+        let padded_row_bytes = row_bytes.div_ceil(ROW_ALIGNMENT) * ROW_ALIGNMENT;
+        let size = (padded_row_bytes * self.height) as wgpu::BufferAddress;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("offscreen_readback"),
+            size,
+            usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("offscreen_readback_encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &buffer,
+                offset: 0,
+                bytes_per_row: padded_row_bytes,
+                rows_per_image: self.height,
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+            },
+        );
+        queue.submit(&[encoder.finish()]);
+
+        // Mapping only completes while the device is polled
+        let mapping = buffer.map_read(0, size);
+        device.poll(wgpu::Maintain::Wait);
+        let mapping = mapping
+            .await
+            .map_err(|e| format!("Failed to read back frame: {:?}", e))?;
+
+        let bgra = match self.format {
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            format => return Err(format!("Can't read back {:?} frames", format).into()),
+        };
+        let mut pixels = Vec::with_capacity((row_bytes * self.height) as usize);
+        for row in mapping.as_slice().chunks(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        if bgra {
+            pixels.chunks_mut(4).for_each(|pixel| pixel.swap(0, 2));
+        }
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or_else(|| "Frame readback was the wrong size".into())
+    }
+}
+
+/// Writes a captured frame, picking the format from the extension. `.exr` files hold linear
+/// 32 bit float colour, everything else goes through the `image` crate. The frame is read back
+/// as 8 bit sRGB, so an EXR has no more precision or range than a PNG, only linear values.
+pub fn save_image(image: &image::RgbaImage, path: &Path) -> Result<()> {
+    let exr = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"));
+    if exr {
+        let file = File::create(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        let mut writer = BufWriter::new(file);
+        write_exr(image, &mut writer)?;
+        writer.flush()?;
+    } else {
+        image
+            .save(path)
+            .map_err(|e| format!("Failed to save {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+// The image crate can't write OpenEXR yet, so this is the simplest file that format allows: one
+// uncompressed scanline per block, with float A, B, G and R channels in the alphabetical order
+// the format requires.
+fn write_exr(image: &image::RgbaImage, writer: &mut impl Write) -> Result<()> {
+    const CHANNELS: [(&str, usize); 4] = [("A", 3), ("B", 2), ("G", 1), ("R", 0)];
+    const FLOAT: i32 = 2;
+    let (width, height) = image.dimensions();
+    let (max_x, max_y) = (width as i32 - 1, height as i32 - 1);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20_000_630_i32.to_le_bytes());
+    header.extend_from_slice(&2_i32.to_le_bytes());
+
+    let mut channels = Vec::new();
+    for (name, _) in CHANNELS.iter() {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&FLOAT.to_le_bytes());
+        // Not perceptually linear, then 3 reserved bytes, then x and y sampling
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    channels.push(0);
+    let window: Vec<u8> = [0, 0, max_x, max_y]
+        .iter()
+        .flat_map(|value| value.to_le_bytes().to_vec())
+        .collect();
+
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        header.extend_from_slice(name.as_bytes());
+        header.push(0);
+        header.extend_from_slice(kind.as_bytes());
+        header.push(0);
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+    attribute("channels", "chlist", &channels);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+    header.push(0);
+
+    // An offset table pointing at each scanline block follows the header
+    let line_bytes = width as usize * CHANNELS.len() * 4;
+    let block_bytes = 8 + line_bytes;
+    let first_block = header.len() + height as usize * 8;
+    writer.write_all(&header)?;
+    for y in 0..height as usize {
+        writer.write_all(&((first_block + y * block_bytes) as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_bytes);
+    for y in 0..height {
+        line.clear();
+        for (_, channel) in CHANNELS.iter() {
+            for x in 0..width {
+                let value = image.get_pixel(x, y)[*channel];
+                let value = if *channel == 3 {
+                    value as f32 / 255.0
+                } else {
+                    srgb_to_linear(value)
+                };
+                line.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        writer.write_all(&(y as i32).to_le_bytes())?;
+        writer.write_all(&(line_bytes as i32).to_le_bytes())?;
+        writer.write_all(&line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i32_at(bytes: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    fn f32_at(bytes: &[u8], at: usize) -> f32 {
+        f32::from_bits(i32_at(bytes, at) as u32)
+    }
+
+    // Skips the header's null terminated attributes to where the offset table starts
+    fn header_end(bytes: &[u8]) -> usize {
+        let mut at = 8;
+        loop {
+            let name_end = at + bytes[at..].iter().position(|&b| b == 0).unwrap();
+            if name_end == at {
+                return at + 1;
+            }
+            let kind_end =
+                name_end + 1 + bytes[name_end + 1..].iter().position(|&b| b == 0).unwrap();
+            at = kind_end + 5 + i32_at(bytes, kind_end + 1) as usize;
+        }
+    }
+
+    #[test]
+    fn writes_scanline_exr() {
+        let mut image = image::RgbaImage::from_pixel(3, 2, image::Rgba([0, 0, 0, 0]));
+        image.put_pixel(0, 0, image::Rgba([255, 0, 128, 51]));
+        let mut bytes = Vec::new();
+        write_exr(&image, &mut bytes).unwrap();
+
+        assert_eq!(i32_at(&bytes, 0), 20_000_630);
+        assert_eq!(i32_at(&bytes, 4), 2);
+
+        // One offset per scanline, each block a y coordinate, a size and 4 channels of 3 floats
+        let table = header_end(&bytes);
+        let offsets: Vec<usize> = bytes[table..table + 16]
+            .chunks(8)
+            .map(|offset| {
+                offset
+                    .iter()
+                    .rev()
+                    .fold(0, |total, &b| total << 8 | b as usize)
+            })
+            .collect();
+        let block = 8 + 3 * 4 * 4;
+        assert_eq!(offsets, vec![table + 16, table + 16 + block]);
+        assert_eq!(bytes.len(), table + 16 + 2 * block);
+
+        let line = offsets[0];
+        assert_eq!((i32_at(&bytes, line), i32_at(&bytes, line + 4)), (0, 48));
+        // Channels are stored A, B, G, R, each a run of the whole scanline
+        let first_pixel: Vec<f32> = (0..4).map(|c| f32_at(&bytes, line + 8 + c * 12)).collect();
+        assert!((first_pixel[0] - 0.2).abs() < 1e-6);
+        assert!((first_pixel[1] - srgb_to_linear(128)).abs() < 1e-6);
+        assert_eq!(&first_pixel[2..], &[0.0, 1.0]);
+        assert_eq!(f32_at(&bytes, line + 8 + 4), 0.0);
+    }
+}
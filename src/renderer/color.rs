@@ -0,0 +1,42 @@
+// sRGB transfer functions, shared by texture uploads, frame captures, exports and model loading
+
+/// An 8 bit sRGB channel as a linear value in `[0, 1]`.
+pub fn srgb_to_linear(value: u8) -> f32 {
+    srgb_float_to_linear(value as f32 / 255.0)
+}
+
+/// An sRGB channel given as a float in `[0, 1]`, such as an MTL colour, as a linear value.
+pub fn srgb_float_to_linear(value: f32) -> f32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// A linear value as an 8 bit sRGB channel, clamped to `[0, 1]` first.
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let srgb = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_value() {
+        for value in 0..=255 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+        assert_eq!(linear_to_srgb(-1.0), 0);
+        assert_eq!(linear_to_srgb(2.0), 255);
+        assert_eq!(srgb_float_to_linear(128.0 / 255.0), srgb_to_linear(128));
+    }
+}
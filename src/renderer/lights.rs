@@ -0,0 +1,247 @@
+use crate::na;
+use crate::objects::Transform;
+use serde::Deserialize;
+
+/// Lights past this many in a scene are ignored. Matches `MAX_LIGHTS` in `lighting.glsl`.
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot,
+}
+
+/// A light source carried by an object in the scene and placed by that object's transform.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: [f32; 3],
+    /// Point and spot lights fall off with the square of distance, so they need far larger
+    /// intensities than a directional light to reach the same brightness.
+    pub intensity: f32,
+    /// Which way directional and spot lights shine, in the object's own space.
+    pub direction: [f32; 3],
+    /// Distance at which point and spot lights have faded out completely.
+    pub range: f32,
+    /// Spot cone half angles in degrees, full strength inside `inner_angle` fading to nothing
+    /// at `outer_angle`.
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Default for Light {
+    fn default() -> Self {
+        Light {
+            kind: LightKind::Point,
+            color: [1.0, 1.0, 1.0],
+            intensity: 1.0,
+            direction: [0.0, -1.0, 0.0],
+            range: 20.0,
+            inner_angle: 20.0,
+            outer_angle: 30.0,
+        }
+    }
+}
+
+/// A light and the position of the lamp marking it.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LightConfig {
+    pub position: [f32; 3],
+    #[serde(flatten)]
+    pub light: Light,
+}
+
+impl Default for LightConfig {
+    fn default() -> Self {
+        LightConfig {
+            position: [0.0, 0.0, 0.0],
+            light: Light::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LightingConfig {
+    /// Linear RGB added to every lit surface.
+    pub ambient: [f32; 3],
+    pub lights: Vec<LightConfig>,
+}
+
+impl Default for LightingConfig {
+    fn default() -> Self {
+        LightingConfig {
+            ambient: [0.15, 0.17, 0.2],
+            lights: vec![LightConfig {
+                position: [0.0, 60.0, 0.0],
+                light: Light {
+                    kind: LightKind::Directional,
+                    color: [1.0, 0.96, 0.9],
+                    direction: [-0.4, -0.8, -0.3],
+                    ..Light::default()
+                },
+            }],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct LightData {
+    // w is the kind, 0 directional, 1 point or 2 spot
+    position: [f32; 4],
+    // w is the range
+    direction: [f32; 4],
+    // w is the intensity
+    color: [f32; 4],
+    // Cosines of the inner and outer spot angles
+    cone: [f32; 4],
+}
+
+impl LightData {
+    fn new(light: &Light, transform: &Transform) -> LightData {
+        let position = transform.isometry.translation.vector;
+        let direction = transform.isometry.rotation * na::Vector3::from(light.direction);
+        let direction = direction
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(|| -na::Vector3::y());
+        let kind = match light.kind {
+            LightKind::Directional => 0.0,
+            LightKind::Point => 1.0,
+            LightKind::Spot => 2.0,
+        };
+
+        LightData {
+            position: [position.x, position.y, position.z, kind],
+            direction: [direction.x, direction.y, direction.z, light.range],
+            color: [
+                light.color[0],
+                light.color[1],
+                light.color[2],
+                light.intensity,
+            ],
+            cone: [
+                light.inner_angle.to_radians().cos(),
+                light.outer_angle.to_radians().cos(),
+                0.0,
+                0.0,
+            ],
+        }
+    }
+}
+
+/// Every light in the scene, bound next to the globals at set 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Lights {
+    pub ambient: [f32; 4],
+    count: [u32; 4],
+    lights: [LightData; MAX_LIGHTS],
+}
+
+unsafe impl bytemuck::Pod for Lights {}
+unsafe impl bytemuck::Zeroable for Lights {}
+
+impl Lights {
+    pub fn new(ambient: [f32; 3]) -> Lights {
+        Lights {
+            ambient: [ambient[0], ambient[1], ambient[2], 1.0],
+            count: [0; 4],
+            lights: [LightData::default(); MAX_LIGHTS],
+        }
+    }
+
+    /// Replaces the lights, keeping the first `MAX_LIGHTS`.
+    pub fn set<'a>(&mut self, lights: impl Iterator<Item = (&'a Light, &'a Transform)>) {
+        let mut count = 0;
+        for (data, (light, transform)) in self.lights.iter_mut().zip(lights) {
+            *data = LightData::new(light, transform);
+            count += 1;
+        }
+        self.count[0] = count;
+    }
+
+    /// Index and direction of the first directional light, the one that casts shadows.
+    pub fn sun(&self) -> Option<(usize, na::Vector3<f32>)> {
+        self.lights[..self.count[0] as usize]
+            .iter()
+            .position(|light| light.position[3] == 0.0)
+            .map(|index| {
+                let [x, y, z, _] = self.lights[index].direction;
+                (index, na::Vector3::new(x, y, z))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    #[test]
+    fn packs_kind_and_range() {
+        let point = Light {
+            range: 7.5,
+            ..Light::default()
+        };
+        let spot = Light {
+            kind: LightKind::Spot,
+            inner_angle: 60.0,
+            outer_angle: 90.0,
+            ..Light::default()
+        };
+        let placed = Transform::from_parts(
+            na::Translation3::new(1.0, 2.0, 3.0),
+            na::UnitQuaternion::identity(),
+            1.0,
+        );
+        let mut lights = Lights::new([0.1; 3]);
+        lights.set(vec![(&point, &placed), (&spot, &placed)].into_iter());
+
+        assert_eq!(lights.count[0], 2);
+        assert_eq!(lights.lights[0].position, [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(lights.lights[0].direction, [0.0, -1.0, 0.0, 7.5]);
+        assert_eq!(lights.lights[1].position[3], 2.0);
+        assert!((lights.lights[1].cone[0] - 0.5).abs() < 1e-6);
+        assert!(lights.lights[1].cone[1].abs() < 1e-6);
+    }
+
+    #[test]
+    fn keeps_the_first_max_lights() {
+        let light = Light::default();
+        let transform = Transform::identity();
+        let mut lights = Lights::new([0.1; 3]);
+        lights.set(std::iter::repeat((&light, &transform)).take(MAX_LIGHTS + 3));
+        assert_eq!(lights.count[0] as usize, MAX_LIGHTS);
+    }
+
+    #[test]
+    fn sun_is_the_first_directional_light() {
+        let point = Light::default();
+        let sun = Light {
+            kind: LightKind::Directional,
+            direction: [1.0, 0.0, 0.0],
+            ..Light::default()
+        };
+        let turned = Transform::from_parts(
+            na::Translation3::identity(),
+            na::UnitQuaternion::from_axis_angle(&na::Vector3::y_axis(), FRAC_PI_2),
+            1.0,
+        );
+        let mut lights = Lights::new([0.1; 3]);
+        lights.set(vec![(&point, &turned), (&sun, &turned), (&sun, &turned)].into_iter());
+
+        let (index, direction) = lights.sun().unwrap();
+        assert_eq!(index, 1);
+        assert!((direction - na::Vector3::new(0.0, 0.0, -1.0)).norm() < 1e-6);
+
+        // Lights left over from a longer list don't count
+        lights.set(vec![(&point, &turned)].into_iter());
+        assert!(lights.sun().is_none());
+    }
+}
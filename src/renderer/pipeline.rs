@@ -1,30 +1,115 @@
+use super::DepthTexture;
 use crate::Result;
 use std::{fs::File, io::Read, path::Path};
 
+#[derive(Clone, Debug)]
+pub struct PipelineOptions {
+    pub color_blend: wgpu::BlendDescriptor,
+    pub alpha_blend: wgpu::BlendDescriptor,
+    pub cull_mode: wgpu::CullMode,
+    /// Written for a standard depth range, the renderer flips it when reverse-Z is on.
+    pub depth_compare: wgpu::CompareFunction,
+    pub depth_write: bool,
+    pub depth_bias: i32,
+    pub depth_bias_slope_scale: f32,
+}
+
+impl Default for PipelineOptions {
+    fn default() -> Self {
+        PipelineOptions {
+            color_blend: wgpu::BlendDescriptor::REPLACE,
+            alpha_blend: wgpu::BlendDescriptor::REPLACE,
+            cull_mode: wgpu::CullMode::Back,
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_write: true,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+        }
+    }
+}
+
+impl PipelineOptions {
+    /// Alpha blended, for transparent surfaces drawn after the opaque ones. These are depth
+    /// tested against the opaque geometry but don't write depth themselves.
+    pub fn transparent() -> Self {
+        let blend = wgpu::BlendDescriptor {
+            src_factor: wgpu::BlendFactor::SrcAlpha,
+            dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+            operation: wgpu::BlendOperation::Add,
+        };
+        PipelineOptions {
+            color_blend: blend.clone(),
+            alpha_blend: blend,
+            depth_write: false,
+            ..Default::default()
+        }
+    }
+
+    /// Depth biased and drawing both faces, for rendering into shadow maps. Heightfields aren't
+    /// closed, so their back faces have to cast shadows too.
+    pub fn shadow(depth_bias: i32, slope_bias: f32) -> Self {
+        PipelineOptions {
+            cull_mode: wgpu::CullMode::None,
+            depth_bias,
+            depth_bias_slope_scale: slope_bias,
+            ..Default::default()
+        }
+    }
+
+    /// The same options with depth compares flipped for reverse-Z.
+    pub fn reverse_z(&self) -> Self {
+        use wgpu::CompareFunction::*;
+
+        let depth_compare = match self.depth_compare {
+            Less => Greater,
+            LessEqual => GreaterEqual,
+            Greater => Less,
+            GreaterEqual => LessEqual,
+            other => other,
+        };
+        PipelineOptions {
+            depth_compare,
+            ..self.clone()
+        }
+    }
+}
+
+/// Builds a render pipeline drawing into `format`, or a depth-only one without a fragment
+/// shader when `frag_file` is `None`.
 pub fn create_pipeline(
     vert_file: &Path,
-    frag_file: &Path,
-    vertex_buffer_descriptor: wgpu::VertexBufferDescriptor,
+    frag_file: Option<&Path>,
+    vertex_buffers: &[wgpu::VertexBufferDescriptor],
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    options: &PipelineOptions,
     format: wgpu::TextureFormat,
     device: &wgpu::Device,
 ) -> Result<wgpu::RenderPipeline> {
-    let mut vs_src = String::new();
-    File::open(vert_file)?.read_to_string(&mut vs_src)?;
-
-    let mut fs_src = String::new();
-    File::open(frag_file)?.read_to_string(&mut fs_src)?;
-
+    let vs_src = read_shader(vert_file)?;
     let vs_spriv = glsl_to_spirv::compile(&vs_src, glsl_to_spirv::ShaderType::Vertex)?;
-    let fs_spriv = glsl_to_spirv::compile(&fs_src, glsl_to_spirv::ShaderType::Fragment)?;
-
     let vs_data = wgpu::read_spirv(vs_spriv)?;
-    let fs_data = wgpu::read_spirv(fs_spriv)?;
-
     let vs_module = device.create_shader_module(&vs_data);
-    let fs_module = device.create_shader_module(&fs_data);
-    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        bind_group_layouts: &[],
-    });
+
+    let fs_module = match frag_file {
+        Some(frag_file) => {
+            let fs_src = read_shader(frag_file)?;
+            let fs_spriv = glsl_to_spirv::compile(&fs_src, glsl_to_spirv::ShaderType::Fragment)?;
+            let fs_data = wgpu::read_spirv(fs_spriv)?;
+            Some(device.create_shader_module(&fs_data))
+        }
+        None => None,
+    };
+    let color_states = match fs_module {
+        Some(_) => vec![wgpu::ColorStateDescriptor {
+            format: format,
+            color_blend: options.color_blend.clone(),
+            alpha_blend: options.alpha_blend.clone(),
+            write_mask: wgpu::ColorWrite::ALL,
+        }],
+        None => Vec::new(),
+    };
+    let layout =
+        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor { bind_group_layouts });
 
     let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         layout: &layout,
@@ -32,29 +117,34 @@ pub fn create_pipeline(
             module: &vs_module,
             entry_point: "main",
         },
-        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-            module: &fs_module,
-            entry_point: "main",
-        }),
+        fragment_stage: fs_module
+            .as_ref()
+            .map(|module| wgpu::ProgrammableStageDescriptor {
+                module,
+                entry_point: "main",
+            }),
         rasterization_state: Some(wgpu::RasterizationStateDescriptor {
             front_face: wgpu::FrontFace::Ccw,
-            cull_mode: wgpu::CullMode::Back,
-            depth_bias: 0,
-            depth_bias_slope_scale: 0.0,
+            cull_mode: options.cull_mode,
+            depth_bias: options.depth_bias,
+            depth_bias_slope_scale: options.depth_bias_slope_scale,
             depth_bias_clamp: 0.0,
         }),
-        color_states: &[wgpu::ColorStateDescriptor {
-            format: format,
-            color_blend: wgpu::BlendDescriptor::REPLACE,
-            alpha_blend: wgpu::BlendDescriptor::REPLACE,
-            write_mask: wgpu::ColorWrite::ALL,
-        }],
+        color_states: &color_states,
         vertex_state: wgpu::VertexStateDescriptor {
             index_format: wgpu::IndexFormat::Uint16,
-            vertex_buffers: &[vertex_buffer_descriptor],
+            vertex_buffers,
         },
         primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-        depth_stencil_state: None,
+        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+            format: DepthTexture::FORMAT,
+            depth_write_enabled: options.depth_write,
+            depth_compare: options.depth_compare,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }),
         sample_count: 1,
         sample_mask: !0,
         alpha_to_coverage_enabled: false,
@@ -62,3 +152,26 @@ pub fn create_pipeline(
 
     Ok(render_pipeline)
 }
+
+// Reads GLSL source, pasting in the files named by `#include "file"` lines. Included paths are
+// relative to the including file.
+fn read_shader(path: &Path) -> Result<String> {
+    let mut source = String::new();
+    File::open(path)
+        .map_err(|e| format!("Failed to open shader {}: {}", path.display(), e))?
+        .read_to_string(&mut source)?;
+
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut expanded = String::with_capacity(source.len());
+    for line in source.lines() {
+        match line.trim().strip_prefix("#include") {
+            Some(name) => {
+                let include = directory.join(name.trim().trim_matches('"'));
+                expanded.push_str(&read_shader(&include)?);
+            }
+            None => expanded.push_str(line),
+        }
+        expanded.push('\n');
+    }
+    Ok(expanded)
+}
@@ -0,0 +1,428 @@
+use super::uniforms::DYNAMIC_ALIGNMENT;
+use super::{DepthTexture, ModelBuffer};
+use crate::na;
+use crate::objects::Camera;
+use crate::scene::Node;
+use serde::Deserialize;
+use std::mem;
+
+/// Most cascades a shadow map can have. Matches `MAX_CASCADES` in `lighting.glsl`.
+pub const MAX_CASCADES: usize = 4;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    /// Width and height of each cascade's depth map in texels.
+    pub resolution: u32,
+    pub cascades: usize,
+    /// Shadows end this far from the camera.
+    pub distance: f32,
+    /// How the cascades split the view, 0 for even slices up to 1 for logarithmic ones that
+    /// keep more detail close to the camera.
+    pub split_lambda: f32,
+    /// PCF samples this many texels either side of the centre, 0 for hard shadows.
+    pub pcf_radius: u32,
+    pub depth_bias: i32,
+    pub slope_bias: f32,
+    /// Shadow lookups are pushed this many texels out along the surface normal.
+    pub normal_offset: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        ShadowConfig {
+            enabled: true,
+            resolution: 2048,
+            cascades: 3,
+            distance: 300.0,
+            split_lambda: 0.6,
+            pcf_radius: 1,
+            depth_bias: 2,
+            slope_bias: 2.0,
+            normal_offset: 1.5,
+        }
+    }
+}
+
+/// What the lit shaders need to look up shadows, bound with the globals at set 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowUniforms {
+    view_proj: [[[f32; 4]; 4]; MAX_CASCADES],
+    // Bounding sphere of each cascade, centre and radius
+    spheres: [[f32; 4]; MAX_CASCADES],
+    // Texel size in texture coordinates, PCF radius and normal offset
+    params: [f32; 4],
+    // Cascade count, 0 when nothing is shadowed, and the index of the shadowed light
+    info: [i32; 4],
+}
+
+unsafe impl bytemuck::Pod for ShadowUniforms {}
+unsafe impl bytemuck::Zeroable for ShadowUniforms {}
+
+// One cascade's light matrix, picked in the shadow pass by dynamic offset
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct CascadeUniform {
+    view_proj: [[f32; 4]; 4],
+}
+
+unsafe impl bytemuck::Pod for CascadeUniform {}
+unsafe impl bytemuck::Zeroable for CascadeUniform {}
+
+/// Cascaded shadow maps for one directional light. Every cascade is a layer of one depth
+/// texture array, fitted around a slice of the camera's view.
+pub struct ShadowMap {
+    resolution: u32,
+    cascades: usize,
+    distance: f32,
+    split_lambda: f32,
+    enabled: bool,
+    _texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    layer_views: Vec<wgpu::TextureView>,
+    pub sampler: wgpu::Sampler,
+    pub uniforms: ShadowUniforms,
+    cascade_buffer: wgpu::Buffer,
+    /// Set 0 of the depth-only pipelines.
+    pub pass_layout: wgpu::BindGroupLayout,
+    pass_bind_group: wgpu::BindGroup,
+    pub depth_bias: i32,
+    pub slope_bias: f32,
+}
+
+impl ShadowMap {
+    pub fn new(device: &wgpu::Device, config: &ShadowConfig) -> Self {
+        let cascades = config.cascades.clamp(1, MAX_CASCADES);
+        // Keep a token texture around when disabled so the bind group stays valid
+        let resolution = if config.enabled {
+            config.resolution.max(1)
+        } else {
+            1
+        };
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow map"),
+            size: wgpu::Extent3d {
+                width: resolution,
+                height: resolution,
+                depth: 1,
+            },
+            array_layer_count: cascades as u32,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DepthTexture::FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+
+        let view_descriptor =
+            |dimension, base_array_layer, array_layer_count| wgpu::TextureViewDescriptor {
+                format: DepthTexture::FORMAT,
+                dimension,
+                aspect: wgpu::TextureAspect::DepthOnly,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer,
+                array_layer_count,
+            };
+        let view = texture.create_view(&view_descriptor(
+            wgpu::TextureViewDimension::D2Array,
+            0,
+            cascades as u32,
+        ));
+        let layer_views = (0..cascades as u32)
+            .map(|layer| {
+                texture.create_view(&view_descriptor(wgpu::TextureViewDimension::D2, layer, 1))
+            })
+            .collect();
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare: wgpu::CompareFunction::LessEqual,
+        });
+
+        let cascade_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadow cascades"),
+            size: MAX_CASCADES as wgpu::BufferAddress * DYNAMIC_ALIGNMENT,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let pass_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: true },
+            }],
+            label: Some("shadow pass"),
+        });
+        let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &pass_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &cascade_buffer,
+                    range: 0..mem::size_of::<CascadeUniform>() as wgpu::BufferAddress,
+                },
+            }],
+            label: Some("shadow pass"),
+        });
+
+        let uniforms = ShadowUniforms {
+            view_proj: [na::Matrix4::identity().into(); MAX_CASCADES],
+            spheres: [[0.0; 4]; MAX_CASCADES],
+            params: [
+                1.0 / resolution as f32,
+                config.pcf_radius as f32,
+                config.normal_offset,
+                0.0,
+            ],
+            info: [0, -1, 0, 0],
+        };
+
+        ShadowMap {
+            resolution,
+            cascades,
+            distance: config.distance,
+            split_lambda: config.split_lambda,
+            enabled: config.enabled,
+            _texture: texture,
+            view,
+            layer_views,
+            sampler,
+            uniforms,
+            cascade_buffer,
+            pass_layout,
+            pass_bind_group,
+            depth_bias: config.depth_bias,
+            slope_bias: config.slope_bias,
+        }
+    }
+
+    /// Whether `update` found a light to cast shadows from.
+    pub fn is_active(&self) -> bool {
+        self.uniforms.info[0] > 0
+    }
+
+    /// Refits the cascades around the camera's view for the light at `light_index`, shining
+    /// along `direction`. Without a light nothing is shadowed.
+    pub fn update(&mut self, camera: &Camera, light: Option<(usize, na::Vector3<f32>)>) {
+        let (light_index, direction) = match light {
+            Some((index, direction)) if self.enabled => (index, direction.normalize()),
+            _ => {
+                self.uniforms.info = [0, -1, 0, 0];
+                return;
+            }
+        };
+
+        let near = camera.znear;
+        let far = self.distance.min(camera.zfar).max(near);
+        let splits = split_distances(near, far, self.cascades, self.split_lambda);
+        let mut split_near = near;
+        for (cascade, split_far) in splits.into_iter().enumerate() {
+            let (center, radius) = slice_sphere(camera, split_near, split_far);
+            let (view_proj, center) = self.light_view_proj(center, radius, direction, far);
+            self.uniforms.view_proj[cascade] = view_proj.into();
+            self.uniforms.spheres[cascade] = [center.x, center.y, center.z, radius];
+
+            split_near = split_far;
+        }
+        self.uniforms.info = [self.cascades as i32, light_index as i32, 0, 0];
+    }
+
+    // Orthographic light projection around a cascade's sphere, and the sphere's centre after
+    // snapping to whole texels so the shadow edges don't crawl as the camera moves
+    fn light_view_proj(
+        &self,
+        center: na::Point3<f32>,
+        radius: f32,
+        direction: na::Vector3<f32>,
+        caster_distance: f32,
+    ) -> (na::Matrix4<f32>, na::Point3<f32>) {
+        let up = if direction.y.abs() > 0.99 {
+            na::Vector3::z()
+        } else {
+            na::Vector3::y()
+        };
+        let rotation = na::Isometry3::look_at_rh(&na::Point3::origin(), &direction.into(), &up);
+
+        let texel = 2.0 * radius / self.resolution as f32;
+        let mut snapped = rotation * center;
+        snapped.x = (snapped.x / texel).floor() * texel;
+        snapped.y = (snapped.y / texel).floor() * texel;
+        let center = rotation.inverse() * snapped;
+
+        // Back off far enough to catch casters between the light and the slice
+        let back = radius + caster_distance;
+        let eye = center - direction * back;
+        let view = na::Isometry3::look_at_rh(&eye, &center, &up);
+        let depth = back + radius;
+
+        #[rustfmt::skip]
+        let projection = na::Matrix4::new(
+            1.0 / radius, 0.0, 0.0, 0.0,
+            0.0, 1.0 / radius, 0.0, 0.0,
+            0.0, 0.0, -1.0 / depth, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        (projection * view.to_homogeneous(), center)
+    }
+
+    pub fn upload(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let stride = DYNAMIC_ALIGNMENT as usize;
+        let mut data = vec![0u8; self.cascades * stride];
+        for (chunk, view_proj) in data.chunks_exact_mut(stride).zip(&self.uniforms.view_proj) {
+            let cascade = CascadeUniform {
+                view_proj: *view_proj,
+            };
+            chunk[..mem::size_of::<CascadeUniform>()].copy_from_slice(bytemuck::bytes_of(&cascade));
+        }
+
+        let staging_buffer = device.create_buffer_with_data(&data, wgpu::BufferUsage::COPY_SRC);
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer,
+            0,
+            &self.cascade_buffer,
+            0,
+            data.len() as wgpu::BufferAddress,
+        );
+    }
+
+    /// Draws every node with a shadow pipeline into each cascade. `nodes` must be in the order
+    /// their models were uploaded to `models`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        nodes: &[&Node],
+        models: &ModelBuffer,
+    ) {
+        if !self.is_active() {
+            return;
+        }
+
+        for (cascade, layer_view) in self.layer_views.iter().enumerate() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: layer_view,
+                    depth_load_op: wgpu::LoadOp::Clear,
+                    depth_store_op: wgpu::StoreOp::Store,
+                    clear_depth: 1.0,
+                    stencil_load_op: wgpu::LoadOp::Clear,
+                    stencil_store_op: wgpu::StoreOp::Store,
+                    clear_stencil: 0,
+                }),
+            });
+            let offset =
+                (cascade as wgpu::BufferAddress * DYNAMIC_ALIGNMENT) as wgpu::DynamicOffset;
+            render_pass.set_bind_group(0, &self.pass_bind_group, &[offset]);
+
+            let mut bound: Option<&wgpu::RenderPipeline> = None;
+            for (index, node) in nodes.iter().enumerate() {
+                let pipeline = match node.object.shadow_pipeline() {
+                    Some(pipeline) => pipeline,
+                    None => continue,
+                };
+                if !matches!(bound, Some(bound) if std::ptr::eq(bound, pipeline)) {
+                    render_pass.set_pipeline(pipeline);
+                    bound = Some(pipeline);
+                }
+                render_pass.set_bind_group(1, &models.bind_group, &[ModelBuffer::offset(index)]);
+                node.object.render(&mut render_pass);
+            }
+        }
+    }
+}
+
+// Far distance of each cascade, a blend of even and logarithmic slices of `near` to `far`
+fn split_distances(near: f32, far: f32, cascades: usize, lambda: f32) -> Vec<f32> {
+    (1..=cascades)
+        .map(|cascade| {
+            let fraction = cascade as f32 / cascades as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let uniform = near + (far - near) * fraction;
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
+
+// Bounding sphere of the part of the camera's view between distances `near` and `far`
+fn slice_sphere(camera: &Camera, near: f32, far: f32) -> (na::Point3<f32>, f32) {
+    let tan_y = (camera.fovy / 2.0).tan();
+    let tan_x = tan_y * camera.aspect;
+    let (forward, right, up) = (camera.forward(), camera.right(), camera.up());
+
+    let mut corners = Vec::with_capacity(8);
+    for &distance in &[near, far] {
+        for &(x, y) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            corners.push(
+                camera.position
+                    + forward * distance
+                    + right * (x * tan_x * distance)
+                    + up * (y * tan_y * distance),
+            );
+        }
+    }
+
+    let center = corners
+        .iter()
+        .fold(na::Vector3::zeros(), |sum, corner| sum + corner.coords)
+        / corners.len() as f32;
+    let center = na::Point3::from(center);
+    let radius = corners
+        .iter()
+        .map(|corner| na::distance(&center, corner))
+        .fold(0.0, f32::max);
+    // Rounded up so the sphere, and with it the texel size, only changes in steps
+    (center, (radius * 16.0).ceil() / 16.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::CameraConfig;
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn blends_even_and_logarithmic_splits() {
+        assert_close(
+            &split_distances(1.0, 101.0, 4, 0.0),
+            &[26.0, 51.0, 76.0, 101.0],
+        );
+        assert_close(&split_distances(1.0, 100.0, 2, 1.0), &[10.0, 100.0]);
+        assert_close(&split_distances(1.0, 100.0, 2, 0.5), &[30.25, 100.0]);
+    }
+
+    #[test]
+    fn slice_sphere_bounds_the_slice() {
+        let config = CameraConfig {
+            fovy: 90.0,
+            position: [0.0, 0.0, 0.0],
+            target: [0.0, 0.0, -1.0],
+            ..CameraConfig::default()
+        };
+        let camera = Camera::new(&config, 2.0);
+        let (center, radius) = slice_sphere(&camera, 1.0, 3.0);
+
+        assert!(center.x.abs() < 1e-5 && center.y.abs() < 1e-5);
+        assert!((center.z + 2.0).abs() < 1e-5);
+        assert_eq!(radius * 16.0, (radius * 16.0).round());
+        // The far corners are the furthest out
+        let corner = na::Point3::new(6.0, 3.0, -3.0);
+        let distance = na::distance(&center, &corner);
+        assert!(radius >= distance && radius < distance + 1.0 / 16.0);
+    }
+}
@@ -0,0 +1,384 @@
+use super::{pipeline, PipelineOptions};
+use crate::na;
+use crate::objects::Camera;
+use crate::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SkyConfig {
+    /// Draw the sky behind everything instead of clearing to `bg_color`.
+    pub enabled: bool,
+    /// Degrees the noon sun stands away from straight overhead, towards -z.
+    pub sun_tilt: f32,
+    /// Degrees the sun's path is turned about the vertical. At 0 it rises in +x.
+    pub sun_azimuth: f32,
+    /// Angular diameter of the sun disc in degrees.
+    pub sun_size: f32,
+    /// The moon stays opposite the sun, full every night.
+    pub moon_size: f32,
+    /// Linear RGB of moonlight, relative to the sun light's own colour and intensity.
+    pub moon_color: [f32; 3],
+    pub moon_intensity: f32,
+    /// Fraction of the ambient light left at night.
+    pub night_ambient: f32,
+    /// Haze, 1 for a clear sky and higher for thicker air with a wider glow around the sun.
+    pub turbidity: f32,
+    pub exposure: f32,
+    /// Fog density at `fog_height`, thinning exponentially above it.
+    pub fog_density: f32,
+    pub fog_height: f32,
+    /// How quickly fog thins with height, per world unit.
+    pub fog_falloff: f32,
+}
+
+impl Default for SkyConfig {
+    fn default() -> Self {
+        SkyConfig {
+            enabled: true,
+            sun_tilt: 30.0,
+            sun_azimuth: 0.0,
+            sun_size: 0.6,
+            moon_size: 0.6,
+            moon_color: [0.55, 0.65, 0.9],
+            moon_intensity: 0.12,
+            night_ambient: 0.2,
+            turbidity: 2.0,
+            exposure: 1.0,
+            fog_density: 0.004,
+            fog_height: 0.0,
+            fog_falloff: 0.05,
+        }
+    }
+}
+
+/// How the sky lights the scene at the current time of day, the sun by day and the moon by
+/// night.
+#[derive(Clone, Copy, Debug)]
+pub struct SkyLight {
+    /// The way the light travels.
+    pub direction: na::Vector3<f32>,
+    /// Multiplies the configured light colour, dimming and reddening the sun near the horizon.
+    pub color: [f32; 3],
+    /// Multiplies the configured ambient light.
+    pub ambient: f32,
+}
+
+/// Sun and fog settings shared at set 0, binding 5, for the sky pass and for shaders that
+/// include `atmosphere.glsl`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Atmosphere {
+    // View ray basis for the sky pass, forward plus right and up scaled to the screen edges
+    forward: [f32; 4],
+    right: [f32; 4],
+    up: [f32; 4],
+    // Towards the sun and moon, w is the cosine of the disc's angular radius
+    sun_direction: [f32; 4],
+    moon_direction: [f32; 4],
+    // Turbidity, exposure and whether the sky is drawn
+    scattering: [f32; 4],
+    // Density, base height and height falloff
+    fog: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for Atmosphere {}
+unsafe impl bytemuck::Zeroable for Atmosphere {}
+
+impl Atmosphere {
+    pub fn new() -> Atmosphere {
+        Atmosphere {
+            forward: [0.0, 0.0, -1.0, 0.0],
+            right: [1.0, 0.0, 0.0, 0.0],
+            up: [0.0, 1.0, 0.0, 0.0],
+            sun_direction: [0.0, 1.0, 0.0, 1.0],
+            moon_direction: [0.0, -1.0, 0.0, 1.0],
+            scattering: [1.0, 1.0, 0.0, 0.0],
+            fog: [0.0; 4],
+        }
+    }
+}
+
+/// An analytic daylight sky drawn behind the scene, with the sun placed by the time of day.
+pub struct Sky {
+    pub enabled: bool,
+    daylight: Daylight,
+    sun_size: f32,
+    moon_size: f32,
+    exposure: f32,
+    fog: [f32; 3],
+    pipeline: wgpu::RenderPipeline,
+}
+
+// The sun's path and the light from it and the moon, kept apart from the pipeline
+struct Daylight {
+    time_of_day: f32,
+    sun_tilt: f32,
+    sun_azimuth: f32,
+    moon_color: [f32; 3],
+    moon_intensity: f32,
+    night_ambient: f32,
+    turbidity: f32,
+}
+
+impl Sky {
+    /// `frame_layout` is the renderer's set 0 layout, the sky reads `Atmosphere` from it.
+    pub fn new(
+        config: &SkyConfig,
+        frame_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        reverse_z: bool,
+        device: &wgpu::Device,
+    ) -> Result<Sky> {
+        // Behind everything, so it neither tests nor writes depth
+        let options = PipelineOptions {
+            cull_mode: wgpu::CullMode::None,
+            depth_compare: wgpu::CompareFunction::Always,
+            depth_write: false,
+            ..Default::default()
+        };
+        let options = if reverse_z {
+            options.reverse_z()
+        } else {
+            options
+        };
+        let pipeline = pipeline::create_pipeline(
+            Path::new("./resources/shaders/sky.vert"),
+            Some(Path::new("./resources/shaders/sky.frag")),
+            &[],
+            &[frame_layout],
+            &options,
+            format,
+            device,
+        )?;
+
+        Ok(Sky {
+            enabled: config.enabled,
+            daylight: Daylight::new(config),
+            sun_size: config.sun_size.to_radians(),
+            moon_size: config.moon_size.to_radians(),
+            exposure: config.exposure,
+            fog: [config.fog_density, config.fog_height, config.fog_falloff],
+            pipeline,
+        })
+    }
+
+    /// Wraps into 0..24 hours.
+    pub fn set_time_of_day(&mut self, hours: f32) {
+        self.daylight.time_of_day = hours.rem_euclid(24.0);
+    }
+
+    /// The way sunlight travels, pointing down by day and up through the ground at night.
+    pub fn sun_direction(&self) -> na::Vector3<f32> {
+        self.daylight.sun_direction()
+    }
+
+    /// The sun while it's up, otherwise the moon. Both fade out at the horizon, so switching
+    /// between them doesn't pop.
+    pub fn light(&self) -> SkyLight {
+        self.daylight.light()
+    }
+
+    /// Sky and fog values for this frame, seen from `camera` with the sun shining along
+    /// `sun_direction`.
+    pub fn atmosphere(&self, camera: &Camera, sun_direction: na::Vector3<f32>) -> Atmosphere {
+        let tan_y = (camera.fovy / 2.0).tan();
+        let tan_x = tan_y * camera.aspect;
+        let (forward, right, up) = (
+            camera.forward(),
+            camera.right() * tan_x,
+            camera.up() * tan_y,
+        );
+        let to_sun = -sun_direction
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(|| -na::Vector3::y());
+
+        Atmosphere {
+            forward: forward.to_homogeneous().into(),
+            right: right.to_homogeneous().into(),
+            up: up.to_homogeneous().into(),
+            sun_direction: [to_sun.x, to_sun.y, to_sun.z, (self.sun_size / 2.0).cos()],
+            moon_direction: [
+                -to_sun.x,
+                -to_sun.y,
+                -to_sun.z,
+                (self.moon_size / 2.0).cos(),
+            ],
+            scattering: [
+                self.daylight.turbidity,
+                self.exposure,
+                if self.enabled { 1.0 } else { 0.0 },
+                0.0,
+            ],
+            fog: [self.fog[0], self.fog[1], self.fog[2], 0.0],
+        }
+    }
+
+    /// Draws the sky over the whole target. Set 0 has to be bound already.
+    pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.enabled {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+impl Daylight {
+    fn new(config: &SkyConfig) -> Daylight {
+        Daylight {
+            time_of_day: 12.0,
+            sun_tilt: config.sun_tilt.to_radians(),
+            sun_azimuth: config.sun_azimuth.to_radians(),
+            moon_color: config.moon_color,
+            moon_intensity: config.moon_intensity,
+            night_ambient: config.night_ambient,
+            turbidity: config.turbidity.max(1.0),
+        }
+    }
+
+    fn sun_direction(&self) -> na::Vector3<f32> {
+        -sun_position(self.time_of_day, self.sun_tilt, self.sun_azimuth)
+    }
+
+    // The moon stays opposite the sun, so its light travels towards it
+    fn moon_direction(&self) -> na::Vector3<f32> {
+        sun_position(self.time_of_day, self.sun_tilt, self.sun_azimuth)
+    }
+
+    fn light(&self) -> SkyLight {
+        let to_sun = -self.sun_direction();
+        let daylight = smoothstep(-0.1, 0.1, to_sun.y);
+        let ambient = self.night_ambient + (1.0 - self.night_ambient) * daylight;
+
+        if to_sun.y >= 0.0 {
+            let fade = smoothstep(-0.02, 0.08, to_sun.y);
+            let tint = sunlight_tint(to_sun.y, self.turbidity);
+            SkyLight {
+                direction: -to_sun,
+                color: [tint[0] * fade, tint[1] * fade, tint[2] * fade],
+                ambient,
+            }
+        } else {
+            let fade = smoothstep(-0.02, 0.08, -to_sun.y) * self.moon_intensity;
+            SkyLight {
+                direction: self.moon_direction(),
+                color: [
+                    self.moon_color[0] * fade,
+                    self.moon_color[1] * fade,
+                    self.moon_color[2] * fade,
+                ],
+                ambient,
+            }
+        }
+    }
+}
+
+// Unit vector towards the sun. It circles once a day, rising in +x at 6, highest at 12 leaning
+// `tilt` towards -z and setting in -x at 18, before the whole path is turned by `azimuth`.
+fn sun_position(hours: f32, tilt: f32, azimuth: f32) -> na::Vector3<f32> {
+    let angle = (hours - 12.0) / 24.0 * std::f32::consts::PI * 2.0;
+    let position = na::Vector3::new(
+        -angle.sin(),
+        angle.cos() * tilt.cos(),
+        -angle.cos() * tilt.sin(),
+    );
+    na::Rotation3::from_axis_angle(&na::Vector3::y_axis(), azimuth) * position
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Sunlight through the air at `cos_zenith` relative to straight overhead, the same extinction
+// as `transmittance` in atmosphere.glsl
+fn sunlight_tint(cos_zenith: f32, turbidity: f32) -> [f32; 3] {
+    const RAYLEIGH: [f32; 3] = [0.058, 0.135, 0.331];
+    const MIE: f32 = 0.021;
+
+    let air_mass = |cos_zenith: f32| {
+        let zenith = cos_zenith.clamp(0.0, 1.0).acos().to_degrees();
+        1.0 / (cos_zenith.max(0.0) + 0.50572 * (96.07995 - zenith).powf(-1.6364))
+    };
+    let (slant, overhead) = (air_mass(cos_zenith), air_mass(1.0));
+    let mut tint = [0.0; 3];
+    for (tint, rayleigh) in tint.iter_mut().zip(&RAYLEIGH) {
+        let extinction = rayleigh + MIE * turbidity;
+        *tint = (-extinction * (slant - overhead)).exp();
+    }
+    tint
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: na::Vector3<f32>, expected: [f32; 3]) {
+        let expected = na::Vector3::from(expected);
+        assert!(
+            (actual - expected).norm() < 1e-5,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn daylight(hours: f32) -> Daylight {
+        let config = SkyConfig {
+            sun_tilt: 0.0,
+            ..SkyConfig::default()
+        };
+        Daylight {
+            time_of_day: hours,
+            ..Daylight::new(&config)
+        }
+    }
+
+    #[test]
+    fn sun_circles_once_a_day() {
+        assert_close(sun_position(6.0, 0.0, 0.0), [1.0, 0.0, 0.0]);
+        assert_close(sun_position(12.0, 0.0, 0.0), [0.0, 1.0, 0.0]);
+        assert_close(sun_position(18.0, 0.0, 0.0), [-1.0, 0.0, 0.0]);
+        assert_close(sun_position(0.0, 0.0, 0.0), [0.0, -1.0, 0.0]);
+
+        let (sin, cos) = 30_f32.to_radians().sin_cos();
+        assert_close(
+            sun_position(12.0, 30_f32.to_radians(), 0.0),
+            [0.0, cos, -sin],
+        );
+        assert_close(
+            sun_position(6.0, 0.0, 90_f32.to_radians()),
+            [0.0, 0.0, -1.0],
+        );
+    }
+
+    #[test]
+    fn smoothstep_eases_between_the_edges() {
+        assert_eq!(smoothstep(0.0, 1.0, -1.0), 0.0);
+        assert_eq!(smoothstep(0.0, 1.0, 2.0), 1.0);
+        assert_eq!(smoothstep(0.0, 1.0, 0.5), 0.5);
+        assert!((smoothstep(0.0, 1.0, 0.25) - 0.156_25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn moon_takes_over_below_the_horizon() {
+        let noon = daylight(12.0).light();
+        assert_close(noon.direction, [0.0, -1.0, 0.0]);
+        assert!(noon.color.iter().all(|&channel| channel > 0.8));
+        assert_eq!(noon.ambient, 1.0);
+
+        let config = SkyConfig::default();
+        let midnight = daylight(0.0).light();
+        assert_close(midnight.direction, [0.0, -1.0, 0.0]);
+        for (channel, moon) in midnight.color.iter().zip(&config.moon_color) {
+            assert!((channel - moon * config.moon_intensity).abs() < 1e-6);
+        }
+        assert_eq!(midnight.ambient, config.night_ambient);
+
+        // Just after sunset the moon is low in the east, shining west
+        let dusk = daylight(18.5).light();
+        assert!(dusk.direction.x < 0.0 && dusk.direction.y < 0.0);
+        assert!(dusk.color[2] > dusk.color[0]);
+    }
+}
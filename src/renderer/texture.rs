@@ -1,27 +1,68 @@
+use super::linear_to_srgb;
 use crate::Result;
-use image::GenericImageView;
-use std::path::Path;
 
+/// A sampled 2D colour texture with its bind group, laid out by `Texture::bind_group_layout`.
 pub struct Texture {
-    pub diffuse_texture: wgpu::Texture,
-    pub diffuse_texture_view: wgpu::TextureView,
-    pub diffuse_sampler: wgpu::Sampler,
+    _diffuse_texture: wgpu::Texture,
+    _diffuse_texture_view: wgpu::TextureView,
+    _diffuse_sampler: wgpu::Sampler,
     pub diffuse_bind_group: wgpu::BindGroup,
-    pub diffuse_bind_group_layout: wgpu::BindGroupLayout,
 }
 
 impl Texture {
-    pub fn new(file: &Path, device: &wgpu::Device, queue: &mut wgpu::Queue) -> Result<Self> {
-        let texture = image::open(file)?;
-        println!("Dimensions {:?}", texture.dimensions());
-        println!("Color Type: {:?}", texture.color());
-        let texture_size = texture.dimensions();
+    /// Layout of every texture's bind group, a texture at binding 0 and its sampler at 1. Bind
+    /// groups only fit pipelines built with the same layout object, so create it once and share
+    /// it between textures.
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        })
+    }
+
+    /// A single texel texture, for surfaces without an image.
+    pub fn from_color(
+        color: [f32; 4],
+        layout: &wgpu::BindGroupLayout,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let texel = srgb_texel(color);
+        Self::from_image(
+            &image::RgbaImage::from_pixel(1, 1, texel),
+            layout,
+            device,
+            queue,
+        )
+    }
+
+    pub fn from_image(
+        image: &image::RgbaImage,
+        layout: &wgpu::BindGroupLayout,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Self {
+        let texture_size = image.dimensions();
         let size = wgpu::Extent3d {
             width: texture_size.0,
             height: texture_size.1,
             depth: 1,
         };
-        let texture_rgba = texture.into_rgba();
 
         let diffuse_texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Diffuse"),
@@ -35,7 +76,7 @@ impl Texture {
         });
 
         let diffuse_buffer = device
-            .create_buffer_with_data(&texture_rgba, wgpu::BufferUsage::COPY_SRC);
+            .create_buffer_with_data(image, wgpu::BufferUsage::COPY_SRC);
 
         let mut encoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("texture_buffer_copy_encoder"), });
@@ -61,9 +102,9 @@ impl Texture {
         let diffuse_texture_view = diffuse_texture.create_default_view();
 
         let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::FilterMode::Nearest,
@@ -72,31 +113,8 @@ impl Texture {
             compare: wgpu::CompareFunction::Always,
         });
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                bindings: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::SampledTexture {
-                            multisampled: false,
-                            dimension: wgpu::TextureViewDimension::D2,
-                            component_type: wgpu::TextureComponentType::Uint
-                        },
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler{
-                            comparison: false
-                        }
-                    },
-                ],
-                label: Some("texture_bind_group_layout")
-            });
-
         let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
+            layout,
             bindings: &[
                 wgpu::Binding {
                     binding: 0,
@@ -110,12 +128,214 @@ impl Texture {
             label: Some("diffuse_bind_group")
         });
 
-        Ok(Texture {
-            diffuse_texture,
-            diffuse_texture_view,
-            diffuse_sampler,
+        Texture {
+            _diffuse_texture: diffuse_texture,
+            _diffuse_texture_view: diffuse_texture_view,
+            _diffuse_sampler: diffuse_sampler,
             diffuse_bind_group,
-            diffuse_bind_group_layout: texture_bind_group_layout,
+        }
+    }
+}
+
+/// A mipmapped array of same sized colour layers for tiling surfaces, with its bind group laid
+/// out like `Texture::bind_group_layout` but with a `texture2DArray` at binding 0.
+pub struct TextureArray {
+    _texture: wgpu::Texture,
+    _view: wgpu::TextureView,
+    _sampler: wgpu::Sampler,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl TextureArray {
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2Array,
+                        component_type: wgpu::TextureComponentType::Float,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                },
+            ],
+            label: Some("texture_array_bind_group_layout"),
+        })
+    }
+
+    /// Uploads `images` as the array's layers, with a full mip chain built on the CPU. Every
+    /// image must be the same size.
+    pub fn from_images(
+        images: &[image::RgbaImage],
+        layout: &wgpu::BindGroupLayout,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Self> {
+        let (width, height) = match images.first() {
+            Some(image) => image.dimensions(),
+            None => return Err("Texture array needs at least one layer".into()),
+        };
+        if let Some(image) = images.iter().find(|image| image.dimensions() != (width, height)) {
+            return Err(format!(
+                "Texture array layers must all be {}x{}, found {}x{}",
+                width,
+                height,
+                image.width(),
+                image.height()
+            )
+            .into());
+        }
+
+        let mip_level_count = 32 - width.max(height).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture array"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            array_layer_count: images.len() as u32,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("texture_array_copy_encoder"),
+        });
+        for (layer, image) in images.iter().enumerate() {
+            for mip_level in 0..mip_level_count {
+                let mip_width = (width >> mip_level).max(1);
+                let mip_height = (height >> mip_level).max(1);
+                // Resized from the full image each time, the filter's cheap enough at load
+                let mip = if mip_level == 0 {
+                    image.clone()
+                } else {
+                    image::imageops::resize(
+                        image,
+                        mip_width,
+                        mip_height,
+                        image::imageops::FilterType::Triangle,
+                    )
+                };
+
+                let buffer = device.create_buffer_with_data(&mip, wgpu::BufferUsage::COPY_SRC);
+                encoder.copy_buffer_to_texture(
+                    wgpu::BufferCopyView {
+                        buffer: &buffer,
+                        offset: 0,
+                        bytes_per_row: 4 * mip_width,
+                        rows_per_image: mip_height,
+                    },
+                    wgpu::TextureCopyView {
+                        texture: &texture,
+                        mip_level,
+                        array_layer: layer as u32,
+                        origin: wgpu::Origin3d::ZERO,
+                    },
+                    wgpu::Extent3d {
+                        width: mip_width,
+                        height: mip_height,
+                        depth: 1,
+                    },
+                );
+            }
+        }
+        queue.submit(&[encoder.finish()]);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            dimension: wgpu::TextureViewDimension::D2Array,
+            aspect: wgpu::TextureAspect::All,
+            base_mip_level: 0,
+            level_count: mip_level_count,
+            base_array_layer: 0,
+            array_layer_count: images.len() as u32,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare: wgpu::CompareFunction::Always,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("texture_array_bind_group"),
+        });
+
+        Ok(TextureArray {
+            _texture: texture,
+            _view: view,
+            _sampler: sampler,
+            bind_group,
         })
     }
 }
+
+// Linear colour to an 8 bit sRGB texel, alpha left linear
+fn srgb_texel(color: [f32; 4]) -> image::Rgba<u8> {
+    image::Rgba([
+        linear_to_srgb(color[0]),
+        linear_to_srgb(color[1]),
+        linear_to_srgb(color[2]),
+        (color[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+    ])
+}
+
+/// Depth attachment for the main render pass, sized to match the swap chain.
+pub struct DepthTexture {
+    _texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl DepthTexture {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::SAMPLED,
+        });
+        let view = texture.create_default_view();
+
+        DepthTexture {
+            _texture: texture,
+            view,
+        }
+    }
+}
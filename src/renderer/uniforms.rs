@@ -0,0 +1,360 @@
+use super::{Atmosphere, Lights, ShadowMap, ShadowUniforms};
+use crate::na;
+use crate::objects::{Camera, Transform};
+use std::mem;
+
+// Dynamic uniform offsets have to be multiples of this
+pub(super) const DYNAMIC_ALIGNMENT: wgpu::BufferAddress = 256;
+
+/// Per-frame values shared by every pipeline at set 0, binding 0.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Globals {
+    pub view_proj: [[f32; 4]; 4],
+    pub camera_position: [f32; 4],
+    pub time: f32,
+    _padding: [f32; 3],
+}
+
+unsafe impl bytemuck::Pod for Globals {}
+unsafe impl bytemuck::Zeroable for Globals {}
+
+impl Globals {
+    pub fn new() -> Globals {
+        Globals {
+            view_proj: na::Matrix4::identity().into(),
+            camera_position: [0.0; 4],
+            time: 0.0,
+            _padding: [0.0; 3],
+        }
+    }
+
+    pub fn update_camera(&mut self, camera: &Camera) {
+        self.view_proj = camera.view_projection().into();
+        self.camera_position = camera.position.to_homogeneous().into();
+    }
+}
+
+/// A uniform buffer with its own single-binding bind group. `data` is copied to the GPU by
+/// `update`.
+pub struct Uniform<T> {
+    pub data: T,
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl<T: bytemuck::Pod> Uniform<T> {
+    pub fn new(device: &wgpu::Device, data: T, visibility: wgpu::ShaderStage, label: &str) -> Self {
+        let buffer = device.create_buffer_with_data(
+            bytemuck::bytes_of(&data),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+            }],
+            label: Some(label),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &buffer,
+                    range: 0..mem::size_of::<T>() as wgpu::BufferAddress,
+                },
+            }],
+            label: Some(label),
+        });
+
+        Uniform {
+            data,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn update(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        upload(device, encoder, &self.buffer, &self.data);
+    }
+}
+
+/// The bind group every pipeline shares at set 0, `Globals` at binding 0 and `Lights` at
+/// binding 1, then the shadow map's texture, sampler and `ShadowUniforms` at bindings 2 to 4
+/// and the sky's `Atmosphere` at binding 5.
+pub struct FrameUniforms {
+    pub globals: Globals,
+    pub lights: Lights,
+    pub shadows: ShadowUniforms,
+    pub atmosphere: Atmosphere,
+    globals_buffer: wgpu::Buffer,
+    lights_buffer: wgpu::Buffer,
+    shadows_buffer: wgpu::Buffer,
+    atmosphere_buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl FrameUniforms {
+    pub fn new(
+        device: &wgpu::Device,
+        globals: Globals,
+        lights: Lights,
+        shadow_map: &ShadowMap,
+    ) -> Self {
+        let shadows = shadow_map.uniforms;
+        let usage = wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST;
+        let globals_buffer = device.create_buffer_with_data(bytemuck::bytes_of(&globals), usage);
+        let lights_buffer = device.create_buffer_with_data(bytemuck::bytes_of(&lights), usage);
+        let shadows_buffer = device.create_buffer_with_data(bytemuck::bytes_of(&shadows), usage);
+        let atmosphere = Atmosphere::new();
+        let atmosphere_buffer =
+            device.create_buffer_with_data(bytemuck::bytes_of(&atmosphere), usage);
+
+        let visibility = wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2Array,
+                        component_type: wgpu::TextureComponentType::Float,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: true },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+            ],
+            label: Some("frame"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &globals_buffer,
+                        range: 0..mem::size_of::<Globals>() as wgpu::BufferAddress,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &lights_buffer,
+                        range: 0..mem::size_of::<Lights>() as wgpu::BufferAddress,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.view),
+                },
+                wgpu::Binding {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadow_map.sampler),
+                },
+                wgpu::Binding {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &shadows_buffer,
+                        range: 0..mem::size_of::<ShadowUniforms>() as wgpu::BufferAddress,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 5,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &atmosphere_buffer,
+                        range: 0..mem::size_of::<Atmosphere>() as wgpu::BufferAddress,
+                    },
+                },
+            ],
+            label: Some("frame"),
+        });
+
+        FrameUniforms {
+            globals,
+            lights,
+            shadows,
+            atmosphere,
+            globals_buffer,
+            lights_buffer,
+            shadows_buffer,
+            atmosphere_buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    pub fn update(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        upload(device, encoder, &self.globals_buffer, &self.globals);
+        upload(device, encoder, &self.lights_buffer, &self.lights);
+        upload(device, encoder, &self.shadows_buffer, &self.shadows);
+        upload(device, encoder, &self.atmosphere_buffer, &self.atmosphere);
+    }
+}
+
+// Copies `data` over the start of `buffer` through a staging buffer
+fn upload<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    encoder: &mut wgpu::CommandEncoder,
+    buffer: &wgpu::Buffer,
+    data: &T,
+) {
+    let staging_buffer =
+        device.create_buffer_with_data(bytemuck::bytes_of(data), wgpu::BufferUsage::COPY_SRC);
+    encoder.copy_buffer_to_buffer(
+        &staging_buffer,
+        0,
+        buffer,
+        0,
+        mem::size_of::<T>() as wgpu::BufferAddress,
+    );
+}
+
+/// Per-object matrices, bound at set 1 with a dynamic offset for each object.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Model {
+    pub model: [[f32; 4]; 4],
+    /// Inverse transpose of the model matrix, for normals.
+    pub normal: [[f32; 4]; 4],
+}
+
+unsafe impl bytemuck::Pod for Model {}
+unsafe impl bytemuck::Zeroable for Model {}
+
+impl Model {
+    pub fn new(transform: &Transform) -> Model {
+        let model = transform.to_homogeneous();
+        let normal = transform.inverse().to_homogeneous().transpose();
+        Model {
+            model: model.into(),
+            normal: normal.into(),
+        }
+    }
+}
+
+/// One `Model` per drawn object in a single buffer, each padded out to the dynamic offset
+/// alignment.
+pub struct ModelBuffer {
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ModelBuffer {
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer { dynamic: true },
+            }],
+            label: Some("models"),
+        });
+        let (buffer, bind_group) = Self::create_buffer(device, &bind_group_layout, capacity);
+
+        ModelBuffer {
+            buffer,
+            capacity,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("models"),
+            size: capacity as wgpu::BufferAddress * DYNAMIC_ALIGNMENT,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &buffer,
+                    range: 0..mem::size_of::<Model>() as wgpu::BufferAddress,
+                },
+            }],
+            label: Some("models"),
+        });
+
+        (buffer, bind_group)
+    }
+
+    /// Dynamic offset of the `index`th model passed to `update`.
+    pub fn offset(index: usize) -> wgpu::DynamicOffset {
+        (index as wgpu::BufferAddress * DYNAMIC_ALIGNMENT) as wgpu::DynamicOffset
+    }
+
+    /// Uploads `models`, growing the buffer if there are more than last time.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        models: &[Model],
+    ) {
+        if models.is_empty() {
+            return;
+        }
+        if models.len() > self.capacity {
+            self.capacity = models.len().next_power_of_two();
+            let (buffer, bind_group) =
+                Self::create_buffer(device, &self.bind_group_layout, self.capacity);
+            self.buffer = buffer;
+            self.bind_group = bind_group;
+        }
+
+        let stride = DYNAMIC_ALIGNMENT as usize;
+        let mut data = vec![0u8; models.len() * stride];
+        for (chunk, model) in data.chunks_exact_mut(stride).zip(models) {
+            chunk[..mem::size_of::<Model>()].copy_from_slice(bytemuck::bytes_of(model));
+        }
+
+        let staging_buffer = device.create_buffer_with_data(&data, wgpu::BufferUsage::COPY_SRC);
+        encoder.copy_buffer_to_buffer(
+            &staging_buffer,
+            0,
+            &self.buffer,
+            0,
+            data.len() as wgpu::BufferAddress,
+        );
+    }
+}
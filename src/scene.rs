@@ -0,0 +1,119 @@
+use crate::na;
+use crate::objects::{Object, Transform};
+use std::cmp::Ordering;
+
+/// Handle to an object in a `Scene`, stays valid until the object is removed.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ObjectId(u64);
+
+pub struct Node {
+    pub id: ObjectId,
+    pub transform: Transform,
+    pub object: Box<dyn Object>,
+}
+
+/// Everything the renderer draws, each object placed in the world by its transform.
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    next_id: u64,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    pub fn add<T: Object>(&mut self, object: T, transform: Transform) -> ObjectId {
+        let id = ObjectId(self.next_id);
+        self.next_id += 1;
+        self.nodes.push(Node {
+            id,
+            transform,
+            object: Box::new(object),
+        });
+        id
+    }
+
+    pub fn node_mut(&mut self, id: ObjectId) -> Option<&mut Node> {
+        self.nodes.iter_mut().find(|node| node.id == id)
+    }
+
+    /// The object behind `id` as its concrete type, `None` if it is gone or of another type.
+    pub fn get_mut<T: Object>(&mut self, id: ObjectId) -> Option<&mut T> {
+        self.node_mut(id)?.object.as_any_mut().downcast_mut()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter()
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for node in &mut self.nodes {
+            node.object.update(&mut node.transform, dt);
+        }
+    }
+
+    /// Opaque objects grouped by pipeline, then transparent objects from furthest to nearest
+    /// `eye`.
+    pub fn draw_order(&self, eye: &na::Point3<f32>) -> Vec<&Node> {
+        sort_draws(
+            self.nodes.iter(),
+            |node| node.object.is_transparent(),
+            |node| node.object.pipeline() as *const wgpu::RenderPipeline as usize,
+            |node| na::distance_squared(eye, &node.transform.isometry.translation.vector.into()),
+        )
+    }
+}
+
+// The ordering behind `draw_order`, apart from the nodes so it can be checked without a GPU
+fn sort_draws<T: Copy>(
+    items: impl Iterator<Item = T>,
+    is_transparent: impl Fn(T) -> bool,
+    pipeline: impl Fn(T) -> usize,
+    distance: impl Fn(T) -> f32,
+) -> Vec<T> {
+    let (mut opaque, mut transparent): (Vec<T>, Vec<T>) =
+        items.partition(|&item| !is_transparent(item));
+
+    // Stable, so objects sharing a pipeline keep the order they were added in
+    opaque.sort_by_key(|&item| pipeline(item));
+
+    transparent.sort_by(|&a, &b| {
+        distance(b)
+            .partial_cmp(&distance(a))
+            .unwrap_or(Ordering::Equal)
+    });
+
+    opaque.extend(transparent);
+    opaque
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (name, transparent, pipeline, distance)
+    type Draw = (&'static str, bool, usize, f32);
+
+    #[test]
+    fn draws_opaque_by_pipeline_then_transparent_back_to_front() {
+        let draws: &[Draw] = &[
+            ("glass", true, 1, 5.0),
+            ("rock", false, 2, 1.0),
+            ("terrain", false, 1, 9.0),
+            ("water", true, 3, 20.0),
+            ("tree", false, 2, 3.0),
+            ("cube", false, 1, 2.0),
+            ("smoke", true, 1, 8.0),
+        ];
+        let order: Vec<&str> = sort_draws(draws.iter(), |d| d.1, |d| d.2, |d| d.3)
+            .into_iter()
+            .map(|d| d.0)
+            .collect();
+        assert_eq!(
+            order,
+            ["terrain", "cube", "rock", "tree", "water", "smoke", "glass"]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::Result;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+mod chunk;
+mod erosion;
+mod formats;
+mod generator;
+mod heightmap;
+mod mesh;
+mod noise;
+mod rng;
+mod scatter;
+mod splat;
+
+pub use chunk::{ChunkConfig, ChunkCoord, ChunkManager};
+pub use erosion::{erode, ErosionConfig};
+pub use formats::{Elevation, HeightFormat, HeightmapFile};
+pub use generator::TerrainGenerator;
+pub use heightmap::Heightmap;
+pub use mesh::{build_mesh, TerrainVertex};
+pub use noise::{fbm, FbmParams, NoiseGraph, NoiseKind, NoiseSource, Perlin};
+pub use rng::Rng;
+pub use scatter::{scatter, ScatterConfig, ScatterPoint};
+pub use splat::{splat_weights, SplatConfig, SplatMap, SplatMasks, MAX_SPLAT_LAYERS};
+
+#[derive(Debug, Deserialize)]
+pub struct TerrainConfig {
+    pub seed: u64,
+    pub width: usize,
+    pub depth: usize,
+    pub cell_size: f32,
+    pub height_scale: f32,
+    pub noise: NoiseKind,
+    pub frequency: f64,
+    pub octaves: u32,
+    pub lacunarity: f64,
+    pub persistence: f64,
+    /// Noise graph TOML describing the terrain, used instead of the fbm settings above. See
+    /// `NoiseGraphConfig`.
+    pub recipe: Option<PathBuf>,
+    /// Heightmap file to use instead of generating one. Chunked terrain always generates.
+    pub import: Option<HeightmapImport>,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig {
+            seed: 1,
+            width: 256,
+            depth: 256,
+            cell_size: 1.0,
+            height_scale: 40.0,
+            noise: NoiseKind::Perlin,
+            frequency: 0.01,
+            octaves: 6,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            recipe: None,
+            import: None,
+        }
+    }
+}
+
+/// A heightmap file to load as the terrain, in any `HeightFormat`.
+#[derive(Debug, Deserialize)]
+pub struct HeightmapImport {
+    pub path: PathBuf,
+    /// Turns the file's values into world heights.
+    #[serde(flatten)]
+    pub elevation: Elevation,
+    /// Width and depth of raw files, square when unset.
+    pub size: Option<[usize; 2]>,
+}
+
+impl HeightmapImport {
+    /// Cells the file has no data for are filled with its lowest height.
+    pub fn load(&self) -> Result<Heightmap> {
+        let mut heightmap = HeightmapFile::load(&self.path, self.elevation, self.size)?.heightmap;
+        let lowest = heightmap
+            .samples
+            .iter()
+            .cloned()
+            .filter(|height| !height.is_nan())
+            .fold(f32::MAX, f32::min);
+        if lowest == f32::MAX {
+            return Err(format!("{} has no heights", self.path.display()).into());
+        }
+        for height in heightmap
+            .samples
+            .iter_mut()
+            .filter(|height| height.is_nan())
+        {
+            *height = lowest;
+        }
+        Ok(heightmap)
+    }
+}
@@ -0,0 +1,447 @@
+use super::{
+    splat_weights, Heightmap, SplatConfig, SplatMap, SplatMasks, TerrainGenerator, TerrainVertex,
+};
+use crate::objects::Mesh;
+use crate::Result;
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ChunkConfig {
+    pub enabled: bool,
+    /// Cells along each side of a chunk. Must be divisible by `2^(lod_levels - 1)`, and at most
+    /// 253 so the finest mesh fits 16 bit indices.
+    pub chunk_size: usize,
+    pub lod_levels: u32,
+    /// Chunks are kept loaded within this many chunks of the focus point.
+    pub view_distance: u32,
+    /// World distance covered by each level of detail before dropping to the next.
+    pub lod_distance: f32,
+    pub skirt_depth: f32,
+    pub workers: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        ChunkConfig {
+            enabled: false,
+            chunk_size: 64,
+            lod_levels: 4,
+            view_distance: 6,
+            lod_distance: 96.0,
+            skirt_depth: 4.0,
+            workers: 2,
+        }
+    }
+}
+
+impl ChunkConfig {
+    /// Fails if the chunk size doesn't suit the LOD levels or the mesh index size.
+    pub fn validate(&self) -> Result<()> {
+        let coarsest_step = 1 << self.lod_levels.saturating_sub(1);
+        if self.lod_levels == 0 || self.chunk_size % coarsest_step != 0 {
+            return Err(format!(
+                "Chunk size {} doesn't divide into {} LOD levels",
+                self.chunk_size, self.lod_levels
+            )
+            .into());
+        }
+        // The finest LOD has every sample plus a row of skirt vertices along each edge
+        let row = self.chunk_size + 1;
+        if row.saturating_mul(row + 4) > u16::MAX as usize + 1 {
+            return Err(format!(
+                "Chunk size {} has too many vertices for 16 bit indices",
+                self.chunk_size
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkCoord {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ChunkCoord {
+    pub fn new(x: i32, z: i32) -> ChunkCoord {
+        ChunkCoord { x, z }
+    }
+
+    /// Chunk containing the world position `(x, z)`.
+    pub fn containing(x: f32, z: f32, chunk_world_size: f32) -> ChunkCoord {
+        ChunkCoord {
+            x: (x / chunk_world_size).floor() as i32,
+            z: (z / chunk_world_size).floor() as i32,
+        }
+    }
+
+    /// Chebyshev distance in chunks, so the loaded area is a square.
+    pub fn distance(&self, other: ChunkCoord) -> u32 {
+        ((self.x - other.x).abs()).max((self.z - other.z).abs()) as u32
+    }
+}
+
+/// A generated chunk with one mesh per level of detail, finest first.
+pub struct ChunkData {
+    pub coord: ChunkCoord,
+    pub lods: Vec<Mesh<TerrainVertex>>,
+}
+
+/// Generates a chunk's heights and builds its LOD meshes. Vertices are in world space. Chunks
+/// aren't eroded, so their materials only follow height, slope and curvature.
+pub fn build_chunk(
+    generator: &TerrainGenerator,
+    coord: ChunkCoord,
+    config: &ChunkConfig,
+    splat_config: &SplatConfig,
+    cell_size: f32,
+) -> ChunkData {
+    let size = config.chunk_size;
+    // One sample of border on every side so normals match across chunk edges
+    let origin = [
+        coord.x as i64 * size as i64 - 1,
+        coord.z as i64 * size as i64 - 1,
+    ];
+    let heightmap = generator.heightmap(origin, size + 3, size + 3);
+    let splat = splat_weights(&heightmap, cell_size, splat_config, &SplatMasks::default());
+
+    let lods = (0..config.lod_levels)
+        .map(|lod| build_lod_mesh(&heightmap, &splat, coord, 1 << lod, config, cell_size))
+        .collect();
+
+    ChunkData { coord, lods }
+}
+
+// Grid over every `step`th sample, plus a skirt hanging down from the chunk edge. Neighbouring
+// chunks at different LODs don't share edge vertices; the skirts cover the gaps between them.
+fn build_lod_mesh(
+    heightmap: &Heightmap,
+    splat: &SplatMap,
+    coord: ChunkCoord,
+    step: usize,
+    config: &ChunkConfig,
+    cell_size: f32,
+) -> Mesh<TerrainVertex> {
+    let size = config.chunk_size;
+    let cells = size / step;
+    let row = cells + 1;
+    let chunk_world_size = size as f32 * cell_size;
+    let (offset_x, offset_z) = (
+        coord.x as f32 * chunk_world_size,
+        coord.z as f32 * chunk_world_size,
+    );
+
+    let vertex = |i: usize, j: usize, drop: f32| {
+        let (x, z) = (i * step, j * step);
+        let normal = heightmap.normal(x + 1, z + 1, cell_size);
+        TerrainVertex {
+            position: [
+                offset_x + x as f32 * cell_size,
+                heightmap.get(x + 1, z + 1) - drop,
+                offset_z + z as f32 * cell_size,
+            ],
+            normal: [normal.x, normal.y, normal.z],
+            uv: [x as f32 / size as f32, z as f32 / size as f32],
+            weights: splat.get(x + 1, z + 1),
+        }
+    };
+
+    let mut vertices = Vec::with_capacity(row * row + 4 * row);
+    let mut indices = Vec::with_capacity(cells * cells * 6 + 4 * cells * 6);
+
+    for j in 0..row {
+        for i in 0..row {
+            vertices.push(vertex(i, j, 0.0));
+        }
+    }
+    for j in 0..cells {
+        for i in 0..cells {
+            let i00 = (j * row + i) as u16;
+            let i10 = i00 + 1;
+            let i01 = i00 + row as u16;
+            let i11 = i01 + 1;
+            indices.extend_from_slice(&[i00, i01, i10, i10, i01, i11]);
+        }
+    }
+
+    // Edges walked so the outside of each skirt faces away from the chunk
+    let edges: [Vec<(usize, usize)>; 4] = [
+        (0..row).rev().map(|i| (i, 0)).collect(),
+        (0..row).map(|i| (i, cells)).collect(),
+        (0..row).map(|j| (0, j)).collect(),
+        (0..row).rev().map(|j| (cells, j)).collect(),
+    ];
+    for edge in edges.iter() {
+        let base = vertices.len() as u16;
+        for &(i, j) in edge {
+            vertices.push(vertex(i, j, config.skirt_depth));
+        }
+        for k in 0..cells {
+            let top0 = (edge[k].1 * row + edge[k].0) as u16;
+            let top1 = (edge[k + 1].1 * row + edge[k + 1].0) as u16;
+            let bottom0 = base + k as u16;
+            let bottom1 = bottom0 + 1;
+            indices.extend_from_slice(&[top0, bottom0, top1, top1, bottom0, bottom1]);
+        }
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+/// Chunks that finished loading and chunks that fell out of range since the last update.
+#[derive(Default)]
+pub struct ChunkUpdate {
+    pub loaded: Vec<ChunkData>,
+    pub evicted: Vec<ChunkCoord>,
+}
+
+/// Keeps the chunks around a focus point loaded. Generation runs on worker threads and finished
+/// chunks are collected without blocking in `update`.
+pub struct ChunkManager {
+    chunk_size: usize,
+    cell_size: f32,
+    view_distance: u32,
+    jobs: Option<Sender<ChunkCoord>>,
+    results: Receiver<ChunkData>,
+    workers: Vec<JoinHandle<()>>,
+    pending: HashSet<ChunkCoord>,
+    loaded: HashSet<ChunkCoord>,
+}
+
+impl ChunkManager {
+    pub fn new(
+        generator: TerrainGenerator,
+        config: ChunkConfig,
+        splat_config: SplatConfig,
+        cell_size: f32,
+    ) -> ChunkManager {
+        let (job_sender, job_receiver) = channel::<ChunkCoord>();
+        let (result_sender, results) = channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let generator = Arc::new(generator);
+        let chunk_size = config.chunk_size;
+        let view_distance = config.view_distance;
+        let workers_count = config.workers.max(1);
+        let config = Arc::new(config);
+        let splat_config = Arc::new(splat_config);
+
+        let workers = (0..workers_count)
+            .map(|_| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let result_sender = result_sender.clone();
+                let generator = Arc::clone(&generator);
+                let config = Arc::clone(&config);
+                let splat_config = Arc::clone(&splat_config);
+                thread::spawn(move || loop {
+                    let job = job_receiver.lock().unwrap().recv();
+                    let coord = match job {
+                        Ok(coord) => coord,
+                        Err(_) => break,
+                    };
+                    let chunk = build_chunk(&generator, coord, &config, &splat_config, cell_size);
+                    if result_sender.send(chunk).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        ChunkManager {
+            chunk_size,
+            cell_size,
+            view_distance,
+            jobs: Some(job_sender),
+            results,
+            workers,
+            pending: HashSet::new(),
+            loaded: HashSet::new(),
+        }
+    }
+
+    pub fn chunk_world_size(&self) -> f32 {
+        self.chunk_size as f32 * self.cell_size
+    }
+
+    /// True once every queued chunk has come back from the workers.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Queues missing chunks around `(x, z)` nearest first, evicts far ones and returns whatever
+    /// the workers have finished.
+    pub fn update(&mut self, x: f32, z: f32) -> ChunkUpdate {
+        let center = ChunkCoord::containing(x, z, self.chunk_world_size());
+        let radius = self.view_distance as i32;
+        let mut update = ChunkUpdate::default();
+
+        let mut wanted = Vec::new();
+        for dz in -radius..=radius {
+            for dx in -radius..=radius {
+                let coord = ChunkCoord::new(center.x + dx, center.z + dz);
+                if !self.loaded.contains(&coord) && !self.pending.contains(&coord) {
+                    wanted.push(coord);
+                }
+            }
+        }
+        wanted.sort_by_key(|coord| (coord.distance(center), *coord));
+        if let Some(jobs) = &self.jobs {
+            for coord in wanted {
+                if jobs.send(coord).is_ok() {
+                    self.pending.insert(coord);
+                }
+            }
+        }
+
+        // Keep one extra ring loaded so chunks don't thrash at the boundary
+        let keep = self.view_distance + 1;
+        let evicted: Vec<ChunkCoord> = self
+            .loaded
+            .iter()
+            .filter(|coord| coord.distance(center) > keep)
+            .cloned()
+            .collect();
+        for coord in evicted {
+            self.loaded.remove(&coord);
+            update.evicted.push(coord);
+        }
+
+        while let Ok(chunk) = self.results.try_recv() {
+            self.pending.remove(&chunk.coord);
+            if chunk.coord.distance(center) <= keep {
+                self.loaded.insert(chunk.coord);
+                update.loaded.push(chunk);
+            }
+        }
+
+        update
+    }
+}
+
+impl Drop for ChunkManager {
+    fn drop(&mut self) {
+        // Closing the job channel lets the workers finish their current chunk and exit
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::TerrainConfig;
+
+    fn config(chunk_size: usize, lod_levels: u32) -> ChunkConfig {
+        ChunkConfig {
+            chunk_size,
+            lod_levels,
+            ..ChunkConfig::default()
+        }
+    }
+
+    #[test]
+    fn validates_chunk_sizes() {
+        assert!(config(64, 4).validate().is_ok());
+        assert!(config(252, 3).validate().is_ok());
+        assert!(config(60, 4).validate().is_err());
+        assert!(config(64, 0).validate().is_err());
+        assert!(config(253, 1).validate().is_ok());
+        assert!(config(254, 1).validate().is_err());
+        assert!(config(256, 4).validate().is_err());
+    }
+
+    #[test]
+    fn largest_chunk_indexes_every_vertex() {
+        let config = config(252, 3);
+        let generator = TerrainGenerator::new(&TerrainConfig::default()).unwrap();
+        let chunk = build_chunk(
+            &generator,
+            ChunkCoord::new(-1, 2),
+            &config,
+            &SplatConfig::default(),
+            1.0,
+        );
+
+        assert_eq!(chunk.lods.len(), 3);
+        for (lod, mesh) in chunk.lods.iter().enumerate() {
+            let row = (252 >> lod) + 1;
+            assert_eq!(mesh.vertices.len(), row * row + 4 * row);
+            assert!(mesh
+                .indices
+                .iter()
+                .all(|&i| (i as usize) < mesh.vertices.len()));
+        }
+    }
+
+    // Updates until every queued chunk is back, returning what loaded and what was evicted
+    fn settle(
+        manager: &mut ChunkManager,
+        x: f32,
+        z: f32,
+    ) -> (HashSet<ChunkCoord>, Vec<ChunkCoord>) {
+        let (mut loaded, mut evicted) = (HashSet::new(), Vec::new());
+        loop {
+            let update = manager.update(x, z);
+            loaded.extend(update.loaded.iter().map(|chunk| chunk.coord));
+            evicted.extend(update.evicted);
+            if manager.is_idle() {
+                return (loaded, evicted);
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    fn ring(x: i32, z: i32) -> HashSet<ChunkCoord> {
+        (-1..=1)
+            .flat_map(|dz| (-1..=1).map(move |dx| ChunkCoord::new(x + dx, z + dz)))
+            .collect()
+    }
+
+    #[test]
+    fn streams_chunks_around_the_focus() {
+        let generator = TerrainGenerator::new(&TerrainConfig::default()).unwrap();
+        let config = ChunkConfig {
+            view_distance: 1,
+            workers: 2,
+            ..config(8, 1)
+        };
+        let mut manager = ChunkManager::new(generator, config, SplatConfig::default(), 1.0);
+
+        let (loaded, evicted) = settle(&mut manager, 4.0, 4.0);
+        assert_eq!(loaded, ring(0, 0));
+        assert!(evicted.is_empty());
+
+        // One chunk over keeps everything, the extra ring stops chunks thrashing at the edge
+        let (loaded, evicted) = settle(&mut manager, 12.0, 4.0);
+        assert_eq!(loaded, (-1..=1).map(|z| ChunkCoord::new(2, z)).collect());
+        assert!(evicted.is_empty());
+
+        // Three chunks over drops the columns more than two chunks away
+        let (loaded, evicted) = settle(&mut manager, 28.0, 4.0);
+        assert_eq!(
+            loaded,
+            (-1..=1)
+                .flat_map(|z| vec![ChunkCoord::new(3, z), ChunkCoord::new(4, z)])
+                .collect()
+        );
+        let evicted: HashSet<_> = evicted.into_iter().collect();
+        assert_eq!(
+            evicted,
+            (-1..=1)
+                .flat_map(|z| vec![ChunkCoord::new(-1, z), ChunkCoord::new(0, z)])
+                .collect()
+        );
+    }
+}
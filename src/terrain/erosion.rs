@@ -0,0 +1,370 @@
+use super::{Heightmap, Rng};
+use crate::na;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ErosionConfig {
+    pub seed: u64,
+    // Hydraulic
+    pub droplets: u32,
+    pub max_lifetime: u32,
+    pub inertia: f32,
+    pub sediment_capacity: f32,
+    pub min_capacity: f32,
+    pub erode_speed: f32,
+    pub deposit_speed: f32,
+    pub evaporate_speed: f32,
+    pub gravity: f32,
+    pub brush_radius: usize,
+    pub initial_water: f32,
+    pub initial_speed: f32,
+    // Thermal
+    pub thermal_iterations: u32,
+    pub talus_angle: f32,
+    pub thermal_rate: f32,
+    // Optional outputs
+    pub track_sediment: bool,
+    pub track_flow: bool,
+}
+
+impl Default for ErosionConfig {
+    fn default() -> Self {
+        ErosionConfig {
+            seed: 1,
+            droplets: 50_000,
+            max_lifetime: 30,
+            inertia: 0.05,
+            sediment_capacity: 4.0,
+            min_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporate_speed: 0.01,
+            gravity: 4.0,
+            brush_radius: 3,
+            initial_water: 1.0,
+            initial_speed: 1.0,
+            thermal_iterations: 20,
+            talus_angle: 35.0,
+            thermal_rate: 0.5,
+            track_sediment: true,
+            track_flow: true,
+        }
+    }
+}
+
+/// Side products of an erosion run, sized like the eroded heightmap.
+#[derive(Debug, Default)]
+pub struct ErosionMaps {
+    /// Total sediment deposited on each cell.
+    pub sediment: Option<Heightmap>,
+    /// Total droplet water that passed over each cell.
+    pub flow: Option<Heightmap>,
+}
+
+/// Runs hydraulic then thermal erosion over `heightmap` in place. `cell_size` is the world
+/// distance between samples and converts the talus angle to a height difference.
+pub fn erode(heightmap: &mut Heightmap, config: &ErosionConfig, cell_size: f32) -> ErosionMaps {
+    let mut maps = ErosionMaps {
+        sediment: if config.track_sediment {
+            Some(Heightmap::new(heightmap.width, heightmap.depth))
+        } else {
+            None
+        },
+        flow: if config.track_flow {
+            Some(Heightmap::new(heightmap.width, heightmap.depth))
+        } else {
+            None
+        },
+    };
+
+    if heightmap.width < 3 || heightmap.depth < 3 {
+        return maps;
+    }
+
+    hydraulic(heightmap, config, &mut maps);
+    thermal(heightmap, config, cell_size);
+
+    maps
+}
+
+struct Brush {
+    offsets: Vec<(isize, isize)>,
+    weights: Vec<f32>,
+}
+
+impl Brush {
+    fn new(radius: usize) -> Brush {
+        let radius = radius.max(1) as isize;
+        let mut offsets = Vec::new();
+        let mut weights = Vec::new();
+        for dz in -radius..=radius {
+            for dx in -radius..=radius {
+                let distance = ((dx * dx + dz * dz) as f32).sqrt();
+                if distance < radius as f32 {
+                    offsets.push((dx, dz));
+                    weights.push(1.0 - distance / radius as f32);
+                }
+            }
+        }
+        let total: f32 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= total);
+
+        Brush { offsets, weights }
+    }
+}
+
+// Particle based hydraulic erosion. Each droplet runs downhill, picking up sediment while it is
+// below its carrying capacity and dropping it when it slows down or climbs.
+fn hydraulic(heightmap: &mut Heightmap, config: &ErosionConfig, maps: &mut ErosionMaps) {
+    let mut rng = Rng::new(config.seed);
+    let brush = Brush::new(config.brush_radius);
+    let max_x = (heightmap.width - 1) as f32;
+    let max_z = (heightmap.depth - 1) as f32;
+
+    for _ in 0..config.droplets {
+        let mut position = na::Vector2::new(rng.range_f32(0.0, max_x), rng.range_f32(0.0, max_z));
+        let mut direction = na::Vector2::zeros();
+        let mut speed = config.initial_speed;
+        let mut water = config.initial_water;
+        let mut sediment = 0.0;
+
+        for _ in 0..config.max_lifetime {
+            let cell_x = position.x.floor() as usize;
+            let cell_z = position.y.floor() as usize;
+            let (height, gradient) = height_and_gradient(heightmap, position);
+
+            direction = direction * config.inertia - gradient * (1.0 - config.inertia);
+            let length = direction.norm();
+            if length <= f32::EPSILON {
+                break;
+            }
+            direction /= length;
+            let old_position = position;
+            position += direction;
+
+            if let Some(flow) = maps.flow.as_mut() {
+                let i = flow.index(cell_x, cell_z);
+                flow.samples[i] += water;
+            }
+
+            if position.x < 0.0 || position.x >= max_x || position.y < 0.0 || position.y >= max_z {
+                break;
+            }
+
+            let delta_height = heightmap.sample(position.x, position.y) - height;
+            let capacity = (-delta_height * speed * water * config.sediment_capacity)
+                .max(config.min_capacity);
+
+            if sediment > capacity || delta_height > 0.0 {
+                // Fill the pit we climbed out of, or drop the excess over capacity
+                let amount = if delta_height > 0.0 {
+                    delta_height.min(sediment)
+                } else {
+                    (sediment - capacity) * config.deposit_speed
+                };
+                sediment -= amount;
+                deposit(heightmap, old_position, amount);
+                if let Some(map) = maps.sediment.as_mut() {
+                    deposit(map, old_position, amount);
+                }
+            } else {
+                let amount = ((capacity - sediment) * config.erode_speed).min(-delta_height);
+                for (&(dx, dz), &weight) in brush.offsets.iter().zip(brush.weights.iter()) {
+                    let x = cell_x as isize + dx;
+                    let z = cell_z as isize + dz;
+                    if x >= 0 && z >= 0 && (x as usize) < heightmap.width && (z as usize) < heightmap.depth
+                    {
+                        let i = heightmap.index(x as usize, z as usize);
+                        heightmap.samples[i] -= amount * weight;
+                    }
+                }
+                sediment += amount;
+            }
+
+            speed = (speed * speed - delta_height * config.gravity).max(0.0).sqrt();
+            water *= 1.0 - config.evaporate_speed;
+        }
+    }
+}
+
+// Bilinear height and gradient at a position inside the map
+fn height_and_gradient(heightmap: &Heightmap, position: na::Vector2<f32>) -> (f32, na::Vector2<f32>) {
+    let x = position.x.floor() as usize;
+    let z = position.y.floor() as usize;
+    let u = position.x - x as f32;
+    let v = position.y - z as f32;
+
+    let h00 = heightmap.get(x, z);
+    let h10 = heightmap.get(x + 1, z);
+    let h01 = heightmap.get(x, z + 1);
+    let h11 = heightmap.get(x + 1, z + 1);
+
+    let gradient = na::Vector2::new(
+        (h10 - h00) * (1.0 - v) + (h11 - h01) * v,
+        (h01 - h00) * (1.0 - u) + (h11 - h10) * u,
+    );
+    let height = h00 * (1.0 - u) * (1.0 - v) + h10 * u * (1.0 - v) + h01 * (1.0 - u) * v + h11 * u * v;
+
+    (height, gradient)
+}
+
+// Spreads `amount` over the four samples around `position`
+fn deposit(map: &mut Heightmap, position: na::Vector2<f32>, amount: f32) {
+    let x = position.x.floor() as usize;
+    let z = position.y.floor() as usize;
+    let u = position.x - x as f32;
+    let v = position.y - z as f32;
+
+    let corners = [
+        (x, z, (1.0 - u) * (1.0 - v)),
+        (x + 1, z, u * (1.0 - v)),
+        (x, z + 1, (1.0 - u) * v),
+        (x + 1, z + 1, u * v),
+    ];
+    for &(x, z, weight) in corners.iter() {
+        let i = map.index(x, z);
+        map.samples[i] += amount * weight;
+    }
+}
+
+// Thermal erosion: material on slopes steeper than the talus angle slumps onto its lower
+// neighbours. Changes are gathered per pass and applied together so the result doesn't depend
+// on iteration order.
+fn thermal(heightmap: &mut Heightmap, config: &ErosionConfig, cell_size: f32) {
+    const NEIGHBOURS: [(isize, isize); 8] = [
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ];
+
+    let talus = config.talus_angle.to_radians().tan() * cell_size;
+    let (width, depth) = (heightmap.width as isize, heightmap.depth as isize);
+    let mut delta = vec![0.0f32; heightmap.samples.len()];
+
+    for _ in 0..config.thermal_iterations {
+        delta.iter_mut().for_each(|d| *d = 0.0);
+
+        for z in 0..depth {
+            for x in 0..width {
+                let height = heightmap.get(x as usize, z as usize);
+                let mut excess = [0.0f32; 8];
+                let mut total_excess = 0.0;
+                let mut max_excess = 0.0f32;
+
+                for (n, &(dx, dz)) in NEIGHBOURS.iter().enumerate() {
+                    let (nx, nz) = (x + dx, z + dz);
+                    if nx < 0 || nz < 0 || nx >= width || nz >= depth {
+                        continue;
+                    }
+                    let limit = if dx != 0 && dz != 0 {
+                        talus * std::f32::consts::SQRT_2
+                    } else {
+                        talus
+                    };
+                    let difference = height - heightmap.get(nx as usize, nz as usize);
+                    if difference > limit {
+                        excess[n] = difference - limit;
+                        total_excess += excess[n];
+                        max_excess = max_excess.max(excess[n]);
+                    }
+                }
+
+                if total_excess <= 0.0 {
+                    continue;
+                }
+
+                let moved = config.thermal_rate * max_excess * 0.5;
+                delta[(z * width + x) as usize] -= moved;
+                for (n, &(dx, dz)) in NEIGHBOURS.iter().enumerate() {
+                    if excess[n] > 0.0 {
+                        let i = ((z + dz) * width + x + dx) as usize;
+                        delta[i] += moved * excess[n] / total_excess;
+                    }
+                }
+            }
+        }
+
+        for (height, d) in heightmap.samples.iter_mut().zip(delta.iter()) {
+            *height += d;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cone() -> Heightmap {
+        Heightmap::from_fn(33, 33, |x, z| {
+            let (dx, dz) = (x as f32 - 16.0, z as f32 - 16.0);
+            20.0 - (dx * dx + dz * dz).sqrt()
+        })
+    }
+
+    // Steepest drop between two neighbouring samples
+    fn max_slope(heightmap: &Heightmap) -> f32 {
+        let mut slope = 0.0f32;
+        for z in 0..heightmap.depth {
+            for x in 0..heightmap.width - 1 {
+                slope = slope.max((heightmap.get(x, z) - heightmap.get(x + 1, z)).abs());
+            }
+        }
+        for z in 0..heightmap.depth - 1 {
+            for x in 0..heightmap.width {
+                slope = slope.max((heightmap.get(x, z) - heightmap.get(x, z + 1)).abs());
+            }
+        }
+        slope
+    }
+
+    #[test]
+    fn same_seed_erodes_the_same() {
+        let config = ErosionConfig {
+            droplets: 2_000,
+            ..ErosionConfig::default()
+        };
+        let (mut first, mut second) = (cone(), cone());
+        let first_maps = erode(&mut first, &config, 1.0);
+        let second_maps = erode(&mut second, &config, 1.0);
+
+        assert_eq!(first, second);
+        assert_eq!(first_maps.sediment, second_maps.sediment);
+        assert_eq!(first_maps.flow, second_maps.flow);
+        assert_ne!(first, cone());
+    }
+
+    #[test]
+    fn hydraulic_erosion_wears_down_the_peak() {
+        let config = ErosionConfig {
+            droplets: 5_000,
+            thermal_iterations: 0,
+            ..ErosionConfig::default()
+        };
+        let mut heightmap = cone();
+        erode(&mut heightmap, &config, 1.0);
+        assert!(heightmap.get(16, 16) < cone().get(16, 16));
+    }
+
+    #[test]
+    fn thermal_erosion_slumps_steep_slopes() {
+        let config = ErosionConfig {
+            droplets: 0,
+            talus_angle: 30.0,
+            ..ErosionConfig::default()
+        };
+        let mut heightmap =
+            Heightmap::from_fn(9, 9, |x, z| if (x, z) == (4, 4) { 10.0 } else { 0.0 });
+        let before = heightmap.samples.iter().sum::<f32>();
+        erode(&mut heightmap, &config, 1.0);
+
+        assert!(max_slope(&heightmap) < 10.0 * 0.5);
+        // Material moves downhill but none is lost
+        assert!((heightmap.samples.iter().sum::<f32>() - before).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,616 @@
+use super::Heightmap;
+use crate::Result;
+use image::ImageEncoder;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+// SRTM tiles mark voids with the lowest 16 bit value
+const HGT_NO_DATA: f32 = -32768.0;
+
+/// Heightmap file formats, picked by extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeightFormat {
+    /// 16 bit grayscale PNG.
+    Png,
+    /// Headerless little endian `u16`, `.r16` or `.raw`.
+    R16,
+    /// Headerless little endian `f32`.
+    R32,
+    /// ESRI ASCII grid.
+    Asc,
+    /// SRTM tile, big endian `i16` covering one degree square.
+    Hgt,
+}
+
+impl HeightFormat {
+    pub fn from_path(path: &Path) -> Result<HeightFormat> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Ok(HeightFormat::Png),
+            "r16" | "raw" => Ok(HeightFormat::R16),
+            "r32" => Ok(HeightFormat::R32),
+            "asc" => Ok(HeightFormat::Asc),
+            "hgt" => Ok(HeightFormat::Hgt),
+            _ => Err(format!("Unknown heightmap format: {}", path.display()).into()),
+        }
+    }
+}
+
+/// How values stored in a file map to heights, `height = stored * scale + offset`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Elevation {
+    pub scale: f32,
+    pub offset: f32,
+    /// Stored value marking cells without data. ASC files can set their own, HGT files always
+    /// use -32768.
+    pub no_data: Option<f32>,
+}
+
+impl Default for Elevation {
+    fn default() -> Self {
+        Elevation {
+            scale: 1.0,
+            offset: 0.0,
+            no_data: None,
+        }
+    }
+}
+
+impl Elevation {
+    /// Spreads `heightmap`'s heights over stored values `0..=max`, keeping `max + 1` for cells
+    /// without data when there are any.
+    pub fn fit(heightmap: &Heightmap, max: f32) -> Elevation {
+        let (min, top) = heightmap
+            .samples
+            .iter()
+            .filter(|height| !height.is_nan())
+            .fold((f32::MAX, f32::MIN), |(min, max), &height| {
+                (min.min(height), max.max(height))
+            });
+        if min > top {
+            return Elevation::default();
+        }
+        let missing = heightmap.samples.iter().any(|height| height.is_nan());
+        let levels = if missing { max - 1.0 } else { max };
+
+        Elevation {
+            scale: ((top - min) / levels).max(f32::EPSILON),
+            offset: min,
+            no_data: if missing { Some(max) } else { None },
+        }
+    }
+
+    fn height(&self, stored: f32) -> f32 {
+        if stored.is_nan() || Some(stored) == self.no_data {
+            f32::NAN
+        } else {
+            stored * self.scale + self.offset
+        }
+    }
+
+    fn stored(&self, height: f32) -> Result<f32> {
+        if height.is_nan() {
+            self.no_data.ok_or_else(|| {
+                "Heightmap has cells without data but no no_data value to write them as".into()
+            })
+        } else {
+            Ok((height - self.offset) / self.scale)
+        }
+    }
+}
+
+/// A heightmap read from or written to a file. Row 0 is the file's first row, the top of a PNG
+/// and the north edge of ASC and HGT grids. Cells without data hold NaN.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeightmapFile {
+    pub heightmap: Heightmap,
+    pub elevation: Elevation,
+    /// Distance between samples in the file's own units, for formats that record it. Degrees
+    /// for HGT.
+    pub cell_size: Option<f64>,
+    /// Position of the grid's south west corner, for formats that record it.
+    pub origin: Option<[f64; 2]>,
+}
+
+impl HeightmapFile {
+    pub fn new(heightmap: Heightmap, elevation: Elevation) -> HeightmapFile {
+        HeightmapFile {
+            heightmap,
+            elevation,
+            cell_size: None,
+            origin: None,
+        }
+    }
+
+    /// Reads any `HeightFormat`. `elevation` is used as is for PNG and raw files, which can't
+    /// record it. `size` is the width and depth of raw files, which are taken to be square
+    /// without it.
+    pub fn load(
+        path: &Path,
+        elevation: Elevation,
+        size: Option<[usize; 2]>,
+    ) -> Result<HeightmapFile> {
+        let format = HeightFormat::from_path(path)?;
+        let bytes =
+            fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut file = HeightmapFile::decode(format, &bytes, elevation, size)
+            .map_err(|e| format!("Failed to load {}: {}", path.display(), e))?;
+
+        // SRTM tiles are named after their south west corner, like N45W122.hgt
+        if format == HeightFormat::Hgt {
+            file.origin = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(hgt_origin);
+        }
+        Ok(file)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let bytes = self.encode(HeightFormat::from_path(path)?)?;
+        fs::write(path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(())
+    }
+
+    pub fn decode(
+        format: HeightFormat,
+        bytes: &[u8],
+        elevation: Elevation,
+        size: Option<[usize; 2]>,
+    ) -> Result<HeightmapFile> {
+        match format {
+            HeightFormat::Png => {
+                let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Png)
+                    .map_err(|e| format!("Bad PNG: {}", e))?
+                    .into_luma16();
+                let (width, depth) = (image.width() as usize, image.height() as usize);
+                let stored = image.into_raw().into_iter().map(|value| value as f32);
+                Ok(HeightmapFile::from_stored(width, depth, stored, elevation))
+            }
+            HeightFormat::R16 => {
+                let values = chunks(bytes, 2)?
+                    .map(|value| u16::from_le_bytes([value[0], value[1]]) as f32)
+                    .collect::<Vec<_>>();
+                let (width, depth) = raw_size(values.len(), size)?;
+                Ok(HeightmapFile::from_stored(width, depth, values, elevation))
+            }
+            HeightFormat::R32 => {
+                let values = chunks(bytes, 4)?
+                    .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+                    .collect::<Vec<_>>();
+                let (width, depth) = raw_size(values.len(), size)?;
+                Ok(HeightmapFile::from_stored(width, depth, values, elevation))
+            }
+            HeightFormat::Asc => decode_ascii_grid(bytes, elevation),
+            HeightFormat::Hgt => {
+                let values = chunks(bytes, 2)?
+                    .map(|value| i16::from_be_bytes([value[0], value[1]]) as f32)
+                    .collect::<Vec<_>>();
+                let (side, _) = raw_size(values.len(), None)?;
+                let elevation = Elevation {
+                    no_data: Some(HGT_NO_DATA),
+                    ..elevation
+                };
+                let mut file = HeightmapFile::from_stored(side, side, values, elevation);
+                if side > 1 {
+                    file.cell_size = Some(1.0 / (side - 1) as f64);
+                }
+                Ok(file)
+            }
+        }
+    }
+
+    /// Integer formats round and clamp heights to what they can store. HGT tiles have to be
+    /// square.
+    pub fn encode(&self, format: HeightFormat) -> Result<Vec<u8>> {
+        let elevation = if format == HeightFormat::Hgt {
+            Elevation {
+                no_data: Some(HGT_NO_DATA),
+                ..self.elevation
+            }
+        } else {
+            self.elevation
+        };
+        let stored = self
+            .heightmap
+            .samples
+            .iter()
+            .map(|&height| elevation.stored(height))
+            .collect::<Result<Vec<f32>>>()?;
+        let (width, depth) = (self.heightmap.width, self.heightmap.depth);
+
+        match format {
+            HeightFormat::Png => {
+                let values: Vec<u16> = stored
+                    .iter()
+                    .map(|value| value.round().clamp(0.0, u16::MAX as f32) as u16)
+                    .collect();
+                let mut bytes = Vec::new();
+                image::png::PngEncoder::new(&mut bytes)
+                    .write_image(
+                        bytemuck::cast_slice(&values),
+                        width as u32,
+                        depth as u32,
+                        image::ColorType::L16,
+                    )
+                    .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+                Ok(bytes)
+            }
+            HeightFormat::R16 => Ok(stored
+                .iter()
+                .flat_map(|value| {
+                    let value = value.round().clamp(0.0, u16::MAX as f32) as u16;
+                    value.to_le_bytes().to_vec()
+                })
+                .collect()),
+            HeightFormat::R32 => Ok(stored
+                .iter()
+                .flat_map(|value| value.to_le_bytes().to_vec())
+                .collect()),
+            HeightFormat::Asc => Ok(self.encode_ascii_grid(&stored).into_bytes()),
+            HeightFormat::Hgt => {
+                if width != depth {
+                    return Err(format!("HGT tiles are square, not {}x{}", width, depth).into());
+                }
+                // Voids are already -32768, which clamping has to leave alone
+                Ok(stored
+                    .iter()
+                    .flat_map(|value| {
+                        let value = if *value == HGT_NO_DATA {
+                            HGT_NO_DATA
+                        } else {
+                            value.round().clamp(HGT_NO_DATA + 1.0, i16::MAX as f32)
+                        };
+                        (value as i16).to_be_bytes().to_vec()
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    fn from_stored(
+        width: usize,
+        depth: usize,
+        stored: impl IntoIterator<Item = f32>,
+        elevation: Elevation,
+    ) -> HeightmapFile {
+        let samples = stored
+            .into_iter()
+            .map(|value| elevation.height(value))
+            .collect();
+        HeightmapFile::new(
+            Heightmap {
+                width,
+                depth,
+                samples,
+            },
+            elevation,
+        )
+    }
+
+    fn encode_ascii_grid(&self, stored: &[f32]) -> String {
+        let [x, y] = self.origin.unwrap_or([0.0, 0.0]);
+        let mut text = format!(
+            "ncols {}\nnrows {}\nxllcorner {}\nyllcorner {}\ncellsize {}\n",
+            self.heightmap.width,
+            self.heightmap.depth,
+            x,
+            y,
+            self.cell_size.unwrap_or(1.0)
+        );
+        if let Some(no_data) = self.elevation.no_data {
+            text += &format!("NODATA_value {}\n", no_data);
+        }
+        for row in stored.chunks(self.heightmap.width.max(1)) {
+            let row: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+            text += &row.join(" ");
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// The header is keyword and value pairs, in any order and case, followed by the rows
+fn decode_ascii_grid(bytes: &[u8], elevation: Elevation) -> Result<HeightmapFile> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "ASC grid isn't text")?;
+    let mut tokens = text.split_whitespace().peekable();
+    let (mut width, mut depth, mut cell_size) = (None, None, None);
+    let (mut corner, mut center) = ([None, None], [None, None]);
+    let mut elevation = elevation;
+
+    while let Some(key) = tokens.next_if(|token| token.starts_with(char::is_alphabetic)) {
+        let value = tokens
+            .next()
+            .ok_or_else(|| format!("ASC header {} has no value", key))?;
+        let number = || {
+            value
+                .parse::<f64>()
+                .map_err(|_| format!("ASC header {} isn't a number: {}", key, value))
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "ncols" => width = Some(number()? as usize),
+            "nrows" => depth = Some(number()? as usize),
+            "xllcorner" => corner[0] = Some(number()?),
+            "yllcorner" => corner[1] = Some(number()?),
+            "xllcenter" => center[0] = Some(number()?),
+            "yllcenter" => center[1] = Some(number()?),
+            "cellsize" => cell_size = Some(number()?),
+            "nodata_value" => elevation.no_data = Some(number()? as f32),
+            _ => return Err(format!("Unknown ASC header {}", key).into()),
+        }
+    }
+    let (width, depth) = match (width, depth) {
+        (Some(width), Some(depth)) => (width, depth),
+        _ => return Err("ASC grid is missing ncols or nrows".into()),
+    };
+
+    let values = tokens
+        .map(|token| {
+            token
+                .parse::<f32>()
+                .map_err(|_| format!("Bad ASC value: {}", token).into())
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    if values.len() != width * depth {
+        return Err(format!(
+            "ASC grid has {} values, {}x{} needs {}",
+            values.len(),
+            width,
+            depth,
+            width * depth
+        )
+        .into());
+    }
+
+    // Origins given at the centre of the corner cell are moved out to its corner
+    let half_cell = cell_size.unwrap_or(0.0) / 2.0;
+    let origin = match (corner, center) {
+        ([Some(x), Some(y)], _) => Some([x, y]),
+        (_, [Some(x), Some(y)]) => Some([x - half_cell, y - half_cell]),
+        _ => None,
+    };
+
+    let mut file = HeightmapFile::from_stored(width, depth, values, elevation);
+    file.cell_size = cell_size;
+    file.origin = origin;
+    Ok(file)
+}
+
+fn chunks(bytes: &[u8], size: usize) -> Result<std::slice::ChunksExact<'_, u8>> {
+    if bytes.len() % size != 0 {
+        return Err(format!("File size isn't a multiple of {} bytes", size).into());
+    }
+    Ok(bytes.chunks_exact(size))
+}
+
+fn raw_size(count: usize, size: Option<[usize; 2]>) -> Result<(usize, usize)> {
+    match size {
+        Some([width, depth]) => match width.checked_mul(depth) {
+            Some(needed) if needed == count => Ok((width, depth)),
+            Some(needed) => Err(format!(
+                "File has {} samples, {}x{} needs {}",
+                count, width, depth, needed
+            )
+            .into()),
+            None => Err(format!("Heightmap size {}x{} is too large", width, depth).into()),
+        },
+        None => {
+            let side = (count as f64).sqrt().round() as usize;
+            if side * side == count {
+                Ok((side, side))
+            } else {
+                Err(format!("File has {} samples, which isn't a square grid", count).into())
+            }
+        }
+    }
+}
+
+// Longitude and latitude of a tile name's corner
+fn hgt_origin(name: &str) -> Option<[f64; 2]> {
+    let name = name.to_ascii_uppercase();
+    let split = name.find(['E', 'W'])?;
+    let (latitude, longitude) = name.split_at(split);
+    let parse = |text: &str, negative: char| {
+        let value = text.get(1..)?.parse::<f64>().ok()?;
+        Some(if text.starts_with(negative) {
+            -value
+        } else {
+            value
+        })
+    };
+    if !latitude.starts_with(['N', 'S']) {
+        return None;
+    }
+    Some([parse(longitude, 'W')?, parse(latitude, 'S')?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terrain() -> Heightmap {
+        Heightmap::from_fn(5, 3, |x, z| x as f32 * 10.0 - z as f32 * 3.5 + 0.25)
+    }
+
+    fn round_trip(file: &HeightmapFile, format: HeightFormat) -> HeightmapFile {
+        let bytes = file.encode(format).unwrap();
+        let size = [file.heightmap.width, file.heightmap.depth];
+        HeightmapFile::decode(format, &bytes, file.elevation, Some(size)).unwrap()
+    }
+
+    fn assert_close(a: &Heightmap, b: &Heightmap, tolerance: f32) {
+        assert_eq!((a.width, a.depth), (b.width, b.depth));
+        for (a, b) in a.samples.iter().zip(&b.samples) {
+            assert!(
+                (a.is_nan() && b.is_nan()) || (a - b).abs() <= tolerance,
+                "{} != {}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn picks_format_by_extension() {
+        let format = |name| HeightFormat::from_path(Path::new(name)).ok();
+        assert_eq!(format("a/b.PNG"), Some(HeightFormat::Png));
+        assert_eq!(format("b.raw"), Some(HeightFormat::R16));
+        assert_eq!(format("N45W122.hgt"), Some(HeightFormat::Hgt));
+        assert_eq!(format("b.tif"), None);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let elevation = Elevation {
+            scale: 0.01,
+            offset: -10.0,
+            no_data: Some(0.0),
+        };
+        let mut heightmap = terrain();
+        heightmap.samples[3] = f32::NAN;
+        let file = HeightmapFile::new(heightmap, elevation);
+
+        let read = round_trip(&file, HeightFormat::Png);
+        assert_eq!(read.elevation, elevation);
+        assert_close(&read.heightmap, &file.heightmap, 0.005);
+        assert!(read.heightmap.samples[3].is_nan());
+    }
+
+    #[test]
+    fn r16_round_trip() {
+        let heightmap = Heightmap::from_fn(4, 4, |x, z| (x * 100 + z) as f32 * 0.5);
+        let file = HeightmapFile::new(heightmap.clone(), Elevation::fit(&heightmap, 65535.0));
+
+        let read = round_trip(&file, HeightFormat::R16);
+        assert_close(&read.heightmap, &heightmap, file.elevation.scale);
+        assert_eq!(read.heightmap.samples[0], heightmap.samples[0]);
+    }
+
+    #[test]
+    fn r32_round_trip_is_exact() {
+        let elevation = Elevation {
+            scale: 2.0,
+            offset: 1.0,
+            no_data: Some(-9999.0),
+        };
+        let mut heightmap = terrain();
+        heightmap.samples[7] = f32::NAN;
+        let file = HeightmapFile::new(heightmap, elevation);
+
+        let read = round_trip(&file, HeightFormat::R32);
+        assert_close(&read.heightmap, &file.heightmap, 0.0);
+        assert!(read.heightmap.samples[7].is_nan());
+    }
+
+    #[test]
+    fn raw_files_need_a_size_when_not_square() {
+        let file = HeightmapFile::new(terrain(), Elevation::default());
+        let bytes = file.encode(HeightFormat::R32).unwrap();
+        let decode = |size| HeightmapFile::decode(HeightFormat::R32, &bytes, file.elevation, size);
+
+        assert!(decode(None).is_err());
+        assert!(decode(Some([3, 5])).is_ok());
+        assert!(decode(Some([4, 4])).is_err());
+        assert!(decode(Some([usize::MAX, 2])).is_err());
+        assert_eq!(decode(Some([5, 3])).unwrap(), file);
+    }
+
+    #[test]
+    fn asc_round_trip() {
+        let mut heightmap = terrain();
+        heightmap.samples[0] = f32::NAN;
+        let file = HeightmapFile {
+            heightmap,
+            elevation: Elevation {
+                no_data: Some(-9999.0),
+                ..Elevation::default()
+            },
+            cell_size: Some(30.0),
+            origin: Some([500_000.5, 4_100_000.0]),
+        };
+
+        let read = round_trip(&file, HeightFormat::Asc);
+        assert_close(&read.heightmap, &file.heightmap, 0.0);
+        assert_eq!(read.elevation, file.elevation);
+        assert_eq!(read.cell_size, file.cell_size);
+        assert_eq!(read.origin, file.origin);
+    }
+
+    #[test]
+    fn reads_asc_headers() {
+        let text = "NCOLS 2\nNROWS 2\nXLLCENTER 10\nYLLCENTER 20\nCELLSIZE 2\n\
+                    NODATA_VALUE -1\n1.5 -1\n3 4\n";
+        let elevation = Elevation {
+            scale: 2.0,
+            offset: 1.0,
+            no_data: None,
+        };
+        let file =
+            HeightmapFile::decode(HeightFormat::Asc, text.as_bytes(), elevation, None).unwrap();
+
+        assert_eq!(file.origin, Some([9.0, 19.0]));
+        assert_eq!(file.elevation.no_data, Some(-1.0));
+        assert_eq!(file.heightmap.samples[0], 4.0);
+        assert!(file.heightmap.samples[1].is_nan());
+        assert_eq!(file.heightmap.samples[3], 9.0);
+
+        let short = "ncols 2\nnrows 2\n1 2 3\n";
+        assert!(
+            HeightmapFile::decode(HeightFormat::Asc, short.as_bytes(), elevation, None).is_err()
+        );
+    }
+
+    #[test]
+    fn hgt_round_trip() {
+        let mut heightmap = Heightmap::from_fn(3, 3, |x, z| (x as f32 - 1.0) * 1200.0 + z as f32);
+        heightmap.samples[4] = f32::NAN;
+        let file = HeightmapFile::new(heightmap, Elevation::default());
+
+        let bytes = file.encode(HeightFormat::Hgt).unwrap();
+        assert_eq!(&bytes[..2], &(-1200_i16).to_be_bytes());
+        assert_eq!(&bytes[8..10], &i16::MIN.to_be_bytes());
+
+        let read = round_trip(&file, HeightFormat::Hgt);
+        assert_close(&read.heightmap, &file.heightmap, 0.0);
+        assert_eq!(read.elevation.no_data, Some(HGT_NO_DATA));
+        assert_eq!(read.cell_size, Some(0.5));
+
+        let wide = HeightmapFile::new(terrain(), Elevation::default());
+        assert!(wide.encode(HeightFormat::Hgt).is_err());
+    }
+
+    #[test]
+    fn missing_cells_need_no_data() {
+        let mut heightmap = terrain();
+        heightmap.samples[0] = f32::NAN;
+        let file = HeightmapFile::new(heightmap, Elevation::default());
+        assert!(file.encode(HeightFormat::R32).is_err());
+    }
+
+    #[test]
+    fn fits_heights_into_integers() {
+        // Heights run from -6.75 to 40.25
+        let mut heightmap = terrain();
+        let elevation = Elevation::fit(&heightmap, 65535.0);
+        assert_eq!(elevation.no_data, None);
+        assert_eq!(elevation.stored(-6.75).unwrap(), 0.0);
+        assert!((elevation.stored(40.25).unwrap() - 65535.0).abs() < 0.01);
+
+        heightmap.samples[1] = f32::NAN;
+        let elevation = Elevation::fit(&heightmap, 65535.0);
+        assert_eq!(elevation.no_data, Some(65535.0));
+        assert!((elevation.stored(40.25).unwrap() - 65534.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn names_hgt_corners() {
+        assert_eq!(hgt_origin("N45W122"), Some([-122.0, 45.0]));
+        assert_eq!(hgt_origin("s03e010"), Some([10.0, -3.0]));
+        assert_eq!(hgt_origin("terrain"), None);
+    }
+}
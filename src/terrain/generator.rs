@@ -0,0 +1,98 @@
+use super::{fbm, FbmParams, Heightmap, NoiseGraph, NoiseSource, TerrainConfig};
+use crate::Result;
+
+pub struct TerrainGenerator {
+    source: Box<dyn NoiseSource + Send + Sync>,
+    // Recipes do their own octaves, so only plain noise goes through `fbm`
+    params: Option<FbmParams>,
+    height_scale: f32,
+}
+
+impl TerrainGenerator {
+    /// Fails if the config's noise graph recipe can't be loaded.
+    pub fn new(config: &TerrainConfig) -> Result<TerrainGenerator> {
+        let (source, params): (Box<dyn NoiseSource + Send + Sync>, _) = match &config.recipe {
+            Some(path) => (Box::new(NoiseGraph::load(path, config.seed)?), None),
+            None => {
+                let params = FbmParams {
+                    octaves: config.octaves,
+                    frequency: config.frequency,
+                    lacunarity: config.lacunarity,
+                    persistence: config.persistence,
+                };
+                (config.noise.source(config.seed), Some(params))
+            }
+        };
+
+        Ok(TerrainGenerator {
+            source,
+            params,
+            height_scale: config.height_scale,
+        })
+    }
+
+    /// World height at sample coordinates `(x, z)`.
+    pub fn height_at(&self, x: f64, z: f64) -> f32 {
+        let value = match &self.params {
+            Some(params) => fbm(self.source.as_ref(), x, z, params),
+            None => self.source.sample(x, z),
+        };
+        value as f32 * self.height_scale
+    }
+
+    /// Heightmap of `width` x `depth` samples whose first sample sits at `origin`.
+    pub fn heightmap(&self, origin: [i64; 2], width: usize, depth: usize) -> Heightmap {
+        Heightmap::from_fn(width, depth, |x, z| {
+            self.height_at((origin[0] + x as i64) as f64, (origin[1] + z as i64) as f64)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::NoiseKind;
+
+    fn heightmap(seed: u64, noise: NoiseKind) -> Heightmap {
+        let config = TerrainConfig {
+            seed,
+            noise,
+            ..TerrainConfig::default()
+        };
+        TerrainGenerator::new(&config)
+            .unwrap()
+            .heightmap([-8, 16], 32, 24)
+    }
+
+    #[test]
+    fn same_seed_gives_same_heights() {
+        for &noise in &[
+            NoiseKind::Perlin,
+            NoiseKind::Simplex,
+            NoiseKind::Value,
+            NoiseKind::Worley,
+        ] {
+            assert_eq!(heightmap(7, noise), heightmap(7, noise));
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_heights() {
+        for &noise in &[
+            NoiseKind::Perlin,
+            NoiseKind::Simplex,
+            NoiseKind::Value,
+            NoiseKind::Worley,
+        ] {
+            assert_ne!(heightmap(7, noise), heightmap(8, noise));
+        }
+    }
+
+    #[test]
+    fn heightmap_samples_from_its_origin() {
+        let generator = TerrainGenerator::new(&TerrainConfig::default()).unwrap();
+        let heightmap = generator.heightmap([-8, 16], 32, 24);
+        assert_eq!(heightmap.samples.len(), 32 * 24);
+        assert_eq!(heightmap.get(3, 5), generator.height_at(-5.0, 21.0));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::na;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heightmap {
+    pub width: usize,
+    pub depth: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(width: usize, depth: usize) -> Heightmap {
+        Heightmap {
+            width,
+            depth,
+            samples: vec![0.0; width * depth],
+        }
+    }
+
+    pub fn from_fn<F: FnMut(usize, usize) -> f32>(width: usize, depth: usize, mut f: F) -> Heightmap {
+        let mut samples = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                samples.push(f(x, z));
+            }
+        }
+        Heightmap {
+            width,
+            depth,
+            samples,
+        }
+    }
+
+    pub fn index(&self, x: usize, z: usize) -> usize {
+        z * self.width + x
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> f32 {
+        self.samples[self.index(x, z)]
+    }
+
+    /// Height at integer coordinates, clamped to the map edges.
+    pub fn get_clamped(&self, x: isize, z: isize) -> f32 {
+        let x = x.max(0).min(self.width as isize - 1) as usize;
+        let z = z.max(0).min(self.depth as isize - 1) as usize;
+        self.get(x, z)
+    }
+
+    /// Bilinearly interpolated height at fractional sample coordinates.
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let x0 = x.floor();
+        let z0 = z.floor();
+        let (fx, fz) = (x - x0, z - z0);
+        let (x0, z0) = (x0 as isize, z0 as isize);
+
+        let h00 = self.get_clamped(x0, z0);
+        let h10 = self.get_clamped(x0 + 1, z0);
+        let h01 = self.get_clamped(x0, z0 + 1);
+        let h11 = self.get_clamped(x0 + 1, z0 + 1);
+
+        let top = h00 + (h10 - h00) * fx;
+        let bottom = h01 + (h11 - h01) * fx;
+        top + (bottom - top) * fz
+    }
+
+    /// Surface normal from central differences, with samples `cell_size` apart.
+    pub fn normal(&self, x: usize, z: usize, cell_size: f32) -> na::Vector3<f32> {
+        let (x, z) = (x as isize, z as isize);
+        let dx = self.get_clamped(x - 1, z) - self.get_clamped(x + 1, z);
+        let dz = self.get_clamped(x, z - 1) - self.get_clamped(x, z + 1);
+        na::Vector3::new(dx, 2.0 * cell_size, dz).normalize()
+    }
+
+    pub fn min_max(&self) -> (f32, f32) {
+        self.samples
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &h| (min.min(h), max.max(h)))
+    }
+}
@@ -0,0 +1,145 @@
+use super::{Heightmap, SplatMap};
+use crate::objects::{Mesh, VertexAttribute};
+use crate::Result;
+use std::mem;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TerrainVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    /// Material layer weights, see `terrain::splat_weights`.
+    pub weights: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for TerrainVertex {}
+unsafe impl bytemuck::Zeroable for TerrainVertex {}
+
+impl VertexAttribute for TerrainVertex {
+    fn description<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<TerrainVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+/// Builds a grid mesh over the heightmap with samples `cell_size` apart. The first sample sits at
+/// the origin and the grid extends along +x and +z. `splat` gives each vertex its material weights
+/// and must match the heightmap's size.
+pub fn build_mesh(
+    heightmap: &Heightmap,
+    splat: &SplatMap,
+    cell_size: f32,
+) -> Result<Mesh<TerrainVertex>> {
+    let (width, depth) = (heightmap.width, heightmap.depth);
+    if (splat.width, splat.depth) != (width, depth) {
+        return Err(format!(
+            "Splat map {}x{} doesn't match heightmap {}x{}",
+            splat.width, splat.depth, width, depth
+        )
+        .into());
+    }
+    if width < 2 || depth < 2 {
+        return Err(format!("Heightmap {}x{} is too small to mesh", width, depth).into());
+    }
+    if width * depth > u16::MAX as usize + 1 {
+        return Err(format!(
+            "Heightmap {}x{} has too many vertices for 16 bit indices",
+            width, depth
+        )
+        .into());
+    }
+
+    let mut vertices = Vec::with_capacity(width * depth);
+    for z in 0..depth {
+        for x in 0..width {
+            let normal = heightmap.normal(x, z, cell_size);
+            vertices.push(TerrainVertex {
+                position: [
+                    x as f32 * cell_size,
+                    heightmap.get(x, z),
+                    z as f32 * cell_size,
+                ],
+                normal: [normal.x, normal.y, normal.z],
+                uv: [
+                    x as f32 / (width - 1) as f32,
+                    z as f32 / (depth - 1) as f32,
+                ],
+                weights: splat.get(x, z),
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity((width - 1) * (depth - 1) * 6);
+    for z in 0..depth - 1 {
+        for x in 0..width - 1 {
+            let i00 = (z * width + x) as u16;
+            let i10 = i00 + 1;
+            let i01 = i00 + width as u16;
+            let i11 = i01 + 1;
+            indices.extend_from_slice(&[i00, i01, i10, i10, i01, i11]);
+        }
+    }
+
+    Ok(Mesh::new(vertices, indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn splat(width: usize, depth: usize) -> SplatMap {
+        SplatMap {
+            width,
+            depth,
+            weights: vec![[1.0, 0.0, 0.0, 0.0]; width * depth],
+        }
+    }
+
+    #[test]
+    fn one_vertex_per_sample_and_two_triangles_per_cell() {
+        let heightmap = Heightmap::from_fn(5, 3, |x, z| (x * z) as f32);
+        let mesh = build_mesh(&heightmap, &splat(5, 3), 2.0).unwrap();
+
+        assert_eq!(mesh.vertices.len(), 5 * 3);
+        assert_eq!(mesh.indices.len(), 4 * 2 * 6);
+        assert!(mesh
+            .indices
+            .iter()
+            .all(|&i| (i as usize) < mesh.vertices.len()));
+        assert_eq!(mesh.vertices[14].position, [8.0, 8.0, 4.0]);
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        let heightmap = Heightmap::new(4, 4);
+        assert!(build_mesh(&heightmap, &splat(4, 3), 1.0).is_err());
+        assert!(build_mesh(&Heightmap::new(1, 4), &splat(1, 4), 1.0).is_err());
+        assert!(build_mesh(&Heightmap::new(257, 256), &splat(257, 256), 1.0).is_err());
+        assert!(build_mesh(&Heightmap::new(256, 256), &splat(256, 256), 1.0).is_ok());
+    }
+}
@@ -0,0 +1,299 @@
+use super::Rng;
+use serde::Deserialize;
+
+mod graph;
+
+pub use graph::NoiseGraph;
+
+pub trait NoiseSource {
+    /// Noise value at `(x, y)`, roughly in `[-1, 1]`.
+    fn sample(&self, x: f64, y: f64) -> f64;
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    Value,
+    Worley,
+}
+
+impl NoiseKind {
+    pub fn source(self, seed: u64) -> Box<dyn NoiseSource + Send + Sync> {
+        match self {
+            NoiseKind::Perlin => Box::new(Perlin::new(seed)),
+            NoiseKind::Simplex => Box::new(Simplex::new(seed)),
+            NoiseKind::Value => Box::new(Value::new(seed)),
+            NoiseKind::Worley => Box::new(Worley::new(seed)),
+        }
+    }
+}
+
+const GRADIENTS: [[f64; 2]; 8] = [
+    [1.0, 1.0],
+    [-1.0, 1.0],
+    [1.0, -1.0],
+    [-1.0, -1.0],
+    [1.0, 0.0],
+    [-1.0, 0.0],
+    [0.0, 1.0],
+    [0.0, -1.0],
+];
+
+// Seeded permutation table shared by the gradient noise types.
+#[derive(Clone)]
+struct Permutation {
+    table: [u8; 512],
+}
+
+impl Permutation {
+    fn new(seed: u64) -> Permutation {
+        let mut rng = Rng::new(seed);
+        let mut values = [0u8; 256];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = i as u8;
+        }
+        for i in (1..values.len()).rev() {
+            let j = rng.below(i + 1);
+            values.swap(i, j);
+        }
+
+        let mut table = [0u8; 512];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = values[i & 255];
+        }
+        Permutation { table }
+    }
+
+    fn hash(&self, x: i64, y: i64) -> usize {
+        let x = (x & 255) as usize;
+        let y = (y & 255) as usize;
+        self.table[self.table[x] as usize + y] as usize
+    }
+
+    // Uniform value in `[0, 1]` per lattice point, `channel` picks between independent ones
+    fn unit(&self, x: i64, y: i64, channel: usize) -> f64 {
+        self.table[self.hash(x, y) + channel] as f64 / 255.0
+    }
+
+    fn gradient(&self, x: i64, y: i64, dx: f64, dy: f64) -> f64 {
+        let g = GRADIENTS[self.hash(x, y) & 7];
+        g[0] * dx + g[1] * dy
+    }
+}
+
+#[derive(Clone)]
+pub struct Perlin {
+    perm: Permutation,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        Perlin {
+            perm: Permutation::new(seed),
+        }
+    }
+}
+
+impl NoiseSource for Perlin {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let (xi, yi) = (x0 as i64, y0 as i64);
+        let (dx, dy) = (x - x0, y - y0);
+
+        let n00 = self.perm.gradient(xi, yi, dx, dy);
+        let n10 = self.perm.gradient(xi + 1, yi, dx - 1.0, dy);
+        let n01 = self.perm.gradient(xi, yi + 1, dx, dy - 1.0);
+        let n11 = self.perm.gradient(xi + 1, yi + 1, dx - 1.0, dy - 1.0);
+
+        let u = fade(dx);
+        let v = fade(dy);
+        lerp(lerp(n00, n10, u), lerp(n01, n11, u), v)
+    }
+}
+
+#[derive(Clone)]
+pub struct Simplex {
+    perm: Permutation,
+}
+
+impl Simplex {
+    pub fn new(seed: u64) -> Simplex {
+        Simplex {
+            perm: Permutation::new(seed),
+        }
+    }
+
+    fn corner(&self, x: i64, y: i64, dx: f64, dy: f64) -> f64 {
+        let t = 0.5 - dx * dx - dy * dy;
+        if t < 0.0 {
+            0.0
+        } else {
+            let t2 = t * t;
+            t2 * t2 * self.perm.gradient(x, y, dx, dy)
+        }
+    }
+}
+
+impl NoiseSource for Simplex {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let f2 = 0.5 * (3.0f64.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0f64.sqrt()) / 6.0;
+
+        // Skew into simplex cell space to find the containing triangle
+        let s = (x + y) * f2;
+        let i = (x + s).floor();
+        let j = (y + s).floor();
+        let t = (i + j) * g2;
+        let x0 = x - (i - t);
+        let y0 = y - (j - t);
+
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let x1 = x0 - i1 as f64 + g2;
+        let y1 = y0 - j1 as f64 + g2;
+        let x2 = x0 - 1.0 + 2.0 * g2;
+        let y2 = y0 - 1.0 + 2.0 * g2;
+
+        let (i, j) = (i as i64, j as i64);
+        let n0 = self.corner(i, j, x0, y0);
+        let n1 = self.corner(i + i1, j + j1, x1, y1);
+        let n2 = self.corner(i + 1, j + 1, x2, y2);
+
+        // Scales the result to roughly [-1, 1]
+        70.0 * (n0 + n1 + n2)
+    }
+}
+
+/// Smoothly interpolated random values on the integer lattice, blobbier than gradient noise.
+#[derive(Clone)]
+pub struct Value {
+    perm: Permutation,
+}
+
+impl Value {
+    pub fn new(seed: u64) -> Value {
+        Value {
+            perm: Permutation::new(seed),
+        }
+    }
+}
+
+impl NoiseSource for Value {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let (xi, yi) = (x0 as i64, y0 as i64);
+        let corner = |x, y| self.perm.unit(x, y, 0) * 2.0 - 1.0;
+
+        let u = fade(x - x0);
+        let v = fade(y - y0);
+        lerp(
+            lerp(corner(xi, yi), corner(xi + 1, yi), u),
+            lerp(corner(xi, yi + 1), corner(xi + 1, yi + 1), u),
+            v,
+        )
+    }
+}
+
+/// Cellular noise, the distance to the nearest of one randomly placed point per lattice cell.
+/// -1 on the points, rising to 1 towards the cell borders.
+#[derive(Clone)]
+pub struct Worley {
+    perm: Permutation,
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Worley {
+        Worley {
+            perm: Permutation::new(seed),
+        }
+    }
+}
+
+impl NoiseSource for Worley {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let (xi, yi) = (x.floor() as i64, y.floor() as i64);
+        let mut nearest = f64::MAX;
+        for cy in yi - 1..=yi + 1 {
+            for cx in xi - 1..=xi + 1 {
+                let px = cx as f64 + self.perm.unit(cx, cy, 0);
+                let py = cy as f64 + self.perm.unit(cx, cy, 1);
+                let distance = (px - x).powi(2) + (py - y).powi(2);
+                nearest = nearest.min(distance);
+            }
+        }
+        (nearest.sqrt() * 2.0 - 1.0).min(1.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FbmParams {
+    pub octaves: u32,
+    pub frequency: f64,
+    pub lacunarity: f64,
+    pub persistence: f64,
+}
+
+/// Fractal Brownian motion: sums `octaves` layers of `source`, each at `lacunarity` times the
+/// frequency and `persistence` times the amplitude of the previous one. The result is
+/// normalised back to roughly `[-1, 1]`.
+pub fn fbm(source: &dyn NoiseSource, x: f64, y: f64, params: &FbmParams) -> f64 {
+    let mut frequency = params.frequency;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut max_amplitude = 0.0;
+
+    for octave in 0..params.octaves {
+        // Offset each octave so the lattice origins don't line up
+        let offset = octave as f64 * 17.31;
+        total += amplitude * source.sample(x * frequency + offset, y * frequency + offset);
+        max_amplitude += amplitude;
+        frequency *= params.lacunarity;
+        amplitude *= params.persistence;
+    }
+
+    if max_amplitude > 0.0 {
+        total / max_amplitude
+    } else {
+        0.0
+    }
+}
+
+/// Ridged multifractal: like `fbm` but each octave is folded into a sharp crest where `source`
+/// crosses zero, and later octaves only add detail where earlier ones are already high. Gives
+/// mountain ranges with sharp ridges and smooth valleys, roughly in `[-1, 1]`.
+pub fn ridged(source: &dyn NoiseSource, x: f64, y: f64, params: &FbmParams) -> f64 {
+    let mut frequency = params.frequency;
+    let mut amplitude = 1.0;
+    let mut weight = 1.0;
+    let mut total = 0.0;
+    let mut max_amplitude = 0.0;
+
+    for octave in 0..params.octaves {
+        let offset = octave as f64 * 17.31;
+        let noise = source.sample(x * frequency + offset, y * frequency + offset);
+        let crest = 1.0 - noise.abs();
+        let signal = crest * crest * weight;
+        weight = (signal * 2.0).clamp(0.0, 1.0);
+        total += amplitude * signal;
+        max_amplitude += amplitude;
+        frequency *= params.lacunarity;
+        amplitude *= params.persistence;
+    }
+
+    if max_amplitude > 0.0 {
+        total / max_amplitude * 2.0 - 1.0
+    } else {
+        0.0
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
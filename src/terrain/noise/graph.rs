@@ -0,0 +1,581 @@
+use super::{fbm, ridged, FbmParams, NoiseKind, NoiseSource};
+use crate::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::{fs, path::Path};
+
+/// A terrain recipe, named nodes wired together by name with `output` as the final height.
+/// Read from TOML like
+///
+/// ```toml
+/// output = "land"
+///
+/// [nodes.hills]
+/// type = "fractal"
+/// noise = "perlin"
+/// frequency = 0.01
+/// octaves = 5
+///
+/// [nodes.land]
+/// type = "terrace"
+/// input = "hills"
+/// steps = 6
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct NoiseGraphConfig {
+    pub output: String,
+    pub nodes: BTreeMap<String, NodeConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeConfig {
+    Constant {
+        value: f64,
+    },
+    /// `noise` summed over octaves like `fbm`, a single octave gives the plain noise.
+    Fractal {
+        noise: NoiseKind,
+        #[serde(default)]
+        seed: u64,
+        frequency: f64,
+        #[serde(default = "one_octave")]
+        octaves: u32,
+        #[serde(default = "default_lacunarity")]
+        lacunarity: f64,
+        #[serde(default = "default_persistence")]
+        persistence: f64,
+    },
+    /// Ridged multifractal, see `ridged`.
+    Ridged {
+        #[serde(default = "default_noise")]
+        noise: NoiseKind,
+        #[serde(default)]
+        seed: u64,
+        frequency: f64,
+        #[serde(default = "one_octave")]
+        octaves: u32,
+        #[serde(default = "default_lacunarity")]
+        lacunarity: f64,
+        #[serde(default = "default_persistence")]
+        persistence: f64,
+    },
+    /// Samples `input` at coordinates pushed up to `strength` samples away by fractal noise,
+    /// bending straight features into swirls.
+    Warp {
+        input: String,
+        strength: f64,
+        #[serde(default = "default_noise")]
+        noise: NoiseKind,
+        #[serde(default)]
+        seed: u64,
+        frequency: f64,
+        #[serde(default = "one_octave")]
+        octaves: u32,
+    },
+    /// Flattens `input` into `steps` shelves across `[-1, 1]`. `sharpness` 1 gives hard steps,
+    /// 0 leaves the input alone.
+    Terrace {
+        input: String,
+        steps: u32,
+        #[serde(default = "default_sharpness")]
+        sharpness: f64,
+    },
+    Clamp {
+        input: String,
+        min: f64,
+        max: f64,
+    },
+    /// Remaps `input` through the `[from, to]` points, linearly between them and held flat
+    /// past the ends.
+    Curve {
+        input: String,
+        points: Vec<[f64; 2]>,
+    },
+    Abs {
+        input: String,
+    },
+    /// `input * scale + bias`.
+    ScaleBias {
+        input: String,
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        bias: f64,
+    },
+    Add {
+        inputs: Vec<String>,
+    },
+    Multiply {
+        inputs: Vec<String>,
+    },
+    /// `a` where `control` is below `threshold`, `b` above it, blended across `falloff` either
+    /// side.
+    Select {
+        a: String,
+        b: String,
+        control: String,
+        #[serde(default)]
+        threshold: f64,
+        #[serde(default)]
+        falloff: f64,
+    },
+    /// Mixes `a` into `b` as `mask` goes from -1 to 1.
+    Blend {
+        a: String,
+        b: String,
+        mask: String,
+    },
+}
+
+fn one_octave() -> u32 {
+    1
+}
+
+fn default_lacunarity() -> f64 {
+    2.0
+}
+
+fn default_persistence() -> f64 {
+    0.5
+}
+
+fn default_noise() -> NoiseKind {
+    NoiseKind::Perlin
+}
+
+fn default_sharpness() -> f64 {
+    1.0
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+type Source = Box<dyn NoiseSource + Send + Sync>;
+
+// Nodes refer to their inputs by index, always an earlier node
+enum Node {
+    Constant(f64),
+    Fractal(Source, FbmParams),
+    Ridged(Source, FbmParams),
+    Warp {
+        input: usize,
+        x: Source,
+        y: Source,
+        params: FbmParams,
+        strength: f64,
+    },
+    Terrace {
+        input: usize,
+        steps: f64,
+        sharpness: f64,
+    },
+    Clamp(usize, f64, f64),
+    Curve(usize, Vec<[f64; 2]>),
+    Abs(usize),
+    ScaleBias(usize, f64, f64),
+    Add(Vec<usize>),
+    Multiply(Vec<usize>),
+    Select {
+        a: usize,
+        b: usize,
+        control: usize,
+        threshold: f64,
+        falloff: f64,
+    },
+    Blend(usize, usize, usize),
+}
+
+/// A compiled `NoiseGraphConfig`, sampled like any other noise source.
+pub struct NoiseGraph {
+    nodes: Vec<Node>,
+}
+
+impl NoiseGraph {
+    /// Reads a recipe from a TOML file. Every node's `seed` is added to `seed`, so one terrain
+    /// seed still changes the whole graph.
+    pub fn load(path: &Path, seed: u64) -> Result<NoiseGraph> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read noise graph {}: {}", path.display(), e))?;
+        let config: NoiseGraphConfig = toml::from_str(&text)
+            .map_err(|e| format!("Failed to parse noise graph {}: {}", path.display(), e))?;
+        NoiseGraph::new(&config, seed)
+    }
+
+    /// Fails on references to missing nodes, on cycles and on curves without usable points.
+    pub fn new(config: &NoiseGraphConfig, seed: u64) -> Result<NoiseGraph> {
+        let mut builder = Builder {
+            config,
+            seed,
+            nodes: Vec::new(),
+            built: BTreeMap::new(),
+            visiting: Vec::new(),
+        };
+        builder.build(&config.output, "output")?;
+        Ok(NoiseGraph {
+            nodes: builder.nodes,
+        })
+    }
+
+    fn eval(&self, node: usize, x: f64, y: f64) -> f64 {
+        match &self.nodes[node] {
+            Node::Constant(value) => *value,
+            Node::Fractal(source, params) => fbm(source.as_ref(), x, y, params),
+            Node::Ridged(source, params) => ridged(source.as_ref(), x, y, params),
+            Node::Warp {
+                input,
+                x: warp_x,
+                y: warp_y,
+                params,
+                strength,
+            } => {
+                let dx = fbm(warp_x.as_ref(), x, y, params) * strength;
+                let dy = fbm(warp_y.as_ref(), x, y, params) * strength;
+                self.eval(*input, x + dx, y + dy)
+            }
+            Node::Terrace {
+                input,
+                steps,
+                sharpness,
+            } => terrace(self.eval(*input, x, y), *steps, *sharpness),
+            Node::Clamp(input, min, max) => self.eval(*input, x, y).max(*min).min(*max),
+            Node::Curve(input, points) => curve(self.eval(*input, x, y), points),
+            Node::Abs(input) => self.eval(*input, x, y).abs(),
+            Node::ScaleBias(input, scale, bias) => self.eval(*input, x, y) * scale + bias,
+            Node::Add(inputs) => inputs.iter().map(|&input| self.eval(input, x, y)).sum(),
+            Node::Multiply(inputs) => inputs.iter().map(|&input| self.eval(input, x, y)).product(),
+            Node::Select {
+                a,
+                b,
+                control,
+                threshold,
+                falloff,
+            } => {
+                let control = self.eval(*control, x, y);
+                let t = if *falloff > 0.0 {
+                    smoothstep(threshold - falloff, threshold + falloff, control)
+                } else if control < *threshold {
+                    0.0
+                } else {
+                    1.0
+                };
+                // Only evaluate the side that shows
+                if t <= 0.0 {
+                    self.eval(*a, x, y)
+                } else if t >= 1.0 {
+                    self.eval(*b, x, y)
+                } else {
+                    lerp(self.eval(*a, x, y), self.eval(*b, x, y), t)
+                }
+            }
+            Node::Blend(a, b, mask) => {
+                let t = ((self.eval(*mask, x, y) + 1.0) / 2.0).clamp(0.0, 1.0);
+                lerp(self.eval(*a, x, y), self.eval(*b, x, y), t)
+            }
+        }
+    }
+}
+
+impl NoiseSource for NoiseGraph {
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        // Building pushes the output last
+        self.eval(self.nodes.len() - 1, x, y)
+    }
+}
+
+// Compiles named nodes depth first, so a node's inputs always come before it
+struct Builder<'a> {
+    config: &'a NoiseGraphConfig,
+    seed: u64,
+    nodes: Vec<Node>,
+    built: BTreeMap<&'a str, usize>,
+    visiting: Vec<&'a str>,
+}
+
+impl<'a> Builder<'a> {
+    fn build(&mut self, name: &'a str, from: &str) -> Result<usize> {
+        if let Some(&index) = self.built.get(name) {
+            return Ok(index);
+        }
+        if self.visiting.contains(&name) {
+            return Err(format!(
+                "Noise graph has a cycle: {} -> {}",
+                self.visiting.join(" -> "),
+                name
+            )
+            .into());
+        }
+        let config =
+            self.config.nodes.get(name).ok_or_else(|| {
+                format!("Noise graph node '{}' uses missing node '{}'", from, name)
+            })?;
+
+        self.visiting.push(name);
+        let node = match config {
+            NodeConfig::Constant { value } => Node::Constant(*value),
+            NodeConfig::Fractal {
+                noise,
+                seed,
+                frequency,
+                octaves,
+                lacunarity,
+                persistence,
+            } => Node::Fractal(
+                noise.source(self.seed.wrapping_add(*seed)),
+                FbmParams {
+                    octaves: *octaves,
+                    frequency: *frequency,
+                    lacunarity: *lacunarity,
+                    persistence: *persistence,
+                },
+            ),
+            NodeConfig::Ridged {
+                noise,
+                seed,
+                frequency,
+                octaves,
+                lacunarity,
+                persistence,
+            } => Node::Ridged(
+                noise.source(self.seed.wrapping_add(*seed)),
+                FbmParams {
+                    octaves: *octaves,
+                    frequency: *frequency,
+                    lacunarity: *lacunarity,
+                    persistence: *persistence,
+                },
+            ),
+            NodeConfig::Warp {
+                input,
+                strength,
+                noise,
+                seed,
+                frequency,
+                octaves,
+            } => {
+                let seed = self.seed.wrapping_add(*seed);
+                Node::Warp {
+                    input: self.build(input, name)?,
+                    x: noise.source(seed),
+                    y: noise.source(seed.wrapping_add(1)),
+                    params: FbmParams {
+                        octaves: *octaves,
+                        frequency: *frequency,
+                        lacunarity: default_lacunarity(),
+                        persistence: default_persistence(),
+                    },
+                    strength: *strength,
+                }
+            }
+            NodeConfig::Terrace {
+                input,
+                steps,
+                sharpness,
+            } => Node::Terrace {
+                input: self.build(input, name)?,
+                steps: (*steps).max(1) as f64,
+                sharpness: sharpness.clamp(0.0, 1.0),
+            },
+            NodeConfig::Clamp { input, min, max } => {
+                Node::Clamp(self.build(input, name)?, *min, *max)
+            }
+            NodeConfig::Curve { input, points } => {
+                if points.is_empty() {
+                    return Err(format!("Noise graph curve '{}' has no points", name).into());
+                }
+                if points.iter().flatten().any(|value| !value.is_finite()) {
+                    return Err(
+                        format!("Noise graph curve '{}' has a non-finite point", name).into(),
+                    );
+                }
+                let mut points = points.clone();
+                points.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+                Node::Curve(self.build(input, name)?, points)
+            }
+            NodeConfig::Abs { input } => Node::Abs(self.build(input, name)?),
+            NodeConfig::ScaleBias { input, scale, bias } => {
+                Node::ScaleBias(self.build(input, name)?, *scale, *bias)
+            }
+            NodeConfig::Add { inputs } => Node::Add(self.build_all(inputs, name)?),
+            NodeConfig::Multiply { inputs } => Node::Multiply(self.build_all(inputs, name)?),
+            NodeConfig::Select {
+                a,
+                b,
+                control,
+                threshold,
+                falloff,
+            } => Node::Select {
+                a: self.build(a, name)?,
+                b: self.build(b, name)?,
+                control: self.build(control, name)?,
+                threshold: *threshold,
+                falloff: falloff.max(0.0),
+            },
+            NodeConfig::Blend { a, b, mask } => Node::Blend(
+                self.build(a, name)?,
+                self.build(b, name)?,
+                self.build(mask, name)?,
+            ),
+        };
+        self.visiting.pop();
+
+        self.nodes.push(node);
+        let index = self.nodes.len() - 1;
+        self.built.insert(name, index);
+        Ok(index)
+    }
+
+    fn build_all(&mut self, names: &'a [String], from: &str) -> Result<Vec<usize>> {
+        names.iter().map(|name| self.build(name, from)).collect()
+    }
+}
+
+fn terrace(value: f64, steps: f64, sharpness: f64) -> f64 {
+    // Shelves evenly spaced over [-1, 1], each flat at first then climbing steeply to the next
+    let scaled = (value + 1.0) / 2.0 * steps;
+    let shelf = scaled.floor();
+    let stepped = (shelf + (scaled - shelf).powi(3)) / steps * 2.0 - 1.0;
+    lerp(value, stepped, sharpness)
+}
+
+fn curve(value: f64, points: &[[f64; 2]]) -> f64 {
+    let first = points[0];
+    let last = points[points.len() - 1];
+    if value <= first[0] {
+        return first[1];
+    }
+    if value >= last[0] {
+        return last[1];
+    }
+    let i = points.iter().position(|point| point[0] > value).unwrap();
+    let ([x0, y0], [x1, y1]) = (points[i - 1], points[i]);
+    lerp(y0, y1, (value - x0) / (x1 - x0))
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(recipe: &str) -> Result<NoiseGraph> {
+        let config: NoiseGraphConfig = toml::from_str(recipe).unwrap();
+        NoiseGraph::new(&config, 1)
+    }
+
+    fn error(recipe: &str) -> String {
+        graph(recipe).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn evaluates_a_recipe() {
+        let graph = graph(
+            r#"
+            output = "land"
+
+            [nodes.low]
+            type = "constant"
+            value = -0.5
+
+            [nodes.high]
+            type = "constant"
+            value = 0.75
+
+            [nodes.control]
+            type = "constant"
+            value = 0.2
+
+            [nodes.picked]
+            type = "select"
+            a = "low"
+            b = "high"
+            control = "control"
+
+            [nodes.curved]
+            type = "curve"
+            input = "picked"
+            points = [[1.0, 1.0], [0.0, 0.0]]
+
+            [nodes.land]
+            type = "terrace"
+            input = "curved"
+            steps = 2
+            "#,
+        )
+        .unwrap();
+
+        // 0.75 sits three quarters of the way up the upper shelf, which climbs as the cube
+        assert!((graph.sample(3.0, -7.0) - 0.421_875).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let message = error(
+            r#"
+            output = "a"
+
+            [nodes.a]
+            type = "abs"
+            input = "b"
+
+            [nodes.b]
+            type = "add"
+            inputs = ["a"]
+            "#,
+        );
+        assert_eq!(message, "Noise graph has a cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn rejects_missing_nodes() {
+        let message = error(
+            r#"
+            output = "a"
+
+            [nodes.a]
+            type = "abs"
+            input = "nothing"
+            "#,
+        );
+        assert_eq!(message, "Noise graph node 'a' uses missing node 'nothing'");
+    }
+
+    #[test]
+    fn rejects_bad_curves() {
+        let recipe = |points: &str| {
+            format!(
+                "output = \"a\"\n[nodes.b]\ntype = \"constant\"\nvalue = 0.0\n\
+                 [nodes.a]\ntype = \"curve\"\ninput = \"b\"\npoints = {}",
+                points
+            )
+        };
+        assert!(error(&recipe("[]")).contains("no points"));
+        assert!(error(&recipe("[[0.0, 1.0], [nan, 0.0]]")).contains("non-finite"));
+        assert!(graph(&recipe("[[0.0, 1.0]]")).is_ok());
+    }
+
+    #[test]
+    fn shaping_functions() {
+        assert_eq!(terrace(-1.0, 4.0, 1.0), -1.0);
+        assert_eq!(terrace(0.3, 4.0, 0.0), 0.3);
+        // Just above a shelf the terrace stays flat
+        assert!((terrace(0.01, 4.0, 1.0) - 0.0).abs() < 1e-5);
+
+        let points = [[-1.0, 2.0], [0.0, 0.0], [1.0, 1.0]];
+        assert_eq!(curve(-5.0, &points), 2.0);
+        assert_eq!(curve(-0.5, &points), 1.0);
+        assert_eq!(curve(0.5, &points), 0.5);
+        assert_eq!(curve(5.0, &points), 1.0);
+
+        assert_eq!(smoothstep(0.0, 1.0, 0.5), 0.5);
+        assert_eq!(smoothstep(0.0, 1.0, -1.0), 0.0);
+    }
+}
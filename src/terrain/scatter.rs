@@ -0,0 +1,196 @@
+use super::{fbm, FbmParams, Heightmap, Perlin, Rng};
+use crate::na;
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ScatterConfig {
+    pub seed: u64,
+    /// Chance of an instance in each heightmap cell where the height, slope and noise allow one.
+    pub density: f32,
+    pub min_height: f32,
+    pub max_height: f32,
+    /// Slope limits in degrees from horizontal.
+    pub min_slope: f32,
+    pub max_slope: f32,
+    // Density mask
+    pub noise_frequency: f64,
+    pub noise_octaves: u32,
+    /// Mask values below this stay empty, the density ramps up to full over `noise_falloff`.
+    pub noise_threshold: f32,
+    pub noise_falloff: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+}
+
+impl Default for ScatterConfig {
+    fn default() -> Self {
+        ScatterConfig {
+            seed: 1,
+            density: 0.05,
+            min_height: f32::MIN,
+            max_height: f32::MAX,
+            min_slope: 0.0,
+            max_slope: 30.0,
+            noise_frequency: 0.02,
+            noise_octaves: 3,
+            noise_threshold: 0.0,
+            noise_falloff: 0.3,
+            min_scale: 0.5,
+            max_scale: 1.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScatterPoint {
+    pub position: na::Point3<f32>,
+    /// Terrain normal under the instance.
+    pub normal: na::Vector3<f32>,
+    /// Rotation about +y in radians.
+    pub yaw: f32,
+    pub scale: f32,
+    /// Uniform in `[0, 1)`, for varying tints between instances.
+    pub variation: f32,
+}
+
+/// Places instances over `heightmap`, at most one per cell, wherever the height, slope and
+/// density mask allow and `keep(x, z)` is true for the cell. The same seed always gives the
+/// same points.
+pub fn scatter(
+    heightmap: &Heightmap,
+    cell_size: f32,
+    config: &ScatterConfig,
+    keep: impl Fn(usize, usize) -> bool,
+) -> Vec<ScatterPoint> {
+    let mut rng = Rng::new(config.seed);
+    let mask = Perlin::new(config.seed ^ 0x5CA7_7E12);
+    let params = FbmParams {
+        octaves: config.noise_octaves,
+        frequency: config.noise_frequency,
+        lacunarity: 2.0,
+        persistence: 0.5,
+    };
+    let min_slope = config.min_slope.to_radians().cos();
+    let max_slope = config.max_slope.to_radians().cos();
+
+    let mut points = Vec::new();
+    for z in 0..heightmap.depth.saturating_sub(1) {
+        for x in 0..heightmap.width.saturating_sub(1) {
+            // Draw every random number up front so one rejected cell doesn't shift the rest
+            let chance = rng.next_f32();
+            let (jitter_x, jitter_z) = (rng.next_f32(), rng.next_f32());
+            let yaw = rng.range_f32(0.0, 2.0 * PI);
+            let scale = rng.range_f32(config.min_scale, config.max_scale);
+            let variation = rng.next_f32();
+
+            if !keep(x, z) {
+                continue;
+            }
+
+            let (sx, sz) = (x as f32 + jitter_x, z as f32 + jitter_z);
+            let height = heightmap.sample(sx, sz);
+            if height < config.min_height || height > config.max_height {
+                continue;
+            }
+
+            // Cosines, so steeper slopes have smaller values
+            let normal = heightmap.normal(x, z, cell_size);
+            if normal.y > min_slope || normal.y < max_slope {
+                continue;
+            }
+
+            let noise = fbm(&mask, sx as f64, sz as f64, &params) as f32;
+            let falloff = config.noise_falloff.max(f32::EPSILON);
+            let coverage = ((noise - config.noise_threshold) / falloff).clamp(0.0, 1.0);
+            if chance >= config.density * coverage {
+                continue;
+            }
+
+            points.push(ScatterPoint {
+                position: na::Point3::new(sx * cell_size, height, sz * cell_size),
+                normal,
+                yaw,
+                scale,
+                variation,
+            });
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flat for x < 16, then a 63 degree ramp
+    fn heightmap() -> Heightmap {
+        Heightmap::from_fn(32, 32, |x, _| x.saturating_sub(16) as f32 * 2.0)
+    }
+
+    // An instance in every cell unless the test filters some out
+    fn everywhere() -> ScatterConfig {
+        ScatterConfig {
+            density: 1.0,
+            max_slope: 90.0,
+            noise_threshold: -2.0,
+            ..ScatterConfig::default()
+        }
+    }
+
+    #[test]
+    fn same_seed_same_points() {
+        let heightmap = heightmap();
+        let config = everywhere();
+        let points = scatter(&heightmap, 1.0, &config, |_, _| true);
+        assert_eq!(points.len(), 31 * 31);
+        assert_eq!(points, scatter(&heightmap, 1.0, &config, |_, _| true));
+
+        let config = ScatterConfig { seed: 2, ..config };
+        assert_ne!(points, scatter(&heightmap, 1.0, &config, |_, _| true));
+    }
+
+    #[test]
+    fn respects_slope_limits() {
+        let heightmap = heightmap();
+        let config = ScatterConfig {
+            max_slope: 30.0,
+            ..everywhere()
+        };
+        let flat = scatter(&heightmap, 1.0, &config, |_, _| true);
+        assert_eq!(flat.len(), 16 * 31);
+        assert!(flat.iter().all(|point| point.position.x < 16.0));
+
+        let config = ScatterConfig {
+            min_slope: 30.0,
+            ..everywhere()
+        };
+        let steep = scatter(&heightmap, 1.0, &config, |_, _| true);
+        assert_eq!(steep.len(), 15 * 31);
+        assert!(steep.iter().all(|point| point.position.x >= 16.0));
+    }
+
+    #[test]
+    fn respects_height_limits() {
+        let config = ScatterConfig {
+            min_height: 4.0,
+            max_height: 20.0,
+            ..everywhere()
+        };
+        let points = scatter(&heightmap(), 1.0, &config, |_, _| true);
+        assert!(!points.is_empty() && points.len() < 31 * 31);
+        assert!(points
+            .iter()
+            .all(|point| (4.0..=20.0).contains(&point.position.y)));
+    }
+
+    #[test]
+    fn keeps_only_allowed_cells() {
+        let points = scatter(&heightmap(), 2.0, &everywhere(), |x, z| x < 8 && z < 4);
+        assert_eq!(points.len(), 8 * 4);
+        assert!(points
+            .iter()
+            .all(|point| point.position.x < 16.0 && point.position.z < 8.0));
+    }
+}
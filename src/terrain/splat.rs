@@ -0,0 +1,416 @@
+use super::Heightmap;
+use crate::biome::BiomeMap;
+use crate::water::WaterMap;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Most material layers the terrain can blend, one per channel of a vertex's weights.
+pub const MAX_SPLAT_LAYERS: usize = 4;
+
+/// One terrain material and the ground it covers. A layer's weight is how well a vertex fits
+/// its height and slope bands, scaled by curvature, plus whatever the masks add.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SplatLayer {
+    pub name: String,
+    /// Tiling colour texture. Without one the layer is a flat `color`.
+    pub texture: Option<PathBuf>,
+    /// Linear RGB tint multiplied into the texture.
+    pub color: [f32; 3],
+    /// World size of one texture repeat.
+    pub scale: f32,
+    pub specular: f32,
+    /// World heights the layer covers fully, fading out over `SplatConfig::height_blend`.
+    pub height: [f32; 2],
+    /// Slopes in degrees the layer covers fully, fading out over `SplatConfig::slope_blend`.
+    pub slope: [f32; 2],
+    /// Above 0 favours hollows and gullies, below 0 ridges and crests.
+    pub curvature: f32,
+    /// Weight added where erosion deposited sediment.
+    pub sediment: f32,
+    /// Weight added along erosion flow paths.
+    pub flow: f32,
+    /// Weight added on and next to water.
+    pub shore: f32,
+    /// Weight added in each named biome.
+    pub biomes: BTreeMap<String, f32>,
+}
+
+impl Default for SplatLayer {
+    fn default() -> Self {
+        SplatLayer {
+            name: String::new(),
+            texture: None,
+            color: [1.0, 1.0, 1.0],
+            scale: 8.0,
+            specular: 0.05,
+            height: [-1000.0, 1000.0],
+            slope: [0.0, 90.0],
+            curvature: 0.0,
+            sediment: 0.0,
+            flow: 0.0,
+            shore: 0.0,
+            biomes: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SplatConfig {
+    /// Up to `MAX_SPLAT_LAYERS`. Textures are resized to match the first layer's.
+    pub layers: Vec<SplatLayer>,
+    pub height_blend: f32,
+    pub slope_blend: f32,
+    /// Multiplies the height Laplacian before layers' `curvature` sees it.
+    pub curvature_scale: f32,
+    /// Cells from water that still count as shore.
+    pub shore_radius: usize,
+    /// How sharply cliff textures switch between projection planes.
+    pub triplanar_sharpness: f32,
+    /// 0 for plain tiling up to 1 to fully break up repeats.
+    pub anti_tiling: f32,
+}
+
+impl Default for SplatConfig {
+    fn default() -> Self {
+        SplatConfig {
+            layers: vec![
+                SplatLayer {
+                    name: "sand".to_string(),
+                    color: [0.76, 0.7, 0.5],
+                    height: [-1000.0, 1.0],
+                    slope: [0.0, 25.0],
+                    sediment: 0.5,
+                    shore: 2.0,
+                    biomes: biome_weights(&[("desert", 1.5)]),
+                    ..SplatLayer::default()
+                },
+                SplatLayer {
+                    name: "grass".to_string(),
+                    color: [0.25, 0.45, 0.15],
+                    height: [-1000.0, 18.0],
+                    slope: [0.0, 30.0],
+                    curvature: 0.5,
+                    ..SplatLayer::default()
+                },
+                SplatLayer {
+                    name: "rock".to_string(),
+                    color: [0.45, 0.42, 0.4],
+                    specular: 0.15,
+                    slope: [34.0, 90.0],
+                    curvature: -0.5,
+                    biomes: biome_weights(&[("rock", 0.5)]),
+                    ..SplatLayer::default()
+                },
+                SplatLayer {
+                    name: "snow".to_string(),
+                    color: [0.9, 0.92, 0.95],
+                    specular: 0.3,
+                    height: [24.0, 1000.0],
+                    slope: [0.0, 45.0],
+                    biomes: biome_weights(&[("snow", 1.5)]),
+                    ..SplatLayer::default()
+                },
+            ],
+            height_blend: 3.0,
+            slope_blend: 8.0,
+            curvature_scale: 1.0,
+            shore_radius: 3,
+            triplanar_sharpness: 4.0,
+            anti_tiling: 1.0,
+        }
+    }
+}
+
+fn biome_weights(weights: &[(&str, f32)]) -> BTreeMap<String, f32> {
+    weights
+        .iter()
+        .map(|&(name, weight)| (name.to_string(), weight))
+        .collect()
+}
+
+/// Optional inputs to `splat_weights`, all sized like the heightmap.
+#[derive(Default)]
+pub struct SplatMasks<'a> {
+    pub sediment: Option<&'a Heightmap>,
+    pub flow: Option<&'a Heightmap>,
+    pub water: Option<&'a WaterMap>,
+    pub biomes: Option<&'a BiomeMap>,
+}
+
+/// Per-sample layer weights summing to one, laid out like the heightmap they came from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SplatMap {
+    pub width: usize,
+    pub depth: usize,
+    pub weights: Vec<[f32; 4]>,
+}
+
+impl SplatMap {
+    pub fn get(&self, x: usize, z: usize) -> [f32; 4] {
+        self.weights[z * self.width + x]
+    }
+}
+
+/// Works out how much of each layer covers every heightmap sample. Layers past
+/// `MAX_SPLAT_LAYERS` are ignored, and samples no layer claims go to the first.
+pub fn splat_weights(
+    heightmap: &Heightmap,
+    cell_size: f32,
+    config: &SplatConfig,
+    masks: &SplatMasks,
+) -> SplatMap {
+    let (width, depth) = (heightmap.width, heightmap.depth);
+    let layers = &config.layers[..config.layers.len().min(MAX_SPLAT_LAYERS)];
+    let flow_scale = masks
+        .flow
+        .map(|flow| (1.0 + flow.samples.iter().cloned().fold(0.0, f32::max)).ln())
+        .unwrap_or(1.0)
+        .max(f32::EPSILON);
+    let shore = masks
+        .water
+        .map(|water| shore_mask(water, config.shore_radius));
+    // Each layer's weight per biome id
+    let biome_weights: Option<Vec<Vec<f32>>> = masks.biomes.map(|biomes| {
+        layers
+            .iter()
+            .map(|layer| {
+                biomes
+                    .table
+                    .names
+                    .iter()
+                    .map(|name| layer.biomes.get(name).cloned().unwrap_or(0.0))
+                    .collect()
+            })
+            .collect()
+    });
+
+    let mut weights = Vec::with_capacity(width * depth);
+    for z in 0..depth {
+        for x in 0..width {
+            let i = heightmap.index(x, z);
+            let height = heightmap.samples[i];
+            let slope = heightmap
+                .normal(x, z, cell_size)
+                .y
+                .min(1.0)
+                .acos()
+                .to_degrees();
+            let curvature = laplacian(heightmap, x, z) / cell_size * config.curvature_scale;
+            // Sediment is in height units, flow in droplets, so both are squashed into 0..1
+            let sediment = masks
+                .sediment
+                .map_or(0.0, |sediment| 1.0 - (-sediment.samples[i].max(0.0)).exp());
+            let flow = masks.flow.map_or(0.0, |flow| {
+                (1.0 + flow.samples[i].max(0.0)).ln() / flow_scale
+            });
+            let shore = shore.as_ref().map_or(0.0, |shore| shore[i]);
+            let biome = masks.biomes.map(|biomes| biomes.biomes[i] as usize);
+
+            let mut sample = [0.0; 4];
+            for (l, (weight, layer)) in sample.iter_mut().zip(layers).enumerate() {
+                let fit = band(height, layer.height, config.height_blend)
+                    * band(slope, layer.slope, config.slope_blend)
+                    * (1.0 + layer.curvature * curvature).max(0.0);
+                let biome = match (&biome_weights, biome) {
+                    (Some(weights), Some(biome)) => weights[l][biome],
+                    _ => 0.0,
+                };
+                *weight = (fit
+                    + layer.sediment * sediment
+                    + layer.flow * flow
+                    + layer.shore * shore
+                    + biome)
+                    .max(0.0);
+            }
+
+            let total: f32 = sample.iter().sum();
+            if total > f32::EPSILON {
+                sample.iter_mut().for_each(|weight| *weight /= total);
+            } else {
+                sample = [1.0, 0.0, 0.0, 0.0];
+            }
+            weights.push(sample);
+        }
+    }
+
+    SplatMap {
+        width,
+        depth,
+        weights,
+    }
+}
+
+// 1 inside `[low, high]`, fading smoothly to 0 over `blend` either side
+fn band(value: f32, [low, high]: [f32; 2], blend: f32) -> f32 {
+    let blend = blend.max(f32::EPSILON);
+    smoothstep(low - blend, low, value) * (1.0 - smoothstep(high, high + blend, value))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Positive in hollows, negative on crests
+fn laplacian(heightmap: &Heightmap, x: usize, z: usize) -> f32 {
+    let (x, z) = (x as isize, z as isize);
+    heightmap.get_clamped(x - 1, z)
+        + heightmap.get_clamped(x + 1, z)
+        + heightmap.get_clamped(x, z - 1)
+        + heightmap.get_clamped(x, z + 1)
+        - 4.0 * heightmap.get_clamped(x, z)
+}
+
+// 1 on water, falling off linearly to 0 at `radius` cells away
+fn shore_mask(water: &WaterMap, radius: usize) -> Vec<f32> {
+    let (width, depth) = (water.surface.width, water.surface.depth);
+    let reach = radius as isize;
+    let mut mask = vec![0.0; width * depth];
+    for z in 0..depth {
+        for x in 0..width {
+            if !water.is_wet(x, z) {
+                continue;
+            }
+            for dz in -reach..=reach {
+                for dx in -reach..=reach {
+                    let (nx, nz) = (x as isize + dx, z as isize + dz);
+                    if nx < 0 || nz < 0 || nx >= width as isize || nz >= depth as isize {
+                        continue;
+                    }
+                    let distance = ((dx * dx + dz * dz) as f32).sqrt();
+                    let value = 1.0 - distance / (radius as f32 + 1.0);
+                    let i = nz as usize * width + nx as usize;
+                    if value > mask[i] {
+                        mask[i] = value;
+                    }
+                }
+            }
+        }
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::biome::{BiomeConfig, BiomeTable};
+    use crate::water::WaterConfig;
+
+    fn layer(height: [f32; 2], slope: [f32; 2]) -> SplatLayer {
+        SplatLayer {
+            height,
+            slope,
+            ..SplatLayer::default()
+        }
+    }
+
+    fn config(layers: Vec<SplatLayer>) -> SplatConfig {
+        SplatConfig {
+            layers,
+            height_blend: 1.0,
+            slope_blend: 5.0,
+            ..SplatConfig::default()
+        }
+    }
+
+    fn assert_weights(actual: [f32; 4], expected: [f32; 4]) {
+        for (a, e) in actual.iter().zip(&expected) {
+            assert!((a - e).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn weights_sum_to_one() {
+        let heightmap = Heightmap::from_fn(16, 16, |x, z| {
+            30.0 * (x as f32 * 0.7).sin() * (z as f32 * 0.4).cos()
+        });
+        let splat = splat_weights(
+            &heightmap,
+            1.0,
+            &SplatConfig::default(),
+            &SplatMasks::default(),
+        );
+        for weights in &splat.weights {
+            assert!(weights.iter().all(|&weight| weight >= 0.0));
+            assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn layers_follow_height_and_slope_bands() {
+        let config = config(vec![
+            layer([-1000.0, 5.0], [0.0, 90.0]),
+            layer([10.0, 1000.0], [0.0, 90.0]),
+            layer([-1000.0, 1000.0], [40.0, 90.0]),
+        ]);
+        // Weights in the middle of an 8 by 4 heightmap
+        let weights = |height: &dyn Fn(usize) -> f32| {
+            let heightmap = Heightmap::from_fn(8, 4, |x, _| height(x));
+            splat_weights(&heightmap, 1.0, &config, &SplatMasks::default()).get(4, 2)
+        };
+
+        assert_weights(weights(&|_| 0.0), [1.0, 0.0, 0.0, 0.0]);
+        assert_weights(weights(&|_| 20.0), [0.0, 1.0, 0.0, 0.0]);
+        // 72 degrees and 12 high in the middle
+        assert_weights(weights(&|x| x as f32 * 3.0), [0.0, 0.5, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn shore_and_biomes_add_weight() {
+        // Sea below x = 3.5, gently sloping up from there
+        let heightmap = Heightmap::from_fn(16, 4, |x, _| x as f32);
+        let water_config = WaterConfig {
+            sea_level: Some(3.5),
+            ..WaterConfig::default()
+        };
+        let water = WaterMap::new(&heightmap, &water_config, None);
+        let table = BiomeTable::new(&BiomeConfig::default()).unwrap();
+        let desert = table
+            .names
+            .iter()
+            .position(|name| name == "desert")
+            .unwrap();
+        let biomes = BiomeMap {
+            width: 16,
+            table,
+            biomes: vec![desert as u8; 64],
+        };
+
+        let nowhere = [-1000.0, -999.0];
+        let config = SplatConfig {
+            shore_radius: 2,
+            ..config(vec![
+                SplatLayer::default(),
+                SplatLayer {
+                    shore: 1.0,
+                    ..layer(nowhere, [0.0, 90.0])
+                },
+                SplatLayer {
+                    biomes: biome_weights(&[("desert", 3.0)]),
+                    ..layer(nowhere, [0.0, 90.0])
+                },
+            ])
+        };
+
+        let masks = SplatMasks {
+            water: Some(&water),
+            ..SplatMasks::default()
+        };
+        let splat = splat_weights(&heightmap, 10.0, &config, &masks);
+        assert_weights(splat.get(1, 1), [0.5, 0.5, 0.0, 0.0]);
+        // Two cells from the water's edge
+        assert_weights(splat.get(5, 1), [0.75, 0.25, 0.0, 0.0]);
+        assert_weights(splat.get(12, 1), [1.0, 0.0, 0.0, 0.0]);
+
+        let masks = SplatMasks {
+            biomes: Some(&biomes),
+            ..masks
+        };
+        let splat = splat_weights(&heightmap, 10.0, &config, &masks);
+        assert_weights(splat.get(1, 1), [0.2, 0.2, 0.6, 0.0]);
+        assert_weights(splat.get(12, 1), [0.25, 0.0, 0.75, 0.0]);
+    }
+}
@@ -0,0 +1,206 @@
+use serde::Deserialize;
+
+mod basins;
+mod flow;
+mod mesh;
+
+pub use basins::{fill_depressions, sea_level};
+pub use flow::{flow_accumulation, flow_receivers};
+pub use mesh::{build_water_mesh, water_levels, WaterVertex};
+
+use crate::terrain::Heightmap;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct WaterConfig {
+    /// Fraction of the map that ends up below sea level. Ignored when `sea_level` is set.
+    pub sea_fraction: f32,
+    pub sea_level: Option<f32>,
+    /// Flow accumulation, in cells of rainfall, above which a cell carries a river.
+    pub river_threshold: f32,
+    pub river_depth: f32,
+    pub min_lake_depth: f32,
+    // Rendering
+    pub wave_amplitude: f32,
+    pub wave_length: f32,
+    pub wave_speed: f32,
+    pub opacity: f32,
+    pub deep_depth: f32,
+    pub shallow_color: [f32; 4],
+    pub deep_color: [f32; 4],
+}
+
+impl Default for WaterConfig {
+    fn default() -> Self {
+        WaterConfig {
+            sea_fraction: 0.2,
+            sea_level: None,
+            river_threshold: 400.0,
+            river_depth: 0.4,
+            min_lake_depth: 0.05,
+            wave_amplitude: 0.08,
+            wave_length: 12.0,
+            wave_speed: 1.5,
+            opacity: 0.7,
+            deep_depth: 4.0,
+            shallow_color: [0.1, 0.45, 0.5, 1.0],
+            deep_color: [0.02, 0.1, 0.25, 1.0],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaterKind {
+    Dry,
+    Ocean,
+    Lake,
+    River,
+}
+
+/// Water bodies over a terrain heightmap, sized like the heightmap.
+#[derive(Debug)]
+pub struct WaterMap {
+    pub sea_level: f32,
+    /// Water surface height. Equal to the terrain height on dry cells.
+    pub surface: Heightmap,
+    pub depth: Heightmap,
+    pub kinds: Vec<WaterKind>,
+}
+
+impl WaterMap {
+    /// Works out sea level, fills closed basins into lakes and traces rivers along the flow
+    /// accumulation. `rainfall` weights each cell's contribution to the flow and defaults to one.
+    pub fn new(terrain: &Heightmap, config: &WaterConfig, rainfall: Option<&Heightmap>) -> WaterMap {
+        let sea_level = config
+            .sea_level
+            .unwrap_or_else(|| sea_level(terrain, config.sea_fraction));
+        let filled = fill_depressions(terrain);
+        let receivers = flow_receivers(&filled.routing);
+        let flow = flow_accumulation(&filled.routing, &receivers, rainfall);
+
+        let mut surface = terrain.clone();
+        let mut depth = Heightmap::new(terrain.width, terrain.depth);
+        let mut kinds = vec![WaterKind::Dry; terrain.samples.len()];
+
+        for (i, kind) in kinds.iter_mut().enumerate() {
+            let ground = terrain.samples[i];
+            let lake_level = filled.level.samples[i];
+
+            let (water_kind, level) = if ground < sea_level && lake_level <= sea_level {
+                (WaterKind::Ocean, sea_level)
+            } else if lake_level - ground >= config.min_lake_depth {
+                (WaterKind::Lake, lake_level.max(sea_level))
+            } else if flow.samples[i] >= config.river_threshold {
+                let strength = (flow.samples[i] / config.river_threshold).sqrt();
+                (WaterKind::River, ground + config.river_depth * strength.min(4.0))
+            } else {
+                (WaterKind::Dry, ground)
+            };
+
+            *kind = water_kind;
+            surface.samples[i] = level;
+            depth.samples[i] = level - ground;
+        }
+
+        WaterMap {
+            sea_level,
+            surface,
+            depth,
+            kinds,
+        }
+    }
+
+    pub fn is_wet(&self, x: usize, z: usize) -> bool {
+        self.kinds[self.surface.index(x, z)] != WaterKind::Dry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Falls towards x = 0, where every row drains off the map
+    fn ramp() -> Heightmap {
+        Heightmap::from_fn(8, 4, |x, _| x as f32)
+    }
+
+    fn config() -> WaterConfig {
+        WaterConfig {
+            sea_level: Some(-100.0),
+            ..WaterConfig::default()
+        }
+    }
+
+    #[test]
+    fn flow_runs_down_a_ramp() {
+        let terrain = ramp();
+        let receivers = flow_receivers(&terrain);
+        let flow = flow_accumulation(&terrain, &receivers, None);
+
+        for z in 0..4 {
+            assert_eq!(receivers[terrain.index(0, z)], None);
+            for x in 1..8 {
+                assert_eq!(
+                    receivers[terrain.index(x, z)],
+                    Some(terrain.index(x - 1, z))
+                );
+                // Every cell uphill in the row drains through this one
+                assert_eq!(flow.get(x, z), (8 - x) as f32);
+            }
+        }
+
+        let rainfall = Heightmap::from_fn(8, 4, |_, _| 0.5);
+        let flow = flow_accumulation(&terrain, &receivers, Some(&rainfall));
+        assert_eq!(flow.get(0, 0), 4.0);
+    }
+
+    #[test]
+    fn rivers_start_at_the_threshold() {
+        let config = WaterConfig {
+            river_threshold: 5.0,
+            ..config()
+        };
+        let water = WaterMap::new(&ramp(), &config, None);
+
+        for z in 0..4 {
+            for x in 0..8 {
+                let i = water.surface.index(x, z);
+                if x <= 3 {
+                    assert_eq!(water.kinds[i], WaterKind::River);
+                    assert!(water.depth.samples[i] >= config.river_depth);
+                } else {
+                    assert_eq!(water.kinds[i], WaterKind::Dry);
+                    assert_eq!(water.depth.samples[i], 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bowls_fill_into_lakes() {
+        // A pit in the middle of a plateau, which fills up to the plateau
+        let terrain = Heightmap::from_fn(7, 7, |x, z| {
+            if (2..5).contains(&x) && (2..5).contains(&z) {
+                1.0
+            } else {
+                5.0
+            }
+        });
+        let water = WaterMap::new(&terrain, &config(), None);
+
+        for z in 0..7 {
+            for x in 0..7 {
+                let i = terrain.index(x, z);
+                if terrain.samples[i] < 5.0 {
+                    assert_eq!(water.kinds[i], WaterKind::Lake);
+                    assert_eq!(
+                        (water.surface.samples[i], water.depth.samples[i]),
+                        (5.0, 4.0)
+                    );
+                } else {
+                    assert_eq!(water.kinds[i], WaterKind::Dry);
+                }
+            }
+        }
+    }
+}
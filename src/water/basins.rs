@@ -0,0 +1,153 @@
+use crate::terrain::Heightmap;
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+// Height added per step when routing across flats so that every cell keeps a downhill neighbour
+const ROUTING_EPSILON: f32 = 1e-4;
+
+// Steps up from `height` by at least `ROUTING_EPSILON`, or by enough to change the value once
+// heights are too large for that to register in an f32
+fn step_up(height: f32) -> f32 {
+    height + ROUTING_EPSILON.max(height.abs() * f32::EPSILON)
+}
+
+pub struct FilledTerrain {
+    /// Terrain with every closed basin filled up to its spill height.
+    pub level: Heightmap,
+    /// Like `level`, but with flats tilted slightly towards their outlet so flow can be routed.
+    pub routing: Heightmap,
+}
+
+#[derive(PartialEq)]
+struct Cell {
+    height: f32,
+    order: usize,
+    index: usize,
+}
+
+impl Eq for Cell {}
+
+// Reversed so the `BinaryHeap` pops the lowest cell first, oldest first on ties
+impl Ord for Cell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .height
+            .partial_cmp(&self.height)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for Cell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Height below which `fraction` of the terrain lies.
+pub fn sea_level(terrain: &Heightmap, fraction: f32) -> f32 {
+    let mut heights = terrain.samples.clone();
+    heights.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    if fraction <= 0.0 || heights.is_empty() {
+        return f32::MIN;
+    }
+    let i = ((heights.len() as f32 * fraction) as usize).min(heights.len() - 1);
+    heights[i]
+}
+
+/// Priority-flood depression filling. Water drains off the map edges, so flooding starts there
+/// and works inwards from the lowest open cell; any cell reached from higher ground is in a
+/// closed basin and is raised to that spill height.
+pub fn fill_depressions(terrain: &Heightmap) -> FilledTerrain {
+    let (width, depth) = (terrain.width, terrain.depth);
+    let mut level = terrain.clone();
+    let mut routing = terrain.clone();
+    let mut closed = vec![false; terrain.samples.len()];
+    let mut open = BinaryHeap::new();
+    let mut order = 0;
+
+    for z in 0..depth {
+        for x in 0..width {
+            if x == 0 || z == 0 || x == width - 1 || z == depth - 1 {
+                let index = terrain.index(x, z);
+                closed[index] = true;
+                open.push(Cell {
+                    height: terrain.samples[index],
+                    order,
+                    index,
+                });
+                order += 1;
+            }
+        }
+    }
+
+    while let Some(cell) = open.pop() {
+        let (x, z) = ((cell.index % width) as isize, (cell.index / width) as isize);
+        for dz in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, nz) = (x + dx, z + dz);
+                if nx < 0 || nz < 0 || nx >= width as isize || nz >= depth as isize {
+                    continue;
+                }
+                let neighbour = terrain.index(nx as usize, nz as usize);
+                if closed[neighbour] {
+                    continue;
+                }
+                closed[neighbour] = true;
+
+                level.samples[neighbour] = level.samples[neighbour].max(level.samples[cell.index]);
+                let spill = step_up(routing.samples[cell.index]);
+                if routing.samples[neighbour] < spill {
+                    routing.samples[neighbour] = spill;
+                }
+
+                open.push(Cell {
+                    height: routing.samples[neighbour],
+                    order,
+                    index: neighbour,
+                });
+                order += 1;
+            }
+        }
+    }
+
+    FilledTerrain { level, routing }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flats_keep_a_downhill_neighbour() {
+        // A flat plateau with one low notch in its rim, low and at real world heights in metres
+        for &base in &[0.0, 5000.0] {
+            let terrain = Heightmap::from_fn(9, 9, |x, z| {
+                if (x, z) == (0, 4) {
+                    base - 10.0
+                } else if x == 0 || z == 0 || x == 8 || z == 8 {
+                    base + 10.0
+                } else {
+                    base
+                }
+            });
+            let routing = fill_depressions(&terrain).routing;
+            for z in 1..8 {
+                for x in 1..8 {
+                    let height = routing.get(x, z);
+                    let lowest = (z - 1..=z + 1)
+                        .flat_map(|nz| (x - 1..=x + 1).map(move |nx| (nx, nz)))
+                        .map(|(nx, nz)| routing.get(nx, nz))
+                        .fold(f32::MAX, f32::min);
+                    assert!(lowest < height, "({}, {}) at {} is a pit", x, z, base);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn steps_up_at_any_height() {
+        for &height in &[-3000.0, 0.0, 1.0, 2048.0, 8848.0, 1e6] {
+            assert!(step_up(height) > height);
+        }
+    }
+}
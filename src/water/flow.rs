@@ -0,0 +1,68 @@
+use crate::terrain::Heightmap;
+use std::cmp::Ordering;
+
+/// Steepest-descent (D8) receiver of every cell, or `None` for cells with no lower neighbour.
+/// Expects a depression-free surface, where only edge cells lack a receiver.
+pub fn flow_receivers(surface: &Heightmap) -> Vec<Option<usize>> {
+    let (width, depth) = (surface.width as isize, surface.depth as isize);
+    let mut receivers = vec![None; surface.samples.len()];
+
+    for z in 0..depth {
+        for x in 0..width {
+            let index = surface.index(x as usize, z as usize);
+            let height = surface.samples[index];
+            let mut steepest = 0.0;
+
+            for dz in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, nz) = (x + dx, z + dz);
+                    if (dx == 0 && dz == 0) || nx < 0 || nz < 0 || nx >= width || nz >= depth {
+                        continue;
+                    }
+                    let neighbour = surface.index(nx as usize, nz as usize);
+                    let distance = if dx != 0 && dz != 0 {
+                        std::f32::consts::SQRT_2
+                    } else {
+                        1.0
+                    };
+                    let slope = (height - surface.samples[neighbour]) / distance;
+                    if slope > steepest {
+                        steepest = slope;
+                        receivers[index] = Some(neighbour);
+                    }
+                }
+            }
+        }
+    }
+
+    receivers
+}
+
+/// Total rainfall draining through each cell. Cells are visited from highest to lowest so every
+/// cell has collected its upstream flow before passing it on.
+pub fn flow_accumulation(
+    surface: &Heightmap,
+    receivers: &[Option<usize>],
+    rainfall: Option<&Heightmap>,
+) -> Heightmap {
+    let mut flow = match rainfall {
+        Some(rainfall) => rainfall.clone(),
+        None => Heightmap::from_fn(surface.width, surface.depth, |_, _| 1.0),
+    };
+
+    let mut order: Vec<usize> = (0..surface.samples.len()).collect();
+    order.sort_by(|&a, &b| {
+        surface.samples[b]
+            .partial_cmp(&surface.samples[a])
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.cmp(&b))
+    });
+
+    for index in order {
+        if let Some(receiver) = receivers[index] {
+            flow.samples[receiver] += flow.samples[index];
+        }
+    }
+
+    flow
+}
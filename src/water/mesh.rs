@@ -0,0 +1,115 @@
+use super::WaterMap;
+use crate::objects::{Mesh, VertexAttribute};
+use crate::terrain::Heightmap;
+use crate::Result;
+use std::mem;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct WaterVertex {
+    pub position: [f32; 3],
+    pub depth: f32,
+}
+
+unsafe impl bytemuck::Pod for WaterVertex {}
+unsafe impl bytemuck::Zeroable for WaterVertex {}
+
+impl VertexAttribute for WaterVertex {
+    fn description<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<WaterVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float,
+                },
+            ],
+        }
+    }
+}
+
+/// Builds the water surface over every grid quad that touches a wet cell, laid out like
+/// `terrain::build_mesh`. Dry corners take the level of the water next to them, clamped to the
+/// ground, so the surface meets the shore instead of dropping away.
+pub fn build_water_mesh(water: &WaterMap, cell_size: f32) -> Result<Mesh<WaterVertex>> {
+    let (width, depth) = (water.surface.width, water.surface.depth);
+    if width * depth > u16::MAX as usize + 1 {
+        return Err(format!(
+            "Water map {}x{} has too many vertices for 16 bit indices",
+            width, depth
+        )
+        .into());
+    }
+
+    let levels = water_levels(water);
+    let mut vertices = Vec::with_capacity(width * depth);
+    for z in 0..depth {
+        for x in 0..width {
+            let ground = water.surface.get(x, z) - water.depth.get(x, z);
+            let level = levels.get(x, z);
+            vertices.push(WaterVertex {
+                position: [x as f32 * cell_size, level, z as f32 * cell_size],
+                depth: level - ground,
+            });
+        }
+    }
+
+    let mut indices = Vec::new();
+    for z in 0..depth.saturating_sub(1) {
+        for x in 0..width.saturating_sub(1) {
+            let wet = water.is_wet(x, z)
+                || water.is_wet(x + 1, z)
+                || water.is_wet(x, z + 1)
+                || water.is_wet(x + 1, z + 1);
+            if !wet {
+                continue;
+            }
+            let i00 = (z * width + x) as u16;
+            let i10 = i00 + 1;
+            let i01 = i00 + width as u16;
+            let i11 = i01 + 1;
+            indices.extend_from_slice(&[i00, i01, i10, i10, i01, i11]);
+        }
+    }
+
+    Ok(Mesh::new(vertices, indices))
+}
+
+/// Height of the water mesh at every sample, the water surface on wet cells and the level of
+/// the water next to dry ones, clamped to the ground.
+pub fn water_levels(water: &WaterMap) -> Heightmap {
+    Heightmap::from_fn(water.surface.width, water.surface.depth, |x, z| {
+        let ground = water.surface.get(x, z) - water.depth.get(x, z);
+        if water.is_wet(x, z) {
+            water.surface.get(x, z)
+        } else {
+            shore_level(water, x, z).map_or(ground, |level| level.min(ground))
+        }
+    })
+}
+
+// Highest water surface among the wet neighbours of a dry cell
+fn shore_level(water: &WaterMap, x: usize, z: usize) -> Option<f32> {
+    let mut level: Option<f32> = None;
+    for dz in -1isize..=1 {
+        for dx in -1isize..=1 {
+            let (nx, nz) = (x as isize + dx, z as isize + dz);
+            if nx < 0 || nz < 0 || nx >= water.surface.width as isize || nz >= water.surface.depth as isize {
+                continue;
+            }
+            let (nx, nz) = (nx as usize, nz as usize);
+            if water.is_wet(nx, nz) {
+                let surface = water.surface.get(nx, nz);
+                level = Some(level.map_or(surface, |l: f32| l.max(surface)));
+            }
+        }
+    }
+    level
+}